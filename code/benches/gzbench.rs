@@ -3,7 +3,9 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use GeneZipLib::fasta_chunk_reader::FastaChunkReader;
 use GeneZipLib::fasta_nucleutide_iterator::FastaNucltudiesIterator;
+use GeneZipLib::kmer::{create_normalized_profile, create_normalized_profile_from_chunks};
 
 use GeneZipLib::lz78::{LenBases, LZ78};
 use GeneZipLib::output_streams::{OutputFileType, OutputStreams};
@@ -21,6 +23,29 @@ fn bench_presentation_example_two_seqs(max_depth: usize) {
     assert!(prediction > 2.229715809318647);
 }
 
+// Building and scoring a genome model, reading the FASTA a byte at a time or in chunks
+fn bench_lz78_bytes(max_depth: usize, buffer_size: usize) {
+    let fasta_path = PathBuf::from("../data/HMP_2012__SRS019219__bin.33_genes.fna.gz");
+    let model = LZ78::new(max_depth, LenBases::new(max_depth), FastaNucltudiesIterator::new(fasta_path.as_path(), buffer_size));
+    black_box(model.average_log_score(FastaNucltudiesIterator::new(fasta_path.as_path(), buffer_size)));
+}
+
+fn bench_lz78_chunks(max_depth: usize, buffer_size: usize) {
+    let fasta_path = PathBuf::from("../data/HMP_2012__SRS019219__bin.33_genes.fna.gz");
+    let model = LZ78::from_chunks(max_depth, LenBases::new(max_depth), FastaChunkReader::new(fasta_path.as_path(), buffer_size));
+    black_box(model.average_log_score_chunks(FastaChunkReader::new(fasta_path.as_path(), buffer_size)));
+}
+
+fn bench_kmer_bytes(k: usize, buffer_size: usize) {
+    let fasta_path = PathBuf::from("../data/HMP_2012__SRS019219__bin.33_genes.fna.gz");
+    black_box(create_normalized_profile(k, FastaNucltudiesIterator::new(fasta_path.as_path(), buffer_size), &false).1.unwrap());
+}
+
+fn bench_kmer_chunks(k: usize, buffer_size: usize) {
+    let fasta_path = PathBuf::from("../data/HMP_2012__SRS019219__bin.33_genes.fna.gz");
+    black_box(create_normalized_profile_from_chunks(k, FastaChunkReader::new(fasta_path.as_path(), buffer_size)).unwrap());
+}
+
 fn bench_small_example(max_depth: usize, buffer_size: usize) {
     let basic_output_path = PathBuf::from("../tests/small_sample_predication_basic.tsv");
     let lz_matrix_path = PathBuf::from("../tests/small_sample_predication_gz.tsv");
@@ -32,7 +57,7 @@ fn bench_small_example(max_depth: usize, buffer_size: usize) {
     };
 
     // max_depth is 12, for consistency with the small sample.
-    let classifier = Arc::new(create_lz_classifier(None, max_depth, &PathBuf::from("../tests/small_example_training.txt"), buffer_size, &None));
+    let classifier = Arc::new(create_lz_classifier(None, max_depth, &PathBuf::from("../tests/small_example_training.txt"), buffer_size, &None, None));
     predict_using_lz_classifier(None,
                                 buffer_size,
                                 &None,
@@ -58,7 +83,7 @@ fn bench_taxonomy_example(max_depth: usize, buffer_size: usize) {
         Ok(os) => os,
     };
 
-    let classifier = Arc::new(create_lz_classifier(None, max_depth, &train_path, buffer_size, &Some(4)));
+    let classifier = Arc::new(create_lz_classifier(None, max_depth, &train_path, buffer_size, &Some(4), None));
     predict_using_lz_classifier(None,
                                 buffer_size,
                                 &Some(4),
@@ -84,7 +109,7 @@ fn bench_tiny_example(max_depth: usize, buffer_size: usize) {
         Ok(os) => os,
     };
 
-    let classifier = Arc::new(create_lz_classifier(None, max_depth, &train_path, buffer_size, &None));
+    let classifier = Arc::new(create_lz_classifier(None, max_depth, &train_path, buffer_size, &None, None));
     predict_using_lz_classifier(None,
                                 buffer_size,
                                 &None,
@@ -107,7 +132,7 @@ fn bench_meta_genes(max_depth: usize, buffer_size: usize) {
     };
 
     // max_depth is 12, for consistency with the small sample.
-    let classifier = create_lz_classifier(None, max_depth, &PathBuf::from("../tests/small_example_training.txt"), buffer_size, &None);
+    let classifier = create_lz_classifier(None, max_depth, &PathBuf::from("../tests/small_example_training.txt"), buffer_size, &None, None);
     meta_predict_using_lz_classifier(None,
                                      buffer_size,
                                      &classifier,
@@ -124,6 +149,10 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("bench_presentation_example_two_seqs 13", |b| b.iter(|| bench_presentation_example_two_seqs(black_box(13))));
     c.bench_function("bench_small_example 12 512", |b| b.iter(|| bench_small_example(black_box(12), black_box(512))));
     c.bench_function("bench_tiny_example 12 512", |b| b.iter(|| bench_tiny_example(black_box(12), black_box(512))));
+    c.bench_function("bench_lz78_bytes 13 1024", |b| b.iter(|| bench_lz78_bytes(black_box(13), black_box(1024))));
+    c.bench_function("bench_lz78_chunks 13 1024", |b| b.iter(|| bench_lz78_chunks(black_box(13), black_box(1024))));
+    c.bench_function("bench_kmer_bytes 4 1024", |b| b.iter(|| bench_kmer_bytes(black_box(4), black_box(1024))));
+    c.bench_function("bench_kmer_chunks 4 1024", |b| b.iter(|| bench_kmer_chunks(black_box(4), black_box(1024))));
 }
 
 fn long_criterion_benchmark(c: &mut Criterion) {
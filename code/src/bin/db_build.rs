@@ -6,7 +6,7 @@ Prepare reference database for GeneZip
     2.1. if any of them are too similar (distance <= 5), merge them
 */
 use GeneZipLib::skani::Skani;
-use std::fs::{create_dir_all, File};
+use std::fs::{create_dir_all, read_to_string, remove_file, File};
 use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
 use std::collections::{HashSet, HashMap};
@@ -21,37 +21,152 @@ use GeneZipLib::graph::Graph;
 use GeneZipLib::static_graph::StaticGraph;
 use GeneZipLib::fastani::FastANI;
 use GeneZipLib::ani_calculator::AniCalculator;
+use GeneZipLib::genezip_distance::GeneZipDistance;
+use GeneZipLib::checkpoint::Checkpoints;
+use GeneZipLib::classifier::Classifier;
+use GeneZipLib::database::Database;
+use GeneZipLib::lz78::LenBases;
+use GeneZipLib::genome_quality::{GenomeQuality, RepresentativeStrategy};
+use GeneZipLib::linkage;
+use GeneZipLib::linkage::{AniSummary, ClusterDiagnostics, Linkage};
+use GeneZipLib::ncbi_taxdump::TaxDump;
+use GeneZipLib::reference_import;
+use GeneZipLib::reference_import::GenomeDirectory;
 
 use std::option::Option;
 use GeneZipLib::ani_calculator_tool::AniCalculatorTool;
-use GeneZipLib::samples_file_reader::{Sample, SampleSource};
+use GeneZipLib::samples_file_reader::{merge_repeated_samples, Sample, SampleError, SampleSource};
 use GeneZipLib::taxonomy::{TaxonomicRank, Taxonomy};
 
 type TaxonomyName = std::string::String;
+// Ordered by the first appearance of the taxa in the input, so cluster numbers are repeatable
+type MergedTaxa = Vec<(Vec<Taxonomy>, HashSet<Sample>)>;
+type MergeDiagnostics = HashMap<Vec<Taxonomy>, ClusterDiagnostics>;
 // type MagName = std::string::String;
 
+// How the pairwise distances inside each genus are calculated
+#[derive(Copy, Clone, Debug)]
+enum DistanceTool {
+    Ani(AniCalculatorTool),
+    // Symmetric GeneZip score, calculated in-process using LZ78 models
+    GeneZip { max_depth: usize, buffer_size: usize },
+}
+
+// How species within a genus are merged into clusters
+#[derive(Copy, Clone, Debug)]
+struct MergeSettings {
+    // Species are merged if 100 - ANI is at most this value
+    distance: f64,
+    tool: DistanceTool,
+    linkage: Linkage,
+}
+
+// How representatives are picked from the genomes of each species
+struct Representatives {
+    per_species: usize,
+    strategy: RepresentativeStrategy,
+    quality: Option<GenomeQuality>,
+    random_state: StdRng,
+}
+
+impl Representatives {
+    fn get_score(&self, sample: &Sample) -> Option<f64> {
+        self.quality.as_ref().and_then(|q| q.get_score(sample))
+    }
+}
+
+// Where and how to train a GeneZip database from the clusters
+#[derive(Debug)]
+struct DatabaseSettings {
+    path: PathBuf,
+    max_depth: usize,
+    buffer_size: usize,
+    kmer_size: Option<usize>,
+}
+
+// Metadata used to build the input list from a local directory of downloaded genomes
+#[derive(Debug)]
+enum ReferenceSource {
+    Gtdb { metadata: PathBuf },
+    Ncbi { assembly_summary: PathBuf, taxdump: PathBuf },
+}
+
+#[derive(Debug)]
+struct ImportSettings {
+    source: ReferenceSource,
+    genomes: PathBuf,
+    representatives_only: bool,
+}
+
+fn get_references_path(work_folder: &Path) -> PathBuf {
+    PathBuf::from(work_folder).join("references.tsv")
+}
+
+fn get_imported_quality_path(work_folder: &Path) -> PathBuf {
+    PathBuf::from(work_folder).join("genome_quality.tsv")
+}
+
+// Writes the imported genomes in the training file format, and their quality if the metadata has it (GTDB).
+// Returns the paths of both files, the quality is None if there is no such data.
+fn import_references(import: &ImportSettings, checkpoints: &Checkpoints, work_folder: &Path) -> std::io::Result<(PathBuf, Option<PathBuf>)> {
+    let references_path = get_references_path(work_folder);
+    let quality_path = get_imported_quality_path(work_folder);
+    if checkpoints.is_done("import") {
+        return Ok((references_path, Some(quality_path).filter(|p| p.exists())));
+    }
+
+    let genomes = GenomeDirectory::scan(import.genomes.as_path())?;
+    eprintln!("Found {} genomes in '{}'", genomes.len(), import.genomes.display());
+    let (metadata, imported) = match &import.source {
+        ReferenceSource::Gtdb { metadata } => (metadata, reference_import::import_gtdb(metadata, &genomes, import.representatives_only)?),
+        ReferenceSource::Ncbi { assembly_summary, taxdump } => {
+            let taxdump = TaxDump::load(taxdump)?;
+            (assembly_summary, reference_import::import_ncbi(assembly_summary, &taxdump, &genomes, import.representatives_only)?)
+        },
+    };
+    if imported.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+                                       format!("E: none of the genomes listed in '{}' were found in '{}'", metadata.display(), import.genomes.display())));
+    }
+    eprintln!("Imported {} genomes listed in '{}'", imported.len(), metadata.display());
+
+    reference_import::write_references(&imported, checkpoints.create_file(references_path.as_path())?)?;
+    if reference_import::has_quality(&imported) {
+        reference_import::write_quality(&imported, checkpoints.create_file(quality_path.as_path())?)?;
+        checkpoints.mark_done("import", &[references_path.as_path(), quality_path.as_path()])?;
+        Ok((references_path, Some(quality_path)))
+    } else {
+        checkpoints.mark_done("import", &[references_path.as_path()])?;
+        Ok((references_path, None))
+    }
+}
+
 // Collect the full sample provided by the user
 // Map taxonomies to the order in which they were found, this can be used to keep consistent order
+// Genomes are compared by the ANI tools as a single FASTA file, a genome of several files (or repeating its name) is refused
 fn collect_samples(input_database: &Path) -> (HashMap<Taxonomy, Vec<Sample>>, HashMap<Taxonomy, usize>) {
     let mut mapping = HashMap::new();
     let mut order = HashMap::new();
 
-    for sample in SampleSource::new(input_database, true).into_iter() {
-        match sample {
-            Err(e) => panic!("E: failed to collect samples from '{}' due to: {}", input_database.display(), e),
-            Ok(sample) => {
-                let taxonomy = sample.get_taxonomy().clone();
-                match taxonomy {
-                    Some(mut taxonomy) => {
-                        if !taxonomy.has_rank(&TaxonomicRank::Species) {
-                            taxonomy.fill_rank(&TaxonomicRank::Species);
-                        }
-                        mapping.entry(taxonomy.clone()).or_insert(Vec::new()).push(sample);
-                        order.entry(taxonomy).or_insert(mapping.len() - 1);
-                    },
-                    None => panic!("E: invalid sample has no taxonomy"),
+    let samples = match SampleSource::new(input_database, true).into_iter().collect::<Result<Vec<Sample>, SampleError>>()
+        .and_then(|samples| merge_repeated_samples(&samples)) {
+        Err(e) => panic!("E: failed to collect samples from '{}' due to: {}", input_database.display(), e),
+        Ok(samples) => samples,
+    };
+    for sample in samples {
+        if sample.get_paths().len() > 1 {
+            panic!("E: genome '{}' of '{}' has several FASTA files, DBBuild needs a single FASTA file per genome", sample.get_name(), input_database.display());
+        }
+        let taxonomy = sample.get_taxonomy().clone();
+        match taxonomy {
+            Some(mut taxonomy) => {
+                if !taxonomy.has_rank(&TaxonomicRank::Species) {
+                    taxonomy.fill_rank(&TaxonomicRank::Species);
                 }
+                mapping.entry(taxonomy.clone()).or_insert(Vec::new()).push(sample);
+                order.entry(taxonomy).or_insert(mapping.len() - 1);
             },
+            None => panic!("E: invalid sample has no taxonomy"),
         }
     }
 
@@ -65,17 +180,43 @@ fn taxa2order_into_ordered_taxa(taxa2order: &HashMap<Taxonomy, usize>) -> Vec<&T
     order.iter().map(|o| order2taxa[o]).collect()
 }
 
-// Randomly pick N (number_of_representatives_per_species) representatives from each taxonomy.
-fn pick_representatives(mapping: HashMap<Taxonomy, Vec<Sample>>, taxa2order: &HashMap<Taxonomy, usize>, number_of_representatives_per_species: usize, random_state: &mut StdRng) -> HashMap<Taxonomy, Vec<Sample>> {
+// Pick N (representatives.per_species) representatives from each taxonomy, randomly or by genome quality.
+fn pick_representatives(mapping: HashMap<Taxonomy, Vec<Sample>>, taxa2order: &HashMap<Taxonomy, usize>, representatives: &mut Representatives) -> HashMap<Taxonomy, Vec<Sample>> {
     // Make sure we sample in a consistent order to achieve repeatable runs
     let ordered_taxa = taxa2order_into_ordered_taxa(taxa2order);
     
     // Now collect
     ordered_taxa.iter()
-        .map(|&tax| (tax.clone(), mapping[tax].choose_multiple(random_state, number_of_representatives_per_species).cloned().collect::<Vec<Sample>>()))
+        .map(|&tax| {
+            let picked = match &representatives.quality {
+                Some(quality) => quality.pick(&mapping[tax], representatives.per_species, representatives.strategy, &mut representatives.random_state),
+                None => mapping[tax].choose_multiple(&mut representatives.random_state, representatives.per_species).cloned().collect::<Vec<Sample>>(),
+            };
+            (tax.clone(), picked)
+        })
         .collect()
 }
 
+fn get_representatives_path(work_folder: &Path) -> PathBuf {
+    PathBuf::from(work_folder).join("representatives.tsv")
+}
+
+// Uses the training file format (name, path and taxonomy), in the order of the input
+fn create_representatives(taxa2mags: &HashMap<Taxonomy, Vec<Sample>>, taxa2order: &HashMap<Taxonomy, usize>, checkpoints: &Checkpoints, work_folder: &Path) -> std::io::Result<()> {
+    let mut stream = BufWriter::new(checkpoints.create_file(get_representatives_path(work_folder).as_path())?);
+    for taxonomy in taxa2order_into_ordered_taxa(taxa2order) {
+        for sample in &taxa2mags[taxonomy] {
+            writeln!(stream, "{}\t{}\t{}", sample.get_name(), sample.get_path().display(), taxonomy)?;
+        }
+    }
+    stream.flush()
+}
+
+fn load_representatives(work_folder: &Path) -> HashMap<Taxonomy, Vec<Sample>> {
+    let (taxa2mags, _) = collect_samples(get_representatives_path(work_folder).as_path());
+    taxa2mags
+}
+
 fn apply_func_on_str_series(str_series: polars::frame::column::Column, func: &dyn Fn (&str) -> String) -> PolarsResult<Option<polars::frame::column::Column>> {
     let results = str_series.
         str()?
@@ -86,7 +227,7 @@ fn apply_func_on_str_series(str_series: polars::frame::column::Column, func: &dy
 }
 
 // fn merge_by_ani(ani_results_path: &dyn AniCalculator, path_str2taxonomy: HashMap<String, Taxonomy>, ani_cutoff: f64) -> PolarsResult<Vec<HashSet<Taxonomy>>> {
-fn merge_by_ani(ani_results_path: &dyn AniCalculator, mag_path2taxonomy: &HashMap<&Path, &Taxonomy>, ani_cutoff: f64) -> PolarsResult<Vec<HashSet<Taxonomy>>> {
+fn merge_by_ani(ani_results_path: &dyn AniCalculator, mag_path2taxonomy: &HashMap<&Path, &Taxonomy>, ani_cutoff: f64, linkage: Linkage) -> PolarsResult<Vec<(HashSet<Taxonomy>, ClusterDiagnostics)>> {
     let g1 = Column(PlSmallStr::from("g1"));
     let g2 = Column(PlSmallStr::from("g2"));
     let ani = Column(PlSmallStr::from("ani"));
@@ -99,25 +240,46 @@ fn merge_by_ani(ani_results_path: &dyn AniCalculator, mag_path2taxonomy: &HashMa
             None => panic!("E: '{}' doesn't represent a path in mag_path2taxonomy, can't recover", s),
         };
         let f = move |c| apply_func_on_str_series(c, &fs);
+        // All values are kept, average and complete linkage need the distant pairs as well
         ani_results_path.get_data_frame().finish()?
             // .filter(g1.clone().lt_eq(g2.clone())) // This leads to lost taxa (make sense)
-            .filter((lit(100.0) - ani).lt_eq(ani_cutoff))
+            .filter(g1.clone().neq(g2.clone())) // A genome compared to itself says nothing about its taxon
             .with_column(g1.clone().map(f.clone(), GetOutput::same_type()).alias(PlSmallStr::from("g1")))
             .with_column(g2.clone().map(f, GetOutput::same_type()).alias(PlSmallStr::from("g2")))
-            .select([g1, g2])
+            .select([g1, g2, ani])
             .collect()
     }?;
 
-    let mut graph: StaticGraph<TaxonomyName, u8, u8> = StaticGraph::new(None, 0);
-    let mut row = ani_df.get_row(0)?;
-    for i in 0..ani_df.height() {
-        ani_df.get_row_amortized(i, &mut row)?;
-        let g1_data = row.0.get(0).unwrap_or_else(|| panic!("E: Row {} is missing g1 data", i)).to_string();
-        let g2_data = row.0.get(1).unwrap_or_else(|| panic!("E: Row {} is missing g2 data", i)).to_string();
-        graph.add_edge(&g1_data, &g2_data, &None, |_, _| None);
+    // Both directions and every genome pair of two taxa are summarized into a single edge, adding an existing edge to the graph is costly
+    let mut vertices = Vec::new();
+    let mut pair2summary: HashMap<(TaxonomyName, TaxonomyName), AniSummary> = HashMap::new();
+    if ani_df.height() > 0 {
+        let mut row = ani_df.get_row(0)?;
+        for i in 0..ani_df.height() {
+            ani_df.get_row_amortized(i, &mut row)?;
+            let g1_data: TaxonomyName = row.0.first().unwrap_or_else(|| panic!("E: Row {} is missing g1 data", i)).str_value().to_string();
+            let g2_data: TaxonomyName = row.0.get(1).unwrap_or_else(|| panic!("E: Row {} is missing g2 data", i)).str_value().to_string();
+            let ani_data: f64 = row.0.get(2).unwrap_or_else(|| panic!("E: Row {} is missing ani data", i)).try_extract()?;
+            vertices.push(g1_data.clone());
+            vertices.push(g2_data.clone());
+            let pair = if g1_data <= g2_data { (g1_data, g2_data) } else { (g2_data, g1_data) };
+            pair2summary.entry(pair)
+                .and_modify(|summary| summary.add(ani_data))
+                .or_insert_with(|| AniSummary::new(ani_data));
+        }
     }
-
-    Ok(graph.connected_components().iter().map(|s| s.iter().map(|&i| Taxonomy::from(i.as_str())).collect()).collect())
+    // Sorted, so the clusters don't depend on the hashing order
+    let mut edges = pair2summary.into_iter()
+        .map(|((g1, g2), summary)| (g1, g2, Some(summary)))
+        .collect::<Vec<_>>();
+    edges.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+    let mut graph: StaticGraph<TaxonomyName, AniSummary, u8> = StaticGraph::new(None, 0);
+    graph.batch_add_vertex(&vertices, &edges, AniSummary::merge);
+
+    Ok(linkage::cluster(&graph, linkage, 100.0 - ani_cutoff).iter()
+        .map(|members| (members.iter().map(|&i| Taxonomy::from(i.as_str())).collect(), ClusterDiagnostics::new(&graph, members)))
+        .collect())
 }
 
 fn get_genus_cluster_list_path(work_folder: &Path, genus_number: usize) -> PathBuf {
@@ -133,6 +295,7 @@ fn get_genus_cluster_ani_path(work_folder: &Path, genus_number: usize) -> PathBu
 // This function create a file for each genus that contain all its samples.
 fn get_mag_path2taxonomy_and_found_genera<'a>(mapping: &'a HashMap<Taxonomy, Vec<Sample>>,
                                               taxa2order: &'a HashMap<Taxonomy, usize>,
+                                              checkpoints: &Checkpoints,
                                               work_folder: &Path) -> std::io::Result<HashMap<&'a Path, &'a Taxonomy>> {
     let mut mag_path2taxonomy = HashMap::new();
 
@@ -153,7 +316,7 @@ fn get_mag_path2taxonomy_and_found_genera<'a>(mapping: &'a HashMap<Taxonomy, Vec
         let genus_output_fp = get_genus_cluster_list_path(work_folder, *genus2order.get(&genus).unwrap_or_else(|| panic!("E: genus '{}' have no order!", genus)));
         if !genus2handler.contains_key(&genus) {
             genus2handler.insert(genus.clone(),
-                                 BufWriter::new(File::create_new(genus_output_fp.clone())?));
+                                 BufWriter::new(checkpoints.create_file(genus_output_fp.as_path())?));
         }
         assert!(genus_output_fp.exists());
         
@@ -171,72 +334,92 @@ fn get_mag_path2taxonomy_and_found_genera<'a>(mapping: &'a HashMap<Taxonomy, Vec
     Ok(mag_path2taxonomy)
 }
 
-fn get_ani_calculators(taxa2order: &HashMap<Taxonomy, usize>, work_folder: &Path, n_jobs: usize, ani_calculator: AniCalculatorTool) -> Vec<Box<dyn AniCalculator>> {
-    taxa2order.values()
-        .filter_map(|i| {
-            let list_path = get_genus_cluster_list_path(work_folder, *i);
-            let ani_results_path = get_genus_cluster_ani_path(work_folder, *i);
-            let ani: Option<Box<dyn AniCalculator>> = if ! ani_results_path.exists() {
-                if list_path.exists() {
-                    Some(match ani_calculator {
-                        AniCalculatorTool::FastANI => Box::new(FastANI::run(get_genus_cluster_list_path(work_folder, *i).as_path(),
-                                                                            ani_results_path.as_path(),
-                                                                            n_jobs)),
-                        AniCalculatorTool::Skani => Box::new(Skani::run(get_genus_cluster_list_path(work_folder, *i).as_path(),
-                                                                        ani_results_path.as_path(),
-                                                                        n_jobs)),
-                    })
-                } else {
-                    None
-                }
-            } else {
-                Some(match ani_calculator {
-                    AniCalculatorTool::FastANI => Box::new(FastANI::pre_calculated(ani_results_path.as_path())),
-                    AniCalculatorTool::Skani => Box::new(Skani::pre_calculated(ani_results_path.as_path())),
-                })
-            };
-            ani
-        })
-        .collect()
+fn pre_calculated_ani(ani_results_path: &Path, distance_tool: DistanceTool) -> Box<dyn AniCalculator> {
+    match distance_tool {
+        DistanceTool::Ani(AniCalculatorTool::FastANI) => Box::new(FastANI::pre_calculated(ani_results_path)),
+        DistanceTool::Ani(AniCalculatorTool::Skani) => Box::new(Skani::pre_calculated(ani_results_path)),
+        DistanceTool::GeneZip { .. } => Box::new(GeneZipDistance::pre_calculated(ani_results_path)),
+    }
+}
+
+// Each genus is a stage of its own, so a resumed run only calculates the genera that didn't finish
+fn get_ani_calculators(taxa2order: &HashMap<Taxonomy, usize>, checkpoints: &Checkpoints, work_folder: &Path, n_jobs: usize, distance_tool: DistanceTool) -> std::io::Result<Vec<Box<dyn AniCalculator>>> {
+    let mut ani_calculators = Vec::new();
+    for i in taxa2order.values() {
+        let list_path = get_genus_cluster_list_path(work_folder, *i);
+        let ani_results_path = get_genus_cluster_ani_path(work_folder, *i);
+        let stage = format!("genus_{}", i);
+        if checkpoints.is_done(&stage) {
+            ani_calculators.push(pre_calculated_ani(ani_results_path.as_path(), distance_tool));
+            continue;
+        }
+
+        // Only marked results are trusted, any other may be of other genomes or another distance tool
+        let ani: Box<dyn AniCalculator> = if list_path.exists() {
+            if ani_results_path.exists() {
+                remove_file(ani_results_path.as_path())?;
+            }
+            match distance_tool {
+                DistanceTool::Ani(AniCalculatorTool::FastANI) => Box::new(FastANI::run(list_path.as_path(),
+                                                                                      ani_results_path.as_path(),
+                                                                                      n_jobs)),
+                DistanceTool::Ani(AniCalculatorTool::Skani) => Box::new(Skani::run(list_path.as_path(),
+                                                                                  ani_results_path.as_path(),
+                                                                                  n_jobs)),
+                DistanceTool::GeneZip { max_depth, buffer_size } => Box::new(GeneZipDistance::run(list_path.as_path(),
+                                                                                                 ani_results_path.as_path(),
+                                                                                                 max_depth,
+                                                                                                 buffer_size)),
+            }
+        } else {
+            continue;
+        };
+        checkpoints.mark_done(&stage, &[list_path.as_path(), ani.results_path()])?;
+        ani_calculators.push(ani);
+    }
+
+    Ok(ani_calculators)
 }
 
 // Merge taxonomies by ANI values.
 fn merge_by_genus(mapping: HashMap<Taxonomy, Vec<Sample>>,
                   taxa2order: &HashMap<Taxonomy, usize>,
-                  merge_distance: f64,
+                  checkpoints: &Checkpoints,
                   work_folder: &Path,
                   n_jobs: usize,
-                  ani_calculator: AniCalculatorTool) -> std::io::Result<HashMap<Vec<Taxonomy>, HashSet<Sample>>> {
-    let mag_path2taxonomy= get_mag_path2taxonomy_and_found_genera(&mapping, taxa2order, work_folder)?;
+                  merge: MergeSettings) -> std::io::Result<(MergedTaxa, MergeDiagnostics)> {
+    let mag_path2taxonomy= get_mag_path2taxonomy_and_found_genera(&mapping, taxa2order, checkpoints, work_folder)?;
     
 
     // Run ANI calculator
-    let ani_calculators = get_ani_calculators(taxa2order, work_folder, n_jobs, ani_calculator);
+    let ani_calculators = get_ani_calculators(taxa2order, checkpoints, work_folder, n_jobs, merge.tool)?;
 
     // Extract merges
-    let merges: Vec<HashSet<Taxonomy>> = ani_calculators.iter()
-        .flat_map(|ani| merge_by_ani(ani.as_ref(), &mag_path2taxonomy, merge_distance)
+    let merges: Vec<(HashSet<Taxonomy>, ClusterDiagnostics)> = ani_calculators.iter()
+        .flat_map(|ani| merge_by_ani(ani.as_ref(), &mag_path2taxonomy, merge.distance, merge.linkage)
             .unwrap_or_else(|e| panic!("E: failed to extract data from '{}' because '{:?}'", ani.results_path().display(), e)))
         .collect();
 
     // Create results
-    let mut results: HashMap<Vec<Taxonomy>, HashSet<Sample>> = HashMap::new();
+    let mut results: MergedTaxa = Vec::new();
+    let mut diagnostics: MergeDiagnostics = HashMap::new();
     let mut merged_taxa: HashSet<Taxonomy> = HashSet::new();
-    for mer in merges.iter().map(|m| Vec::from_iter(m.iter().cloned())) {
-        results.insert(mer.clone(), HashSet::new()); // mer is turned into a tuple on the python version
-        for m in mer.iter() {
-            results.get_mut(&mer).expect("E: we did insert the empty set, so it should wrong.").extend(mapping[m].iter().cloned());
-        }
+    for (mut mer, diagnostic) in merges.into_iter().map(|(m, d)| (Vec::from_iter(m), d)) {
+        mer.sort_by_key(|m| taxa2order[m]);
+        let mags = mer.iter().flat_map(|m| mapping[m].iter().cloned()).collect(); // mer is turned into a tuple on the python version
         merged_taxa.extend(mer.iter().cloned());
+        diagnostics.insert(mer.clone(), diagnostic);
+        results.push((mer, mags));
     }
 
     for (m, k) in mapping {
         if ! merged_taxa.contains(&m) {
-            results.insert(vec![m.clone()], HashSet::from_iter(k)); // m was in a tuple
+            results.push((vec![m.clone()], HashSet::from_iter(k))); // m was in a tuple
         }
     }
+    results.sort_by_key(|(mer, _)| taxa2order[&mer[0]]);
 
-    Ok(results)
+    Ok((results, diagnostics))
 }
 
 #[allow(dead_code)]
@@ -257,10 +440,25 @@ fn drop_no_species(mapping: &mut HashMap<Taxonomy, Vec<Sample>>) {
     }
 }
 
-fn create_taxa2cluster(merged_taxa2mags: &HashMap<Vec<Taxonomy>, HashSet<Sample>>, work_folder: &Path) -> std::io::Result<()> {
-    let path = PathBuf::from(work_folder).join("taxa2cluster.tsv");
-    let mut stream = BufWriter::new(File::create_new(path.as_path())?);
-    for (i, cluster) in merged_taxa2mags.keys().enumerate() {
+fn get_taxa2cluster_path(work_folder: &Path) -> PathBuf {
+    PathBuf::from(work_folder).join("taxa2cluster.tsv")
+}
+
+fn get_cluster_diagnostics_path(work_folder: &Path) -> PathBuf {
+    PathBuf::from(work_folder).join("cluster_diagnostics.tsv")
+}
+
+fn get_representative2cluster_path(work_folder: &Path) -> PathBuf {
+    PathBuf::from(work_folder).join("representative2cluster.tsv")
+}
+
+fn get_training_path(work_folder: &Path) -> PathBuf {
+    PathBuf::from(work_folder).join("training.tsv")
+}
+
+fn create_taxa2cluster(merged_taxa2mags: &MergedTaxa, checkpoints: &Checkpoints, work_folder: &Path) -> std::io::Result<()> {
+    let mut stream = BufWriter::new(checkpoints.create_file(get_taxa2cluster_path(work_folder).as_path())?);
+    for (i, (cluster, _)) in merged_taxa2mags.iter().enumerate() {
         for member in cluster {
             writeln!(stream, "{}\t{}", member, i)?;
         }
@@ -268,21 +466,80 @@ fn create_taxa2cluster(merged_taxa2mags: &HashMap<Vec<Taxonomy>, HashSet<Sample>
     stream.flush()
 }
 
-fn create_representative2cluster(merged_taxa2mags: &HashMap<Vec<Taxonomy>, HashSet<Sample>>, work_folder: &Path) -> std::io::Result<()> {
-    let path = PathBuf::from(work_folder).join("representative2cluster.tsv");
-    let mut stream = BufWriter::new(File::create_new(path.as_path())?);
-    for (i, cluster) in merged_taxa2mags.values().enumerate() {
+// Cluster numbers match taxa2cluster.tsv
+fn create_cluster_diagnostics(merged_taxa2mags: &MergedTaxa, diagnostics: &MergeDiagnostics, linkage: Linkage, checkpoints: &Checkpoints, work_folder: &Path) -> std::io::Result<()> {
+    let mut stream = BufWriter::new(checkpoints.create_file(get_cluster_diagnostics_path(work_folder).as_path())?);
+    let na = |v: Option<f64>| v.map_or(String::from("NA"), |v| v.to_string());
+    writeln!(stream, "cluster\tlinkage\ttaxa\tgenomes\tcomparisons\tmissing_pairs\tmin_ani\tmean_ani")?;
+    for (i, (cluster, mags)) in merged_taxa2mags.iter().enumerate() {
+        let diagnostic = diagnostics.get(cluster).cloned().unwrap_or_default();
+        writeln!(stream, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", i, linkage, cluster.len(), mags.len(),
+                 diagnostic.get_comparisons(), diagnostic.get_missing_pairs(), na(diagnostic.get_min_ani()), na(diagnostic.get_mean_ani()))?;
+    }
+    stream.flush()
+}
+
+// Reload the clusters of a finished merging stage, keeping their numbers
+fn load_taxa2cluster(taxa2mags: &HashMap<Taxonomy, Vec<Sample>>, work_folder: &Path) -> std::io::Result<MergedTaxa> {
+    let path = get_taxa2cluster_path(work_folder);
+    let mut results: MergedTaxa = Vec::new();
+    for line in read_to_string(path.as_path())?.lines() {
+        let (taxonomy, cluster) = match line.rsplit_once('\t').map(|(t, c)| (t, c.parse::<usize>())) {
+            Some((taxonomy, Ok(cluster))) => (Taxonomy::from(taxonomy), cluster),
+            _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("E: invalid line '{}' in '{}'", line, path.display()))),
+        };
+        if results.len() <= cluster {
+            results.resize(cluster + 1, (Vec::new(), HashSet::new()));
+        }
+        match taxa2mags.get(&taxonomy) {
+            Some(mags) => results[cluster].1.extend(mags.iter().cloned()),
+            None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("E: '{}' lists '{}' which has no representatives", path.display(), taxonomy))),
+        }
+        results[cluster].0.push(taxonomy);
+    }
+
+    Ok(results)
+}
+
+// One model per cluster, trained on all its representatives, and reporting the lowest common taxonomy of its taxa
+fn create_database(merged_taxa2mags: &MergedTaxa, database: &DatabaseSettings, n_jobs: usize) -> std::io::Result<()> {
+    let clusters = merged_taxa2mags.iter().enumerate()
+        .map(|(i, (taxa, mags))| {
+            let taxonomy = taxa.iter().skip(1)
+                .try_fold(taxa[0].clone(), |lca, taxonomy| lca.lca(taxonomy))
+                .unwrap_or_else(|| panic!("E: cluster {} has no common taxonomy, its taxa are {:?}", i, taxa));
+            let mut fastas = mags.iter().map(|sample| sample.get_path().to_path_buf()).collect::<Vec<PathBuf>>();
+            fastas.sort();
+            (get_cluster_model_name(i), fastas, taxonomy)
+        })
+        .collect::<Vec<(String, Vec<PathBuf>, Taxonomy)>>();
+
+    let mut classifier = Classifier::new(LenBases::new(database.max_depth));
+    classifier.batch_add_clusters(&clusters, database.max_depth, database.buffer_size, &database.kmer_size);
+    Database::new(classifier, database.max_depth, database.kmer_size).save(database.path.as_path(), n_jobs)
+        .map_err(|e| std::io::Error::other(format!("E: failed to save GeneZip database to '{}' due to '{}'", database.path.display(), e)))
+}
+
+// Cluster numbers match taxa2cluster.tsv
+fn get_cluster_model_name(cluster: usize) -> String {
+    format!("cluster_{}", cluster)
+}
+
+// Each representative is written with the strategy used to pick it and its quality score (NA if missing)
+fn create_representative2cluster(merged_taxa2mags: &MergedTaxa, representatives: &Representatives, checkpoints: &Checkpoints, work_folder: &Path) -> std::io::Result<()> {
+    let mut stream = BufWriter::new(checkpoints.create_file(get_representative2cluster_path(work_folder).as_path())?);
+    for (i, (_, cluster)) in merged_taxa2mags.iter().enumerate() {
         for member in cluster {
-            writeln!(stream, "{}\t{}", member.get_path().display(), i)?;
+            let score = representatives.get_score(member).map_or(String::from("NA"), |s| s.to_string());
+            writeln!(stream, "{}\t{}\t{}\t{}", member.get_path().display(), i, representatives.strategy, score)?;
         }
     }
     stream.flush()
 }
 
-fn create_training_file(merged_taxa2mags: &HashMap<Vec<Taxonomy>, HashSet<Sample>>, work_folder: &Path) -> std::io::Result<()> {
-    let path = PathBuf::from(work_folder).join("training.tsv");
-    let mut stream = BufWriter::new(File::create_new(path.as_path())?);
-    for cluster in merged_taxa2mags.values() {
+fn create_training_file(merged_taxa2mags: &MergedTaxa, checkpoints: &Checkpoints, work_folder: &Path) -> std::io::Result<()> {
+    let mut stream = BufWriter::new(checkpoints.create_file(get_training_path(work_folder).as_path())?);
+    for (_, cluster) in merged_taxa2mags {
         for member in cluster {
             writeln!(stream, "{}\t{}", member.get_name(), member.get_path().display())?;
         }
@@ -304,15 +561,21 @@ fn get_genus_order(taxa2order: &HashMap<Taxonomy, usize>) -> HashMap<Taxonomy, u
     genus_order
 }
 
-fn run(input_database: &Path, merge_distance: f64, work_folder: &Path, n_jobs: usize, sample_size: usize, random_state: &mut StdRng, ani_calculator: AniCalculatorTool) -> std::io::Result<()> {
-    if !work_folder.exists() {
-        create_dir_all(work_folder)?;
-    }
+// The run is made of stages (sampling, per-genus ANI, merging and outputs), each records a checkpoint when done.
+// With resume set, finished stages are skipped as long as their outputs are intact.
+fn run(input_database: &Path, work_folder: &Path, n_jobs: usize, representatives: &mut Representatives, merge: MergeSettings, database: Option<&DatabaseSettings>, checkpoints: &Checkpoints) -> std::io::Result<()> {
 
     let (taxa2mags, taxa2order) = collect_samples(input_database);
     assert_eq!(taxa2mags.len(), taxa2order.len());
     // drop_no_species(&mut taxa2mags);
-    let taxa2mags = pick_representatives(taxa2mags, &taxa2order, sample_size, random_state);
+    let taxa2mags = if checkpoints.is_done("sampling") {
+        load_representatives(work_folder)
+    } else {
+        let taxa2mags = pick_representatives(taxa2mags, &taxa2order, representatives);
+        create_representatives(&taxa2mags, &taxa2order, checkpoints, work_folder)?;
+        checkpoints.mark_done("sampling", &[get_representatives_path(work_folder).as_path()])?;
+        taxa2mags
+    };
 
     // Debug missing list files:
     // for (taxa, order) in taxa2order.iter() {
@@ -323,12 +586,31 @@ fn run(input_database: &Path, merge_distance: f64, work_folder: &Path, n_jobs: u
     // }
     
     assert_eq!(taxa2mags.len(), taxa2order.len());
-    let merged_taxa2mags = merge_by_genus(taxa2mags, &taxa2order, merge_distance, work_folder, n_jobs, ani_calculator)?;
+    let merged_taxa2mags = if checkpoints.is_done("merging") {
+        load_taxa2cluster(&taxa2mags, work_folder)?
+    } else {
+        let (merged_taxa2mags, diagnostics) = merge_by_genus(taxa2mags, &taxa2order, checkpoints, work_folder, n_jobs, merge)?;
+        create_taxa2cluster(&merged_taxa2mags, checkpoints, work_folder)?;
+        create_cluster_diagnostics(&merged_taxa2mags, &diagnostics, merge.linkage, checkpoints, work_folder)?;
+        checkpoints.mark_done("merging", &[get_taxa2cluster_path(work_folder).as_path(), get_cluster_diagnostics_path(work_folder).as_path()])?;
+        merged_taxa2mags
+    };
 
     // Create output files
-    create_taxa2cluster(&merged_taxa2mags, work_folder)?;
-    create_representative2cluster(&merged_taxa2mags, work_folder)?;
-    create_training_file(&merged_taxa2mags, work_folder)
+    if !checkpoints.is_done("outputs") {
+        create_representative2cluster(&merged_taxa2mags, representatives, checkpoints, work_folder)?;
+        create_training_file(&merged_taxa2mags, checkpoints, work_folder)?;
+        checkpoints.mark_done("outputs", &[get_representative2cluster_path(work_folder).as_path(), get_training_path(work_folder).as_path()])?;
+    }
+
+    if let Some(database) = database {
+        if !checkpoints.is_done("database") {
+            create_database(&merged_taxa2mags, database, n_jobs)?;
+            checkpoints.mark_done("database", &[database.path.as_path()])?;
+        }
+    }
+
+    Ok(())
 }
 
 #[derive(Parser)]
@@ -343,29 +625,197 @@ pub struct DBBuilderCLI {
     #[arg(short = 's', long = "sample", value_name = "sample", required = true)]
     sample: usize,
 
-    /// Output folder
+    /// Output folder. A folder that holds an earlier run is only used with --resume or --overwrite
     #[arg(short = 'o', long = "output", value_name = "output", required = true)]
     output: PathBuf,
 
-    /// Merge distance
+    /// Merge distance, species are merged if 100 - ANI is at most this value.
+    /// With --gz-distance the distance is 100 * (score - 1), using the symmetric GeneZip score.
     #[arg(short = 'm', long = "merge", value_name = "merger", required = true)]
     merge: f64,
 
     /// Input database
-    #[arg(short = 'i', long = "input", value_name = "input", required = true)]
-    input: PathBuf,
+    #[arg(short = 'i', long = "input", value_name = "input", required_unless_present_any = ["gtdb", "ncbi"], conflicts_with_all = ["gtdb", "ncbi"])]
+    input: Option<PathBuf>,
+
+    /// Build the input from a GTDB metadata table (e.g. bac120_metadata.tsv) and the genomes in --genomes.
+    /// Its CheckM columns are used as the genome quality, unless --quality is set
+    #[arg(long = "gtdb", value_name = "gtdb", requires = "genomes", conflicts_with = "ncbi")]
+    gtdb: Option<PathBuf>,
+
+    /// Build the input from an NCBI assembly_summary.txt, with taxonomies from --taxdump, and the genomes in --genomes
+    #[arg(long = "ncbi", value_name = "ncbi", requires_all = ["genomes", "taxdump"])]
+    ncbi: Option<PathBuf>,
+
+    /// Folder of the NCBI taxdump, holding nodes.dmp and names.dmp
+    #[arg(long = "taxdump", value_name = "taxdump", requires = "ncbi")]
+    taxdump: Option<PathBuf>,
+
+    /// Folder of downloaded genomes (searched recursively), matched to the metadata by the GCA_/GCF_ accession in their file names
+    #[arg(long = "genomes", value_name = "genomes")]
+    genomes: Option<PathBuf>,
+
+    /// Only import GTDB species representatives, or NCBI reference and representative genomes
+    #[arg(long = "representatives-only", value_name = "representatives-only", default_value_t = false)]
+    representatives_only: bool,
 
     /// Random state seed
     #[arg(short = 'r', long = "rss", value_name = "rss", default_value_t = 1)]
     rss: u64,
     
     /// Choose ANI calculation tool
-    #[arg(short = 'a', long = "ani", value_name = "ani")]
-    ani: AniCalculatorTool,
+    #[arg(short = 'a', long = "ani", value_name = "ani", required_unless_present = "gz_distance")]
+    ani: Option<AniCalculatorTool>,
+
+    /// Cluster using the symmetric GeneZip score (GZ(G_1, G_2) + GZ(G_2, G_1)) / (GZ(G_1, G_1) + GZ(G_2, G_2))
+    /// instead of an external ANI tool
+    #[arg(short = 'g', long = "gz-distance", value_name = "gz-distance", default_value_t = false, conflicts_with = "ani")]
+    gz_distance: bool,
+
+    /// Maximum depth of the context trees used by --gz-distance and --db
+    #[arg(short = 'd', long = "depth", value_name = "depth", default_value_t = 13)]
+    max_depth: usize,
+
+    /// buffer size used to read files, must be >= 1
+    #[arg(short = 'b', long = "buffer", value_name = "buffer", default_value_t = 512)]
+    buffer_size: usize,
+
+    /// How species are merged into clusters, single linkage merges connected components of close species
+    #[arg(short = 'l', long = "linkage", value_name = "linkage", default_value_t = Linkage::Single)]
+    linkage: Linkage,
+
+    /// How representatives are picked from the genomes of each species
+    #[arg(short = 'p', long = "pick", value_name = "pick", default_value_t = RepresentativeStrategy::Random)]
+    pick: RepresentativeStrategy,
+
+    /// Per-genome metadata table (tab-separated with a header), the first column holds the genome name, path or file name.
+    /// Unless --score-column is set, the score is completeness - 5 * contamination + 0.5 * log10(N50).
+    /// Required by the top-score and weighted strategies, unless importing with --gtdb
+    #[arg(short = 'q', long = "quality", value_name = "quality")]
+    quality: Option<PathBuf>,
+
+    /// Use this column of the metadata table as the genome score
+    #[arg(long = "score-column", value_name = "score-column")]
+    score_column: Option<String>,
+
+    /// Resume an interrupted run in the output folder, stages with intact outputs are skipped.
+    /// The other parameters must match the interrupted run
+    #[arg(long = "resume", value_name = "resume", default_value_t = false)]
+    resume: bool,
+
+    /// Start over in an output folder that holds an earlier run, replacing its outputs
+    #[arg(long = "overwrite", value_name = "overwrite", default_value_t = false, conflicts_with = "resume")]
+    overwrite: bool,
+
+    /// Also train a GeneZip database, with a model per cluster trained on all its representatives.
+    /// Prediction output reports the lowest common taxonomy of the cluster
+    #[arg(long = "db", value_name = "db")]
+    db: Option<PathBuf>,
+
+    /// k-mer size used for the database models, set to 0 to disable
+    #[arg(short = 'k', long = "kmer", value_name = "kmer", default_value_t = 4, requires = "db")]
+    kmer_size: usize,
 }
+
+impl DBBuilderCLI {
+    fn get_database_settings(&self) -> Option<DatabaseSettings> {
+        self.db.as_ref().map(|path| DatabaseSettings {
+            path: path.clone(),
+            max_depth: self.max_depth,
+            buffer_size: self.buffer_size,
+            kmer_size: if self.kmer_size == 0 { None } else { Some(self.kmer_size) },
+        })
+    }
+
+    fn get_import_settings(&self) -> Option<ImportSettings> {
+        let source = match (&self.gtdb, &self.ncbi, &self.taxdump) {
+            (Some(metadata), _, _) => ReferenceSource::Gtdb { metadata: metadata.clone() },
+            (None, Some(assembly_summary), Some(taxdump)) => ReferenceSource::Ncbi { assembly_summary: assembly_summary.clone(), taxdump: taxdump.clone() },
+            _ => return None,
+        };
+        self.genomes.as_ref().map(|genomes| ImportSettings {
+            source,
+            genomes: genomes.clone(),
+            representatives_only: self.representatives_only,
+        })
+    }
+
+    // Replaces clap's required_if_eq_any, as GTDB imports come with their own quality table
+    fn check_quality_arguments(&self) {
+        if self.quality.is_none() && self.gtdb.is_none() {
+            let missing = match self.pick {
+                RepresentativeStrategy::TopScore | RepresentativeStrategy::Weighted => Some(format!("--pick {} requires --quality or --gtdb", self.pick)),
+                RepresentativeStrategy::Random if self.score_column.is_some() => Some(String::from("--score-column requires --quality or --gtdb")),
+                RepresentativeStrategy::Random => None,
+            };
+            if let Some(message) = missing {
+                clap::Error::raw(clap::error::ErrorKind::MissingRequiredArgument, format!("{}\n", message)).exit();
+            }
+        }
+    }
+
+    // The quality table set by the user takes precedence over the imported one
+    fn get_representatives(&self, imported_quality: Option<&Path>) -> std::io::Result<Representatives> {
+        let quality_path = self.quality.as_deref().or(imported_quality);
+        match (quality_path, self.pick) {
+            (None, RepresentativeStrategy::TopScore | RepresentativeStrategy::Weighted) =>
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("E: --pick {} requires --quality", self.pick))),
+            (None, _) if self.score_column.is_some() =>
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "E: --score-column requires --quality")),
+            _ => (),
+        }
+
+        Ok(Representatives {
+            per_species: self.sample,
+            strategy: self.pick,
+            random_state: StdRng::seed_from_u64(self.rss),
+            quality: match quality_path {
+                Some(path) => Some(GenomeQuality::load(path, self.score_column.as_deref())
+                    .map_err(|e| std::io::Error::new(e.kind(), format!("E: failed to load genome quality from '{}' due to '{}'", path.display(), e)))?),
+                None => None,
+            },
+        })
+    }
+
+    // Everything that affects the outputs, used to refuse resuming a run with different parameters
+    fn get_parameters(&self) -> String {
+        format!("input\t{:?}\nimport\t{:?}\nsample\t{}\nrss\t{}\npick\t{}\nquality\t{:?}\nscore_column\t{:?}\nmerge\t{:?}\ndb\t{:?}\n",
+                self.input, self.get_import_settings(), self.sample, self.rss, self.pick, self.quality, self.score_column, self.get_merge_settings(), self.get_database_settings())
+    }
+
+    fn get_merge_settings(&self) -> MergeSettings {
+        MergeSettings {
+            distance: self.merge,
+            tool: self.get_distance_tool(),
+            linkage: self.linkage,
+        }
+    }
+
+    fn get_distance_tool(&self) -> DistanceTool {
+        match self.ani {
+            Some(ani) if !self.gz_distance => DistanceTool::Ani(ani),
+            _ => DistanceTool::GeneZip { max_depth: self.max_depth, buffer_size: self.buffer_size },
+        }
+    }
+}
+
 fn interface() {
     let cli = DBBuilderCLI::parse();
-    match run(cli.input.as_path(), cli.merge, cli.output.as_path(), cli.jobs, cli.sample, &mut StdRng::seed_from_u64(cli.rss), cli.ani) {
+    cli.check_quality_arguments();
+    if cli.jobs > 0 {
+        rayon::ThreadPoolBuilder::new().num_threads(cli.jobs).build_global().unwrap();
+    }
+    if !cli.output.exists() {
+        create_dir_all(cli.output.as_path()).unwrap_or_else(|e| panic!("E: failed to create '{}' due to '{}'", cli.output.display(), e));
+    }
+    let checkpoints = Checkpoints::new(cli.output.as_path(), cli.resume, cli.overwrite, &cli.get_parameters()).unwrap_or_else(|e| panic!("{}", e));
+    let (input, imported_quality) = match (cli.get_import_settings(), &cli.input) {
+        (Some(import), _) => import_references(&import, &checkpoints, cli.output.as_path()).unwrap_or_else(|e| panic!("E: failed to import references due to '{}'", e)),
+        (None, Some(input)) => (input.clone(), None),
+        (None, None) => unreachable!("clap requires either --input or an importer"),
+    };
+    let mut representatives = cli.get_representatives(imported_quality.as_deref()).unwrap_or_else(|e| panic!("{}", e));
+    match run(input.as_path(), cli.output.as_path(), cli.jobs, &mut representatives, cli.get_merge_settings(), cli.get_database_settings().as_ref(), &checkpoints) {
         Ok(_) => eprintln!("Done"),
         Err(e) => eprintln!("E: failed to run to conclusion, got the following error: {}", e),
     }
@@ -375,3 +825,39 @@ fn interface() {
 fn main() {
     interface()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::{read_to_string, write};
+    use tempdir::TempDir;
+    use GeneZipLib::checkpoint::Checkpoints;
+    use GeneZipLib::taxonomy::Taxonomy;
+    use crate::{get_ani_calculators, get_genus_cluster_ani_path, get_genus_cluster_list_path, DistanceTool};
+
+    #[test]
+    fn overwrite_recalculates_ani() {
+        let work_dir = TempDir::new("genezip").unwrap();
+        let taxa2order = HashMap::from([(Taxonomy::from("d__Bacteria;p__P;c__C;o__O;f__F;g__G;s__S"), 0)]);
+        let list_path = get_genus_cluster_list_path(work_dir.path(), 0);
+        let ani_path = get_genus_cluster_ani_path(work_dir.path(), 0);
+        write(&list_path, "../tests/presentation_train.fna\n../tests/paper_train.fna\n").unwrap();
+        let tool = DistanceTool::GeneZip { max_depth: 13, buffer_size: 512 };
+
+        // An earlier run of other genomes or another tool
+        Checkpoints::new(work_dir.path(), false, false, "earlier").unwrap();
+        write(&ani_path, "other.fna\tother.fna\t100\t1\n").unwrap();
+
+        let checkpoints = Checkpoints::new(work_dir.path(), false, true, "new").unwrap();
+        assert_eq!(get_ani_calculators(&taxa2order, &checkpoints, work_dir.path(), 1, tool).unwrap().len(), 1);
+        let ani = read_to_string(&ani_path).unwrap();
+        assert_eq!(ani.lines().count(), 4);
+        assert!(!ani.contains("other.fna"));
+
+        // Results of a finished stage are reused when resuming
+        let checkpoints = Checkpoints::new(work_dir.path(), true, false, "new").unwrap();
+        assert!(checkpoints.is_done("genus_0"));
+        assert_eq!(get_ani_calculators(&taxa2order, &checkpoints, work_dir.path(), 1, tool).unwrap().len(), 1);
+        assert_eq!(read_to_string(&ani_path).unwrap(), ani);
+    }
+}
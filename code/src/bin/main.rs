@@ -4,7 +4,7 @@
 
 use std::fs::File;
 use std::io::{BufWriter, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use chrono::Utc;
 use GeneZipLib::ani_calculator_tool::AniCalculatorTool;
@@ -16,11 +16,21 @@ use GeneZipLib::ani_rating::create_ani_run;
 use GeneZipLib::kmer_database::KmerDatabase;
 use GeneZipLib::kmer_prediction::KmerClassifier;
 use GeneZipLib::print_kmer::print_kmers;
-use GeneZipLib::samples_file_reader::{Sample, SampleError, SampleSource};
+use GeneZipLib::samples_file_reader::{is_glob_pattern, is_stdin, merge_repeated_samples, Sample, SampleError, SampleSource};
 use GeneZipLib::database;
-use GeneZipLib::use_classifier::{create_lz_classifier, meta_predict_using_lz_classifier, predict_using_lz_classifier};
-use GeneZipLib::output_streams::{OutputFileType, OutputStreams};
+use GeneZipLib::use_classifier::{create_lz_classifier, create_lz_classifier_from_samples, meta_predict_using_lz_classifier, predict_using_lz_classifier};
+use GeneZipLib::taxonomy_validation::validate_taxonomies;
+use GeneZipLib::evaluation::evaluate;
+use GeneZipLib::scoring::{read_best_hits, read_truth, score};
+use GeneZipLib::simulation::{simulate, write_fragments};
+use GeneZipLib::calibration::calibrate;
+use GeneZipLib::novelty::novelty_profiles;
+use GeneZipLib::genezip_distance::GeneZipScores;
+use GeneZipLib::phylogeny::build_tree;
+use GeneZipLib::profile::{read_classifications, read_weights, Profile};
+use GeneZipLib::output_streams::{OutputFileType, OutputSink, OutputStreams};
 use GeneZipLib::logger::log_event;
+use GeneZipLib::ncbi_taxdump::TaxDump;
 
 
 
@@ -65,7 +75,7 @@ fn kmer_prediction(mut log_stream: Option<&mut BufWriter<Box<dyn Write>>>,
 
     // Open the output stream
     let mut output_stream = {
-        let fout = File::create(output_file).unwrap_or_else(|_| panic!("E: Cannot create output file '{}'", output_file.display()));
+        let fout = OutputSink::new(output_file).unwrap_or_else(|_| panic!("E: Cannot create output file '{}'", output_file.display()));
         BufWriter::new(fout)
     };
 
@@ -75,7 +85,7 @@ fn kmer_prediction(mut log_stream: Option<&mut BufWriter<Box<dyn Write>>>,
 
     for sample in SampleSource::new(prediction_name2file, false) {
         let sample = sample?;
-        let model_name2score = classifier.predict(sample.get_path(), buffer_size);
+        let model_name2score = classifier.predict(sample.get_paths(), buffer_size);
         classifier.print_prediction(sample.get_name(), &mut output_stream, &model_name2score).unwrap_or_else(|_| panic!("E: Failed to write prediction into '{}", output_file.display()));
     }
 
@@ -86,8 +96,40 @@ fn kmer_prediction(mut log_stream: Option<&mut BufWriter<Box<dyn Write>>>,
     Ok(())
 }
 
+// The base output, and the LZ values (as Parquet for a .parquet path), Krona, BIOM and JSON Lines outputs if the user asked for them
+fn create_prediction_output_streams(usage: &Usage, base_output: &Path) -> OutputStreams {
+    let mut output_streams_map: std::collections::HashMap<OutputFileType, &Path> = [(OutputFileType::BaseGz, base_output)].into_iter().collect();
+    if let Some(lz_output_path) = usage.get_gz_values_file() {
+        if lz_output_path.extension().is_some_and(|extension| extension == "parquet") {
+            output_streams_map.insert(OutputFileType::LzParquet, lz_output_path);
+        } else {
+            output_streams_map.insert(OutputFileType::LzValues, lz_output_path);
+        }
+    }
+    let optional_outputs = usage.get_optional_outputs();
+    if let Some(krona_path) = optional_outputs.and_then(|a| a.get_krona()) {
+        output_streams_map.insert(OutputFileType::Krona, krona_path);
+    }
+    if let Some(biom_path) = optional_outputs.and_then(|a| a.get_biom()) {
+        output_streams_map.insert(OutputFileType::Biom, biom_path);
+    }
+    if let Some(jsonl_path) = optional_outputs.and_then(|a| a.get_jsonl()) {
+        output_streams_map.insert(OutputFileType::JsonLines, jsonl_path);
+    }
+    match OutputStreams::new(&output_streams_map) {
+        Err(e) => {
+            panic!("E: failed to create output files, got {}", e);
+        },
+        Ok(os) => match optional_outputs {
+            Some(optional_outputs) => os.with_unit(optional_outputs.get_unit()).with_json_hits(optional_outputs.get_jsonl_hits()),
+            None => os,
+        },
+    }
+}
+
+// stdin and glob patterns are inputs as well, a glob that matches nothing fails when read
 fn is_file_missing(file_path: &Path) -> bool {
-    if file_path.exists() {
+    if file_path.exists() || is_stdin(file_path) || is_glob_pattern(file_path) {
         false
     } else {
         eprintln!("E: The file '{}' is missing", file_path.display());
@@ -95,6 +137,35 @@ fn is_file_missing(file_path: &Path) -> bool {
     }
 }
 
+fn load_taxdump(usage: &Usage) -> Option<Arc<TaxDump>> {
+    usage.get_taxdump().map(|taxdump| match TaxDump::load(taxdump) {
+        Ok(taxdump) => Arc::new(taxdump),
+        Err(e) => panic!("E: Failed to read the NCBI taxdump from '{}', encountered the following error: '{}'", taxdump.display(), e),
+    })
+}
+
+// Loads the training samples, and refuses inconsistent taxonomies unless the user asked for leniency
+fn validate_training_taxonomies(usage: &Usage, training_name2file: &Path) -> Vec<Sample> {
+    let settings = usage.get_validation_settings().expect("E: Trying to validate taxonomies, but the task has no validation settings. This should never happen.");
+    let source = SampleSource::new(training_name2file, usage.get_kmer_size().is_some()).with_taxdump(load_taxdump(usage));
+    let (samples, report) = validate_taxonomies(&source, settings.get_fill_ranks());
+    let written = File::create(settings.get_report()).and_then(|f| report.write(f));
+    if let Err(e) = written {
+        panic!("E: Failed to write the taxonomy report to '{}', encountered the following error: '{}'", settings.get_report().display(), e);
+    }
+
+    eprintln!("Taxonomy validation of '{}': {}", training_name2file.display(), report);
+    if !report.is_consistent() {
+        if settings.get_lenient() {
+            eprintln!("W: Inconsistent taxonomies, see '{}'", settings.get_report().display());
+        } else {
+            panic!("E: Inconsistent taxonomies, see '{}'. Use --fill-ranks to fill missing ranks, or --lenient to build anyway", settings.get_report().display());
+        }
+    }
+
+    samples
+}
+
 fn compute_task(usage: &Usage) {
     let mut log_stream =  if usage.get_print_statistics() {
         Some(BufWriter::new(Box::new(std::io::stderr()) as Box<dyn Write>))
@@ -117,11 +188,19 @@ fn compute_task(usage: &Usage) {
                 let md = usage.get_max_depth().expect("E: Trying to use user-provided max depth, however, the user did not provide max depth. This should never happen.");
                 let database_path = usage.get_database_path().expect("E: Trying to use user-provided database path, however, the user did not provde that information. This should never happen.");
 
-                let classifier = create_lz_classifier(log_stream.as_mut(),
-                                                      md,
-                                                      training_name2file,
-                                                      usage.get_buffer_size(),
-                                                      &usage.get_kmer_size());
+                let samples = validate_training_taxonomies(usage, training_name2file);
+                let mut classifier = create_lz_classifier_from_samples(log_stream.as_mut(),
+                                                                       md,
+                                                                       &samples,
+                                                                       usage.get_buffer_size(),
+                                                                       &usage.get_kmer_size());
+                if usage.get_novelty() {
+                    log_event(&mut log_stream.as_mut(), "Scoring the models on the other references for novelty detection");
+                    match novelty_profiles(&classifier, usage.get_buffer_size()) {
+                        Ok(model2novelty) => classifier.set_model2novelty(model2novelty),
+                        Err(e) => panic!("{}", e),
+                    }
+                }
 
                 let database = database::Database::new(classifier, md, usage.get_kmer_size());
                 if let Err(e) = database.save(database_path, usage.get_jobs().unwrap_or(0)) {
@@ -138,18 +217,7 @@ fn compute_task(usage: &Usage) {
                     Err(e) => panic!("E: Failed to read GeneZip database from '{}', encountered the following error: '{}'", database_path.display(), e),
                 };
                 let base_output = usage.get_out_file().expect("E: Trying to open the output file, but no path was provided by user. This should never happen.");
-                let mut output_streams = {
-                    let mut output_streams_map: std::collections::HashMap<OutputFileType, &Path> = [(OutputFileType::BaseGz, base_output)].into_iter().collect();
-                    if let Some(lz_output_path) = usage.get_gz_values_file() {
-                        output_streams_map.insert(OutputFileType::LzValues, lz_output_path);
-                    }
-                    match OutputStreams::new(&output_streams_map) {
-                        Err(e) => {
-                            panic!("E: failed to create output files, got {}", e);
-                        },
-                        Ok(os) => os,
-                    }
-                };
+                let mut output_streams = create_prediction_output_streams(usage, base_output);
                 if let Err(e) = predict_using_lz_classifier(log_stream.as_mut(),
                                                             usage.get_buffer_size(),
                                                             database.get_kmer_size(),
@@ -179,18 +247,7 @@ fn compute_task(usage: &Usage) {
         UserTask::Predict => {
             let md = usage.get_max_depth().expect("E: Trying to use user-provided max depth, however, the user did not provide max depth. This should never happen.");
             let gz_output_path = usage.get_out_file().expect("E: Trying to get the output file, but no path was provided by user. This should never happen.");
-            let mut output_streams = {
-                let mut output_streams_map: std::collections::HashMap<OutputFileType, &Path> = [(OutputFileType::BaseGz, gz_output_path)].into_iter().collect();
-                if let Some(lz_output_path) = usage.get_gz_values_file() {
-                    output_streams_map.insert(OutputFileType::LzValues, lz_output_path);
-                }
-                match OutputStreams::new(&output_streams_map) {
-                    Err(e) => {
-                        panic!("E: failed to create output files, got {}", e);
-                    },
-                    Ok(os) => os,
-                }
-            };
+            let mut output_streams = create_prediction_output_streams(usage, gz_output_path);
             let prediction_name2file = usage.get_prediction_name2file_file().expect("E: Trying to get prediction input path, however, the user was not asked to provide that. This should never happen.");
             let training_name2file = usage.get_training_name2file_file().expect("E: Trying to read path to training file provided by the user, however, the user did not provide that. This should never happen");
 
@@ -199,7 +256,8 @@ fn compute_task(usage: &Usage) {
                                                       md,
                                                       training_name2file,
                                                       usage.get_buffer_size(),
-                                                      &usage.get_kmer_size()));
+                                                      &usage.get_kmer_size(),
+                                                      load_taxdump(usage)));
                 if let Err(e) = predict_using_lz_classifier(log_stream.as_mut(),
                                             usage.get_buffer_size(),
                                             &usage.get_kmer_size(),
@@ -269,26 +327,15 @@ fn compute_task(usage: &Usage) {
         UserTask::MetaPredict => {
             let training_name2file = usage.get_training_name2file_file().expect("E: Trying to read path to sequences file provided by the user, however, the user did not provide that. This should never happen");
             let prediction_name2file = usage.get_prediction_name2file_file().expect("E: Trying to get prediction input path, however, the user was not asked to provide that. This should never happen.");
-            let mut output_streams = {
-                let gz_output_path = usage.get_out_file().expect("E: Trying to get the output file, but no path was provided by user. This should never happen.");
-                let mut output_streams_map: std::collections::HashMap<OutputFileType, &Path> = [(OutputFileType::BaseGz, gz_output_path)].into_iter().collect();
-                if let Some(lz_output_path) = usage.get_gz_values_file() {
-                    output_streams_map.insert(OutputFileType::LzValues, lz_output_path);
-                }
-                match OutputStreams::new(&output_streams_map) {
-                    Err(e) => {
-                        panic!("E: failed to create output files, got {}", e);
-                    },
-                    Ok(os) => os,
-                }
-            };
+            let mut output_streams = create_prediction_output_streams(usage, usage.get_out_file().expect("E: Trying to get the output file, but no path was provided by user. This should never happen."));
             if ! is_file_missing(training_name2file) && ! is_file_missing(prediction_name2file) {
                 let md = usage.get_max_depth().expect("E: Trying to use user-provided max depth, however, the user did not provide max depth. This should never happen.");
                 let classifier = create_lz_classifier(log_stream.as_mut(),
                                                       md,
                                                       training_name2file,
                                                       usage.get_buffer_size(),
-                                                      &usage.get_kmer_size());
+                                                      &usage.get_kmer_size(),
+                                                      load_taxdump(usage));
                 if let Err(e) = meta_predict_using_lz_classifier(log_stream.as_mut(),
                                                                             usage.get_buffer_size(),
                                                                             &classifier,
@@ -301,6 +348,150 @@ fn compute_task(usage: &Usage) {
                 }
             }
         },
+        UserTask::Evaluate => {
+            let training_name2file = usage.get_training_name2file_file().expect("E: Trying to read path to training file provided by the user, however, the user did not provide that. This should never happen");
+            let output_prefix = usage.get_out_file().expect("E: Trying to get the output file, but no path was provided by user. This should never happen.");
+            if ! is_file_missing(training_name2file) {
+                let parameters = usage.get_evaluation_parameters().expect("E: Trying to get the evaluation parameters, however, the user did not provide them. This should never happen.");
+                let samples = match SampleSource::new(training_name2file, true).with_taxdump(load_taxdump(usage)).into_iter().collect::<Result<Vec<Sample>, SampleError>>() {
+                    Ok(samples) => samples,
+                    Err(e) => panic!("{}", e),
+                };
+                log_event(&mut log_stream.as_mut(), "Evaluating");
+                match evaluate(&samples, &parameters) {
+                    Ok(report) => {
+                        if let Err(e) = report.write(output_prefix) {
+                            panic!("E: Failed to write the evaluation report to '{}', encountered the following error: '{}'", output_prefix.display(), e);
+                        }
+                        eprint!("{}", report);
+                    },
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+        },
+        UserTask::Score => {
+            let settings = usage.get_score_settings().expect("E: Trying to get the scoring settings, however, the user did not provide them. This should never happen.");
+            let database_path = usage.get_database_path().expect("E: Trying to use user-provided database path, however, the user did not provde that information. This should never happen.");
+            let out_file = usage.get_out_file().expect("E: Trying to get the output file, but no path was provided by user. This should never happen.");
+            if ! is_file_missing(database_path) && ! is_file_missing(settings.get_predictions()) && ! is_file_missing(settings.get_truth()) {
+                let database = match database::Database::load(database_path, usage.get_jobs().unwrap_or(0)) {
+                    Ok(db) => db,
+                    Err(e) => panic!("E: Failed to read GeneZip database from '{}', encountered the following error: '{}'", database_path.display(), e),
+                };
+                let best_hits = read_best_hits(settings.get_predictions()).unwrap_or_else(|e| panic!("{}", e));
+                let truth = read_truth(settings.get_truth()).unwrap_or_else(|e| panic!("{}", e));
+                match score(&best_hits, &database.get_classifier().collect_model2taxonomy(), &truth, &settings.get_parameters()) {
+                    Ok(report) => {
+                        if let Err(e) = report.write(out_file) {
+                            panic!("E: Failed to write the scores to '{}', encountered the following error: '{}'", out_file.display(), e);
+                        }
+                        eprint!("{}", report);
+                    },
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+        },
+        UserTask::Simulate => {
+            let settings = usage.get_simulation_settings().expect("E: Trying to get the simulation settings, however, the user did not provide them. This should never happen.");
+            let output_prefix = usage.get_out_file().expect("E: Trying to get the output file, but no path was provided by user. This should never happen.");
+            if ! is_file_missing(settings.get_input()) {
+                let samples = match SampleSource::new(settings.get_input(), true).into_iter().collect::<Result<Vec<Sample>, SampleError>>()
+                    .and_then(|samples| merge_repeated_samples(&samples)) {
+                    Ok(samples) => samples,
+                    Err(e) => panic!("{}", e),
+                };
+                log_event(&mut log_stream.as_mut(), "Simulating fragments");
+                match simulate(&samples, &settings.get_parameters(usage.get_buffer_size())) {
+                    Ok(fragments) => match write_fragments(&samples, &fragments, output_prefix) {
+                        Ok(summary) => eprint!("{}", summary),
+                        Err(e) => panic!("E: Failed to write the fragments to '{}', encountered the following error: '{}'", output_prefix.display(), e),
+                    },
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+        },
+        UserTask::Calibrate => {
+            let database_path = usage.get_database_path().expect("E: Trying to use user-provided database path, however, the user did not provde that information. This should never happen.");
+            let calibration_name2file = usage.get_calibration_name2file_file().expect("E: Trying to get the calibration input path, however, the user was not asked to provide that. This should never happen.");
+            let out_file = usage.get_out_file().expect("E: Trying to get the output file, but no path was provided by user. This should never happen.");
+            if ! is_file_missing(database_path) && ! is_file_missing(calibration_name2file) {
+                let mut database = match database::Database::load(database_path, usage.get_jobs().unwrap_or(0)) {
+                    Ok(db) => db,
+                    Err(e) => panic!("E: Failed to read GeneZip database from '{}', encountered the following error: '{}'", database_path.display(), e),
+                };
+                let samples = match SampleSource::new(calibration_name2file, true).into_iter().collect::<Result<Vec<Sample>, SampleError>>() {
+                    Ok(samples) => samples,
+                    Err(e) => panic!("{}", e),
+                };
+                log_event(&mut log_stream.as_mut(), "Calibrating");
+                match calibrate(database.get_classifier(), &samples, usage.get_gc_limit(), database.get_kmer_size(), usage.get_buffer_size()) {
+                    Ok(calibration) => {
+                        eprint!("{}", calibration);
+                        database.set_calibration(calibration);
+                        if let Err(e) = database.save(out_file, usage.get_jobs().unwrap_or(0)) {
+                            panic!("E: Failed to save GeneZip database to '{}', encountered the following error: '{}'", out_file.display(), e)
+                        }
+                    },
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+        },
+        UserTask::Distances => {
+            let settings = usage.get_distance_settings().expect("E: Trying to get the distance settings, however, the user did not provide them. This should never happen.");
+            let input = usage.get_training_name2file_file().expect("E: Trying to read path to training file provided by the user, however, the user did not provide that. This should never happen");
+            let out_file = usage.get_out_file().expect("E: Trying to get the output file, but no path was provided by user. This should never happen.");
+            let md = usage.get_max_depth().expect("E: Trying to use user-provided max depth, however, the user did not provide max depth. This should never happen.");
+            if ! is_file_missing(input) {
+                // A genome repeating its name over several lines is a single genome of all its FASTA files
+                let samples = match SampleSource::new(input, !settings.get_taxa().is_empty() || settings.get_lca_labels()).into_iter().collect::<Result<Vec<Sample>, SampleError>>()
+                    .and_then(|samples| merge_repeated_samples(&samples)) {
+                    Ok(samples) => samples,
+                    Err(e) => panic!("{}", e),
+                };
+                let samples = samples.into_iter()
+                    .filter(|s| settings.get_taxa().is_empty() || s.get_taxonomy().as_ref().is_some_and(|t| settings.get_taxa().iter().any(|clade| t.is_within(clade))))
+                    .collect::<Vec<Sample>>();
+                if samples.is_empty() {
+                    panic!("E: No genome of '{}' is within the requested taxa", input.display());
+                }
+                log_event(&mut log_stream.as_mut(), &format!("Computing GeneZip scores between {} genomes", samples.len()));
+                let genomes = samples.iter().map(|s| s.get_paths().to_vec()).collect::<Vec<Vec<PathBuf>>>();
+                let names = samples.iter().map(|s| s.get_name().to_string()).collect::<Vec<String>>();
+                let scores = GeneZipScores::new(&genomes, md, usage.get_buffer_size());
+                let written = OutputSink::new(out_file).and_then(|f| scores.write_matrix(&names, settings.get_mode(), settings.get_format(), f));
+                if let Err(e) = written {
+                    panic!("E: Failed to write the GeneZip scores to '{}', encountered the following error: '{}'", out_file.display(), e);
+                }
+                if let Some(newick) = settings.get_newick() {
+                    log_event(&mut log_stream.as_mut(), &format!("Building a tree using {}", settings.get_tree_method()));
+                    let tree = build_tree(&scores.distances(), settings.get_tree_method());
+                    let taxonomies = samples.iter().map(|s| s.get_taxonomy().clone()).collect::<Vec<_>>();
+                    let tree = tree.to_newick(&names, if settings.get_lca_labels() { Some(&taxonomies) } else { None });
+                    if let Err(e) = std::fs::write(newick, format!("{}\n", tree)) {
+                        panic!("E: Failed to write the tree to '{}', encountered the following error: '{}'", newick.display(), e);
+                    }
+                }
+            }
+        },
+        UserTask::Profile => {
+            let settings = usage.get_profile_settings().expect("E: Trying to get the profile settings, however, the user did not provide them. This should never happen.");
+            let database_path = usage.get_database_path().expect("E: Trying to use user-provided database path, however, the user did not provde that information. This should never happen.");
+            let output_prefix = usage.get_out_file().expect("E: Trying to get the output file, but no path was provided by user. This should never happen.");
+            if ! is_file_missing(database_path) && ! is_file_missing(settings.get_predictions()) && ! settings.get_weights().is_some_and(is_file_missing) {
+                let database = match database::Database::load(database_path, usage.get_jobs().unwrap_or(0)) {
+                    Ok(db) => db,
+                    Err(e) => panic!("E: Failed to read GeneZip database from '{}', encountered the following error: '{}'", database_path.display(), e),
+                };
+                let classifications = read_classifications(settings.get_predictions()).unwrap_or_else(|e| panic!("{}", e));
+                let weights = settings.get_weights().map(|weights| read_weights(weights).unwrap_or_else(|e| panic!("{}", e)));
+                let profile = Profile::new(settings.get_sample_id(), &classifications, &database.get_classifier().collect_model2taxonomy(), weights.as_ref(), settings.get_unit())
+                    .unwrap_or_else(|e| panic!("{}", e));
+                if let Err(e) = profile.write(output_prefix) {
+                    panic!("E: Failed to write the profile to '{}', encountered the following error: '{}'", output_prefix.display(), e);
+                }
+                eprint!("{}", profile);
+            }
+        },
     }
 
     if let Some(mut log_stream) = log_stream {
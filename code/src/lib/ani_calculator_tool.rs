@@ -1,7 +1,7 @@
 use std::fmt::{Display, Formatter};
 use clap::ValueEnum;
 
-#[derive(Copy, Clone, ValueEnum)]
+#[derive(Copy, Clone, Debug, ValueEnum)]
 pub enum AniCalculatorTool {
     FastANI,
     Skani,
@@ -12,7 +12,8 @@ use std::process::{Command, ExitStatus};
 use rayon::prelude::*;
 use crate::ani_calculator_tool::AniCalculatorTool;
 use crate::samples_file_reader;
-use crate::samples_file_reader::{Sample, SampleError, SampleErrorType};
+use crate::samples_file_reader::{merge_repeated_samples, Sample, SampleError, SampleErrorType};
+use crate::output_streams::OutputSink;
 
 fn get_reference2queries(gene_zip_best_hit_table: &Path) -> HashMap<String, Vec<String>> {
     let file = File::open(gene_zip_best_hit_table).expect("ERROR: failed to open GeneZip best hit table for reading, quitting");
@@ -211,16 +212,26 @@ fn transform_path2ani_into_name2ani(path2ani: &HashMap<String, f64>, name2path:
 pub fn create_ani_run<I>(genezip_output_table: &Path, output_path: &Path, testing_path: &Path, traning_path: I, ani_calculator_tool: AniCalculatorTool) -> Result<(), SampleError> where
     I: IntoIterator<Item=Result<Sample, SampleError>> {
     let reference2queries = get_reference2queries(genezip_output_table);
-    let training_name2path = traning_path.into_iter()
-        .map(|sample| sample.map (|sample| (sample.get_name().to_string(), sample.get_path().display().to_string())))
-        .collect::<Result<HashMap<String, String>, SampleError>>()?;
+    // A model trained on several FASTA files gets the best ANI of any of them
+    let training_samples = traning_path.into_iter().collect::<Result<Vec<Sample>, SampleError>>()?;
+    let training_name2paths = merge_repeated_samples(&training_samples)?
+        .into_iter()
+        .map(|sample| (sample.get_name().to_string(), sample.get_paths().iter().map(|path| path.display().to_string()).collect::<Vec<String>>()))
+        .collect::<HashMap<String, Vec<String>>>();
     
-    let testing_name2path = samples_file_reader::SampleSource::new(testing_path, false)
+    // The ANI tools compare a single query FASTA, a query of several files (or repeating its name) can't be rated
+    let testing_samples = samples_file_reader::SampleSource::new(testing_path, false)
         .into_iter()
-        .map(|sample| sample.map (|sample| (sample.get_name().to_string(), sample.get_path().display().to_string())))
+        .collect::<Result<Vec<Sample>, SampleError>>()?;
+    let testing_name2path = merge_repeated_samples(&testing_samples)?
+        .into_iter()
+        .map(|sample| match sample.get_paths() {
+            [path] => Ok((sample.get_name().to_string(), path.display().to_string())),
+            _ => Err(SampleError::new(&format!("E: Query '{}' has several FASTA files, ANI can only be calculated for a query of a single FASTA file", sample.get_name()), SampleErrorType::None)),
+        })
         .collect::<Result<HashMap<String, String>, SampleError>>()?;
 
-    let output_file = match File::create(output_path) {
+    let output_file = match OutputSink::new(output_path) {
         Ok(f) => Ok(f),
         Err(e) => Err(SampleError::new(&format!("Failed to create the output file {}, quitting", output_path.display()), SampleErrorType::IoError(e))),
     }?;
@@ -231,9 +242,17 @@ pub fn create_ani_run<I>(genezip_output_table: &Path, output_path: &Path, testin
     let name_ref_2_ani= reference2queries.into_iter()
         .par_bridge()
         .map(|(reference, query)| {
-            let path2ani = run_ani_collection(training_name2path.get(&reference).expect("ERROR: A model name found with no path"),
-                                              query.iter().map(|name| testing_name2path[name].clone()),
-                                              1, ani_calculator_tool);
+            let path2ani = training_name2paths.get(&reference).expect("ERROR: A model name found with no path").iter()
+                .map(|reference_genome| run_ani_collection(reference_genome,
+                                                           query.iter().map(|name| testing_name2path[name].clone()),
+                                                           1, ani_calculator_tool))
+                .try_fold(HashMap::new(), |mut best: HashMap<String, f64>, path2ani| {
+                    for (path, ani) in path2ani? {
+                        let best_ani = best.entry(path).or_insert(ani);
+                        *best_ani = best_ani.max(ani);
+                    }
+                    Ok(best)
+                });
 
             let name2ani = match path2ani {
                 Ok(path2ani) => Ok(transform_path2ani_into_name2ani(&path2ani, &testing_name2path)),
@@ -1,21 +1,55 @@
 use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
-use crate::fasta_nucleutide_iterator::FastaNucltudiesIterator;
+use std::sync::Arc;
+use crate::fasta_chunk_reader::{FastaChunk, FastaChunkReader};
+use crate::fasta_nucleutide_iterator::{FastaNucltudiesIterator, MultiFastaNucltudiesIterator};
 
+// Clones share the buffer, as a sequence is scored by every model
 #[derive(Clone)]
 pub struct CachedFastaNucltudiesIterator {
-    buffer: Vec<u8>,
+    buffer: Arc<[u8]>,
     buffer_index: usize,
-    source: PathBuf,
+    // Every FASTA file of the sequences, for messages
+    sources: Vec<PathBuf>,
 }
 
 impl From<FastaNucltudiesIterator> for CachedFastaNucltudiesIterator {
     fn from(value: FastaNucltudiesIterator) -> Self {
-        let source = value.get_path().to_path_buf();
+        let sources = vec![value.get_path().to_path_buf()];
         Self {
-            buffer: value.collect::<Vec<u8>>(),
+            buffer: value.collect::<Vec<u8>>().into(),
             buffer_index: 0,
-            source,
+            sources,
+        }
+    }
+}
+
+impl From<MultiFastaNucltudiesIterator> for CachedFastaNucltudiesIterator {
+    fn from(value: MultiFastaNucltudiesIterator) -> Self {
+        let sources = value.get_paths().to_vec();
+        Self {
+            buffer: value.collect::<Vec<u8>>().into(),
+            buffer_index: 0,
+            sources,
+        }
+    }
+}
+
+impl CachedFastaNucltudiesIterator {
+    // The sequences of a sample, which may have several FASTA files, read in chunks with an 'N' between records
+    pub fn from_paths(fasta_paths: &[PathBuf], buffer_size: usize) -> Self {
+        let mut buffer = Vec::new();
+        let mut fasta = FastaChunkReader::from_paths(fasta_paths, buffer_size);
+        while let Some(chunk) = fasta.next_chunk() {
+            match chunk {
+                FastaChunk::Record(_) => buffer.push(b'N'),
+                FastaChunk::Sequence(sequence) => buffer.extend_from_slice(sequence),
+            }
+        }
+        Self {
+            buffer: buffer.into(),
+            buffer_index: 0,
+            sources: fasta_paths.to_vec(),
         }
     }
 }
@@ -35,7 +69,21 @@ impl Iterator for CachedFastaNucltudiesIterator {
 
 impl Display for CachedFastaNucltudiesIterator {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.source.display())
+        write!(f, "{}", self.sources.iter().map(|p| p.display().to_string()).collect::<Vec<String>>().join(","))
     }
 } 
 
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use crate::cached_fasta_nucleutide_iterator::CachedFastaNucltudiesIterator;
+    use crate::fasta_nucleutide_iterator::{FastaNucltudiesIterator, MultiFastaNucltudiesIterator};
+
+    #[test]
+    fn chunks_match_nucleotides() {
+        let paths = [PathBuf::from("../tests/presentation_test_two_seq.fna"), PathBuf::from("../data/HMP_2012__SRS019219__bin.33_genes.fna.gz")];
+        assert_eq!(CachedFastaNucltudiesIterator::from_paths(&paths[..1], 7).collect::<Vec<u8>>(), FastaNucltudiesIterator::new(&paths[0], 1024).collect::<Vec<u8>>());
+        assert_eq!(CachedFastaNucltudiesIterator::from_paths(&paths, 7).collect::<Vec<u8>>(), MultiFastaNucltudiesIterator::new(&paths, 1024).collect::<Vec<u8>>());
+        assert_eq!(CachedFastaNucltudiesIterator::from_paths(&paths, 7).to_string(), MultiFastaNucltudiesIterator::new(&paths, 1024).to_string());
+    }
+}
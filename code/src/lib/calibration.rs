@@ -0,0 +1,266 @@
+// Calibration of GeneZip scores into the probability that the best hit is correct at each rank.
+// Fitted on a labeled held-out set as a logistic regression on the margin of the best hit over the runner-up and the log of the query length.
+
+use std::fmt::{Display, Formatter};
+use std::io::{Error, ErrorKind};
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use crate::cached_fasta_nucleutide_iterator::CachedFastaNucltudiesIterator;
+use crate::classifier::Classifier;
+use crate::samples_file_reader::Sample;
+use crate::taxonomy::{TaxonomicRank, Taxonomy};
+
+// L2 penalty of the (standardized) slopes, as scikit-learn's default
+const RIDGE: f64 = 1.0;
+const MAX_ITERATIONS: usize = 100;
+const TOLERANCE: f64 = 1e-10;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RankCalibration {
+    rank: TaxonomicRank,
+    // Intercept, margin and log length slopes, on the standardized features
+    coefficients: [f64; 3],
+    samples: usize,
+    correct: usize,
+    // Mean squared error of the fitted probabilities on the calibration set
+    brier: f64,
+}
+
+impl RankCalibration {
+    pub fn get_rank(&self) -> TaxonomicRank { self.rank }
+    pub fn get_samples(&self) -> usize { self.samples }
+    pub fn get_correct(&self) -> usize { self.correct }
+    pub fn get_brier(&self) -> f64 { self.brier }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Calibration {
+    means: [f64; 2],
+    sds: [f64; 2],
+    // Margin of queries with a single scored model, the largest margin of the calibration set
+    max_margin: f64,
+    ranks: Vec<RankCalibration>,
+}
+
+// A calibration query: the margin and length of its prediction, the taxonomy of its best hit and its true taxonomy
+pub struct Observation<'a> {
+    pub margin: Option<f64>,
+    pub length: usize,
+    pub predicted: Option<&'a Taxonomy>,
+    pub truth: &'a Taxonomy,
+}
+
+/*
+The margin of the best (lowest) score over the runner-up, None if a single model was scored.
+None altogether if no model was scored.
+ */
+pub fn prediction_margin(prediction: &[(&String, Option<f64>)]) -> Option<Option<f64>> {
+    let mut scores = prediction.iter().filter_map(|(_, score)| *score).collect::<Vec<f64>>();
+    scores.sort_by(f64::total_cmp);
+    match scores.as_slice() {
+        [] => None,
+        [_] => Some(None),
+        [best, runner_up, ..] => Some(Some(runner_up - best)),
+    }
+}
+
+fn sigmoid(value: f64) -> f64 {
+    1.0 / (1.0 + (-value).exp())
+}
+
+fn solve3(mut a: [[f64; 3]; 3], mut b: [f64; 3]) -> Option<[f64; 3]> {
+    for column in 0..3 {
+        let pivot = (column..3).max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))?;
+        if a[pivot][column].abs() < f64::EPSILON {
+            return None;
+        }
+        a.swap(column, pivot);
+        b.swap(column, pivot);
+        for row in column + 1..3 {
+            let pivot_row = a[column];
+            let factor = a[row][column] / pivot_row[column];
+            for (value, pivot_value) in a[row].iter_mut().zip(pivot_row).skip(column) {
+                *value -= factor * pivot_value;
+            }
+            b[row] -= factor * b[column];
+        }
+    }
+    let mut x = [0.0; 3];
+    for row in (0..3).rev() {
+        x[row] = (b[row] - (row + 1..3).map(|k| a[row][k] * x[k]).sum::<f64>()) / a[row][row];
+    }
+    Some(x)
+}
+
+/*
+Ridge-penalized logistic regression by Newton's method, the first feature is the intercept and isn't penalized.
+The labels are smoothed to Platt's targets, (positives + 1) / (positives + 2) and 1 / (negatives + 2),
+so a rank that is always right on a small set doesn't get a certain confidence.
+ */
+fn fit_logistic(features: &[[f64; 3]], labels: &[bool]) -> [f64; 3] {
+    let positives = labels.iter().filter(|&&y| y).count() as f64;
+    let negatives = labels.len() as f64 - positives;
+    let (positive_target, negative_target) = ((positives + 1.0) / (positives + 2.0), 1.0 / (negatives + 2.0));
+    let mut weights = [0.0; 3];
+    for _ in 0..MAX_ITERATIONS {
+        let mut gradient = [0.0, RIDGE * weights[1], RIDGE * weights[2]];
+        let mut hessian = [[0.0; 3], [0.0, RIDGE, 0.0], [0.0, 0.0, RIDGE]];
+        for (x, &y) in features.iter().zip(labels) {
+            let p = sigmoid((0..3).map(|i| weights[i] * x[i]).sum());
+            for i in 0..3 {
+                gradient[i] += (p - if y { positive_target } else { negative_target }) * x[i];
+                for j in 0..3 {
+                    hessian[i][j] += p * (1.0 - p) * x[i] * x[j];
+                }
+            }
+        }
+        let Some(step) = solve3(hessian, gradient) else { break };
+        for i in 0..3 {
+            weights[i] -= step[i];
+        }
+        if step.iter().map(|s| s * s).sum::<f64>() < TOLERANCE {
+            break;
+        }
+    }
+    weights
+}
+
+fn mean_sd(values: impl Iterator<Item=f64> + Clone) -> (f64, f64) {
+    let n = values.clone().count().max(1) as f64;
+    let mean = values.clone().sum::<f64>() / n;
+    let sd = (values.map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt();
+    (mean, if sd > 0.0 { sd } else { 1.0 })
+}
+
+impl Calibration {
+    /*
+    Fits a regression per rank, on the observations whose truth names the rank.
+    Observations without a best hit get no confidence when predicting, and are left out.
+     */
+    pub fn fit(observations: &[Observation]) -> std::io::Result<Self> {
+        let observations = observations.iter().filter(|o| o.predicted.is_some()).collect::<Vec<&Observation>>();
+        if observations.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "E: no calibration sample has a best hit, can't calibrate"));
+        }
+        let max_margin = observations.iter().filter_map(|o| o.margin).fold(0.0, f64::max);
+        let raw = observations.iter()
+            .map(|o| [o.margin.unwrap_or(max_margin), (o.length.max(1) as f64).ln()])
+            .collect::<Vec<[f64; 2]>>();
+        let (margin_mean, margin_sd) = mean_sd(raw.iter().map(|r| r[0]));
+        let (length_mean, length_sd) = mean_sd(raw.iter().map(|r| r[1]));
+        let mut calibration = Calibration { means: [margin_mean, length_mean], sds: [margin_sd, length_sd], max_margin, ranks: Vec::new() };
+
+        for rank in TaxonomicRank::down_iterator() {
+            let (features, labels): (Vec<[f64; 3]>, Vec<bool>) = observations.iter()
+                .zip(&raw)
+                .filter(|(o, _)| o.truth.has_rank(&rank))
+                .map(|(o, r)| {
+                    let correct = o.predicted.is_some_and(|p| p.has_rank(&rank) && p.equal_to_rank(o.truth, &rank));
+                    (calibration.standardize(r[0], r[1]), correct)
+                })
+                .unzip();
+            if features.is_empty() {
+                continue;
+            }
+            let coefficients = fit_logistic(&features, &labels);
+            let brier = features.iter().zip(&labels)
+                .map(|(x, &y)| (sigmoid((0..3).map(|i| coefficients[i] * x[i]).sum()) - y as u8 as f64).powi(2))
+                .sum::<f64>() / features.len() as f64;
+            calibration.ranks.push(RankCalibration {
+                rank,
+                coefficients,
+                samples: features.len(),
+                correct: labels.iter().filter(|&&y| y).count(),
+                brier,
+            });
+        }
+        Ok(calibration)
+    }
+
+    fn standardize(&self, margin: f64, log_length: f64) -> [f64; 3] {
+        [1.0, (margin - self.means[0]) / self.sds[0], (log_length - self.means[1]) / self.sds[1]]
+    }
+
+    pub fn get_ranks(&self) -> &[RankCalibration] { &self.ranks }
+
+    // Probability that the best hit is correct at each calibrated rank, from the domain down
+    pub fn confidence(&self, margin: Option<f64>, length: usize) -> Vec<(TaxonomicRank, f64)> {
+        let x = self.standardize(margin.unwrap_or(self.max_margin).min(self.max_margin), (length.max(1) as f64).ln());
+        self.ranks.iter()
+            .map(|r| (r.rank, sigmoid((0..3).map(|i| r.coefficients[i] * x[i]).sum())))
+            .collect()
+    }
+}
+
+impl Display for Calibration {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "rank\tsamples\tcorrect\tbrier")?;
+        for r in &self.ranks {
+            writeln!(f, "{}\t{}\t{}\t{:.4}", r.rank, r.samples, r.correct, r.brier)?;
+        }
+        Ok(())
+    }
+}
+
+// Predicts the held-out samples with the classifier and fits the calibration on their best hits
+pub fn calibrate(classifier: &Classifier, samples: &[Sample], gc_limit: Option<f64>, kmer_size: &Option<usize>, buffer_size: usize) -> std::io::Result<Calibration> {
+    if let Some(sample) = samples.iter().find(|s| s.get_taxonomy().is_none()) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("E: sample '{}' has no taxonomy, calibration needs the taxonomy of every sample", sample.get_name())));
+    }
+    let model2taxonomy = classifier.collect_model2taxonomy();
+    let predictions = samples.par_iter()
+        .map(|sample| {
+            let sequence = CachedFastaNucltudiesIterator::from_paths(sample.get_paths(), buffer_size);
+            let (prediction, length) = classifier.predict(sequence, gc_limit, kmer_size, false);
+            let best_hit = Classifier::get_best_model_name(&prediction).cloned();
+            (best_hit, prediction_margin(&prediction).flatten(), length)
+        })
+        .collect::<Vec<_>>();
+
+    let observations = samples.iter().zip(&predictions)
+        .filter_map(|(sample, (best_hit, margin, length))| sample.get_taxonomy().as_ref().map(|truth| Observation {
+            margin: *margin,
+            length: *length,
+            predicted: best_hit.as_ref().and_then(|name| model2taxonomy.get(name)),
+            truth,
+        }))
+        .collect::<Vec<Observation>>();
+    Calibration::fit(&observations)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::calibration::{prediction_margin, Calibration, Observation};
+    use crate::taxonomy::{TaxonomicRank, Taxonomy};
+
+    #[test]
+    fn margin_calibration() {
+        let truth = Taxonomy::parse("d__Bacteria;p__Firmicutes;c__;o__;f__;g__;s__").unwrap();
+        let wrong = Taxonomy::parse("d__Bacteria;p__Proteobacteria;c__;o__;f__;g__;s__").unwrap();
+        // Right at the phylum when the margin is wide, mostly wrong when it is narrow
+        let observations = (0..40)
+            .map(|i| Observation {
+                margin: Some(i as f64 / 100.0),
+                length: 1000 + 10 * i,
+                predicted: Some(if i >= 20 || i % 5 == 0 { &truth } else { &wrong }),
+                truth: &truth,
+            })
+            .collect::<Vec<Observation>>();
+        let calibration = Calibration::fit(&observations).unwrap();
+
+        assert_eq!(calibration.get_ranks().iter().map(|r| r.get_rank()).collect::<Vec<_>>(), vec![TaxonomicRank::Domain, TaxonomicRank::Phylum]);
+        assert_eq!((calibration.get_ranks()[1].get_samples(), calibration.get_ranks()[1].get_correct()), (40, 24));
+        let narrow = calibration.confidence(Some(0.01), 1000);
+        let wide = calibration.confidence(Some(0.35), 1300);
+        assert!(narrow[1].1 < 0.5 && wide[1].1 > 0.5);
+        // Always right at the domain
+        assert!(narrow[0].1 > 0.9);
+        // A single scored model is as sure as the widest margin
+        assert_eq!(calibration.confidence(None, 1300), calibration.confidence(Some(10.0), 1300));
+
+        let (m1, m2) = (String::from("m1"), String::from("m2"));
+        assert_eq!(prediction_margin(&[(&m1, Some(1.5)), (&m2, Some(1.25))]), Some(Some(0.25)));
+        assert_eq!(prediction_margin(&[(&m1, Some(1.5)), (&m2, None)]), Some(None));
+        assert_eq!(prediction_margin(&[(&m1, None)]), None);
+    }
+}
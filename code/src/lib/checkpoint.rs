@@ -0,0 +1,136 @@
+// Completion markers for long runs made of stages, so an interrupted run can be resumed.
+// A marker lists the outputs of its stage with their sizes, a stage is only skipped if all of them are intact.
+// The run parameters are recorded as well, resuming with different parameters is refused.
+// A new run refuses a folder of an earlier run, unless told to overwrite it.
+
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_file, rename, File};
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+const CHECKPOINTS_FOLDER: &str = "checkpoints";
+const PARAMETERS_FILE: &str = "parameters";
+
+pub struct Checkpoints {
+    folder: PathBuf,
+    resume: bool,
+    overwrite: bool,
+}
+
+impl Checkpoints {
+    pub fn new(work_folder: &Path, resume: bool, overwrite: bool, parameters: &str) -> std::io::Result<Self> {
+        let folder = work_folder.join(CHECKPOINTS_FOLDER);
+        create_dir_all(&folder)?;
+
+        // Markers of the overwritten run would let a later resume skip stages it never finished
+        if overwrite {
+            for entry in read_dir(&folder)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|extension| extension == "done") {
+                    remove_file(path)?;
+                }
+            }
+        }
+
+        let parameters_path = folder.join(PARAMETERS_FILE);
+        if resume && parameters_path.exists() {
+            let recorded = read_to_string(&parameters_path)?;
+            if recorded != parameters {
+                return Err(Error::new(ErrorKind::InvalidInput,
+                                      format!("E: can't resume, '{}' was created with different parameters:\n{}", work_folder.display(), recorded)));
+            }
+        } else {
+            let mut stream = match if resume || overwrite { File::create(&parameters_path) } else { File::create_new(&parameters_path) } {
+                Err(e) if e.kind() == ErrorKind::AlreadyExists =>
+                    return Err(Error::new(ErrorKind::AlreadyExists,
+                                          format!("E: '{}' holds an earlier run, resume it, overwrite it or use a new folder", work_folder.display()))),
+                stream => stream?,
+            };
+            write!(stream, "{}", parameters)?;
+        }
+
+        Ok(Self { folder, resume, overwrite })
+    }
+
+    pub fn is_resuming(&self) -> bool { self.resume }
+
+    fn marker_path(&self, stage: &str) -> PathBuf {
+        self.folder.join(format!("{}.done", stage))
+    }
+
+    // Resuming runs overwrite leftovers of earlier attempts, new runs must not touch existing files unless overwriting.
+    pub fn create_file(&self, path: &Path) -> std::io::Result<File> {
+        if self.resume || self.overwrite {
+            File::create(path)
+        } else {
+            File::create_new(path)
+        }
+    }
+
+    // True only when resuming, and all the outputs recorded by the stage are unchanged.
+    pub fn is_done(&self, stage: &str) -> bool {
+        if !self.resume {
+            return false;
+        }
+
+        match read_to_string(self.marker_path(stage)) {
+            Err(_) => false,
+            Ok(marker) => marker.lines().all(|line| match line.rsplit_once('\t') {
+                Some((path, size)) => match (Path::new(path).metadata(), size.parse::<u64>()) {
+                    (Ok(metadata), Ok(size)) => metadata.len() == size,
+                    _ => false,
+                },
+                None => false,
+            }),
+        }
+    }
+
+    // The marker is written to a temporary file and renamed, so a crash can't leave a partial marker behind.
+    pub fn mark_done(&self, stage: &str, outputs: &[&Path]) -> std::io::Result<()> {
+        let marker_path = self.marker_path(stage);
+        let temporary_path = marker_path.with_extension("tmp");
+        let mut stream = BufWriter::new(File::create(&temporary_path)?);
+        for output in outputs {
+            writeln!(stream, "{}\t{}", output.display(), output.metadata()?.len())?;
+        }
+        stream.flush()?;
+        drop(stream);
+        rename(temporary_path, marker_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use tempdir::TempDir;
+    use crate::checkpoint::Checkpoints;
+
+    #[test]
+    fn resume_stages() {
+        let work_dir = TempDir::new("genezip").unwrap();
+        let output = work_dir.path().join("output.tsv");
+
+        let checkpoints = Checkpoints::new(work_dir.path(), false, false, "a").unwrap();
+        writeln!(checkpoints.create_file(&output).unwrap(), "data").unwrap();
+        assert!(checkpoints.create_file(&output).is_err());
+        checkpoints.mark_done("stage", &[&output]).unwrap();
+        // Markers are ignored unless resuming
+        assert!(!checkpoints.is_done("stage"));
+
+        assert!(Checkpoints::new(work_dir.path(), false, false, "a").is_err());
+        assert!(Checkpoints::new(work_dir.path(), true, false, "b").is_err());
+        let checkpoints = Checkpoints::new(work_dir.path(), true, false, "a").unwrap();
+        assert!(checkpoints.is_done("stage"));
+        assert!(!checkpoints.is_done("other_stage"));
+
+        // A changed output invalidates its stage
+        writeln!(File::options().append(true).open(&output).unwrap(), "more data").unwrap();
+        assert!(!checkpoints.is_done("stage"));
+
+        // Overwriting starts over in the folder, with any parameters
+        let checkpoints = Checkpoints::new(work_dir.path(), false, true, "b").unwrap();
+        assert!(checkpoints.create_file(&output).is_ok());
+        assert!(!checkpoints.is_done("stage"));
+        assert!(!Checkpoints::new(work_dir.path(), true, false, "b").unwrap().is_done("stage"));
+    }
+}
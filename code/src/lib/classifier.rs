@@ -1,10 +1,11 @@
 //  Created by Or Leibovich, Yochai Meir, and Itai Sharon
 
 use std::io::{BufWriter, Write};
-use std::path::{Path};
+use std::path::{Path, PathBuf};
 use std::string::String;
 use std::collections::HashSet;
 use std::fmt::Display;
+use std::sync::Arc;
 
 use rayon::prelude::*;
 use hashbrown::{HashMap};
@@ -17,15 +18,28 @@ use crate::kmer::create_normalized_profile;
 use crate::lz78::{LenBases, LZ78};
 use crate::reference_sequence::ReferenceSequence;
 use crate::taxonomy::{TaxonomicRank, Taxonomy};
+use crate::calibration::{prediction_margin, Calibration};
+use crate::novelty::NoveltyProfile;
+use crate::ncbi_taxdump::{TaxDump, TaxonLineage, GENUS_RANK};
 use serde::{Serialize, Deserialize};
 use crate::output_streams::{OutputFileType, OutputStreams};
-use crate::samples_file_reader::{Sample, SampleError, SampleSource};
+use crate::samples_file_reader::{merge_repeated_samples, Sample, SampleError, SampleSource};
 
 #[derive(Serialize, Deserialize)]
 pub struct Classifier {
     models: Box<HashMap<String, ReferenceSequence>>,
     len_bases: LenBases,
     models_order: Vec<String>,
+    // Taxonomy reported for each model, e.g. the merged taxonomy of DBBuild clusters.
+    // Stored apart from the classifier by the database, so older databases can still be loaded.
+    #[serde(skip)]
+    model2taxonomy: HashMap<String, Taxonomy>,
+    // NCBI lineage of models trained with a taxid, stored by the database like model2taxonomy
+    #[serde(skip)]
+    model2lineage: HashMap<String, TaxonLineage>,
+    // Maps predictions to the probability the best hit is correct, fitted by calibrate and stored by the database
+    #[serde(skip)]
+    calibration: Option<Calibration>,
 }
 
 impl Classifier {
@@ -34,34 +48,109 @@ impl Classifier {
             models: Box::new(HashMap::new()),
             len_bases,
             models_order: Vec::new(),
+            model2taxonomy: HashMap::new(),
+            model2lineage: HashMap::new(),
+            calibration: None,
         }
     }
 
+    pub fn get_model2taxonomy(&self) -> &HashMap<String, Taxonomy> { &self.model2taxonomy }
+    pub fn set_model2taxonomy(&mut self, model2taxonomy: HashMap<String, Taxonomy>) { self.model2taxonomy = model2taxonomy; }
+    pub fn get_model2lineage(&self) -> &HashMap<String, TaxonLineage> { &self.model2lineage }
+    pub fn set_model2lineage(&mut self, model2lineage: HashMap<String, TaxonLineage>) { self.model2lineage = model2lineage; }
+    pub fn get_calibration(&self) -> Option<&Calibration> { self.calibration.as_ref() }
+    pub fn set_calibration(&mut self, calibration: Option<Calibration>) { self.calibration = calibration; }
+    pub fn get_model2novelty(&self) -> HashMap<&String, &NoveltyProfile> {
+        self.models.iter()
+            .filter_map(|(name, model)| model.get_novelty().map(|novelty| (name, novelty)))
+            .collect()
+    }
+    pub fn set_model2novelty(&mut self, mut model2novelty: HashMap<String, NoveltyProfile>) {
+        for (name, model) in self.models.iter_mut() {
+            model.set_novelty(model2novelty.remove(name));
+        }
+    }
+    // The FASTA files of models trained on several, see ReferenceSequence::from_fastas
+    pub fn get_model2other_fasta_paths(&self) -> HashMap<&String, &[PathBuf]> {
+        self.models.iter()
+            .filter(|(_, model)| !model.get_other_fasta_paths().is_empty())
+            .map(|(name, model)| (name, model.get_other_fasta_paths()))
+            .collect()
+    }
+    pub fn set_model2other_fasta_paths(&mut self, mut model2other_fasta_paths: HashMap<String, Vec<PathBuf>>) {
+        for (name, model) in self.models.iter_mut() {
+            model.set_other_fasta_paths(model2other_fasta_paths.remove(name).unwrap_or_default());
+        }
+    }
+    fn has_novelty(&self) -> bool { self.models.values().any(|model| model.get_novelty().is_some()) }
+    // Taxonomy of every model: the one given with its cluster, or else the one it was trained with
+    pub fn collect_model2taxonomy(&self) -> HashMap<String, Taxonomy> {
+        self.models.iter()
+            .filter_map(|(name, model)| self.model2taxonomy.get(name).or(model.get_kmer_cluster()).map(|taxonomy| (name.clone(), taxonomy.clone())))
+            .collect()
+    }
+
     pub fn add_model(&mut self, name: &str, model: ReferenceSequence) {
         self.models.insert(name.to_string(), model);
         self.models_order.push(name.to_string());
         self.models_order.sort();
     }
 
-    pub fn batch_add_model(&mut self, name2file: &Path, max_depth: usize, buffer_size: usize, kmer_size: &Option<usize>) -> Result<(), SampleError> {
+    pub fn batch_add_model(&mut self, name2file: &Path, max_depth: usize, buffer_size: usize, kmer_size: &Option<usize>, taxdump: Option<Arc<TaxDump>>) -> Result<(), SampleError> {
         let samples = SampleSource::new(name2file, kmer_size.is_some())
+            .with_taxdump(taxdump)
             .into_iter()
             .collect::<Result<Vec<Sample>, SampleError>>()?;
+        self.batch_add_samples(&samples, max_depth, buffer_size, kmer_size);
+        Ok(())
+    }
+
+    // Samples repeating a name are a single model, trained on all of their FASTA files
+    pub fn batch_add_samples(&mut self, samples: &[Sample], max_depth: usize, buffer_size: usize, kmer_size: &Option<usize>) {
+        let samples = merge_repeated_samples(samples).unwrap_or_else(|e| panic!("{}", e));
         let mut models: Vec<_> = samples
             .par_iter()
-            .map(|sample|  (sample.get_name().to_string(), Some(ReferenceSequence::new(sample.get_path(), sample.get_name(), kmer_size, buffer_size, self.len_bases.clone(), max_depth, sample.get_taxonomy()))))
+            .map(|sample| {
+                let model = match sample.get_paths() {
+                    [fasta_path] => ReferenceSequence::new(fasta_path, sample.get_name(), kmer_size, buffer_size, self.len_bases.clone(), max_depth, sample.get_taxonomy()),
+                    fasta_paths => ReferenceSequence::from_fastas(fasta_paths, sample.get_name(), kmer_size, buffer_size, self.len_bases.clone(), max_depth, sample.get_taxonomy()),
+                };
+                (sample.get_name().to_string(), Some(model.with_gc(sample.get_gc())))
+            })
             .collect();
 
         models.iter_mut()
             .for_each(|(name, model)| self.add_model(name.as_str(), model.take().unwrap()));
-        Ok(())
+        self.model2lineage.extend(samples.iter()
+            .filter_map(|sample| sample.get_lineage().map(|lineage| (sample.get_name().to_string(), lineage.clone()))));
+    }
+
+    // Each model is trained on the concatenation of its FASTA files, and reports the given taxonomy.
+    pub fn batch_add_clusters(&mut self, clusters: &[(String, Vec<PathBuf>, Taxonomy)], max_depth: usize, buffer_size: usize, kmer_size: &Option<usize>) {
+        let mut models: Vec<_> = clusters
+            .par_iter()
+            .map(|(name, fastas, taxonomy)| (name.to_string(), Some(ReferenceSequence::from_fastas(fastas, name, kmer_size, buffer_size, self.len_bases.clone(), max_depth, &Some(taxonomy.clone())))))
+            .collect();
+
+        models.iter_mut()
+            .for_each(|(name, model)| self.add_model(name.as_str(), model.take().unwrap()));
+        self.model2taxonomy.extend(clusters.iter().map(|(name, _, taxonomy)| (name.to_string(), taxonomy.clone())));
     }
 
     pub fn print_prediction(&self, name: &str, out_streams: &mut OutputStreams, prediction: &(Vec<(&String, Option<f64>)>, usize)) -> std::io::Result<()> {
+        let best_hit_taxonomy = Classifier::get_best_model_name(&prediction.0).and_then(|best| self.get_model_taxonomy(best));
+        out_streams.add_classification(name, best_hit_taxonomy, prediction.1);
+        out_streams.add_scores(name, &prediction.0)?;
+        let json_hits = out_streams.get_json_hits();
         for (ost, fout) in out_streams.streams_iter() {
             match ost {
                 OutputFileType::BaseGz => self.print_prediction_base_gz_file(name, fout, prediction)?,
                 OutputFileType::LzValues => self.print_prediction_lz_matrix(name, fout, &prediction.0)?,
+                OutputFileType::JsonLines => self.print_prediction_json_lines(name, fout, prediction, json_hits)?,
+                // Written once all predictions are in, see OutputStreams::finish
+                OutputFileType::Krona | OutputFileType::Biom => (),
+                // Not a text stream, see OutputStreams::add_scores
+                OutputFileType::LzParquet => (),
             }
         }
 
@@ -71,12 +160,120 @@ impl Classifier {
     fn print_prediction_base_gz_file<W: Write>(&self, name: &str, fout: &mut BufWriter<W>, prediction: &(Vec<(&String, Option<f64>)>, usize)) -> std::io::Result<()> {
         write!(fout, "{name}\t{}", prediction.1)?;
 
-        match Classifier::get_best_model_name(&prediction.0) {
-            Some(best_model_name) => writeln!(fout, "\t{best_model_name}")?,
-            None => writeln!(fout, "\tNA")?,
+        let best_model_name = Classifier::get_best_model_name(&prediction.0);
+        match best_model_name {
+            Some(best_model_name) => write!(fout, "\t{best_model_name}")?,
+            None => write!(fout, "\tNA")?,
         }
 
-        Ok(())
+        if !self.model2taxonomy.is_empty() {
+            match best_model_name.and_then(|name| self.model2taxonomy.get(name)) {
+                Some(taxonomy) => write!(fout, "\t{taxonomy}")?,
+                None => write!(fout, "\tNA")?,
+            }
+        }
+
+        if !self.model2lineage.is_empty() {
+            match best_model_name.and_then(|name| self.model2lineage.get(name)) {
+                Some(lineage) => write!(fout, "\t{}\t{lineage}\t{}", lineage.get_taxid().map_or(String::from("NA"), |t| t.to_string()), lineage.get_taxid_path())?,
+                None => write!(fout, "\tNA\tNA\tNA")?,
+            }
+        }
+
+        if let Some(calibration) = &self.calibration {
+            match prediction_margin(&prediction.0) {
+                Some(margin) => for (_, confidence) in calibration.confidence(margin, prediction.1) {
+                    write!(fout, "\t{confidence:.4}")?;
+                },
+                None => for _ in calibration.get_ranks() {
+                    write!(fout, "\tNA")?;
+                },
+            }
+        }
+
+        if self.has_novelty() {
+            match self.novelty_rank(prediction, best_model_name) {
+                Some(rank) => write!(fout, "\t{rank}")?,
+                None => write!(fout, "\tNA")?,
+            }
+        }
+
+        writeln!(fout)
+    }
+
+    // The deepest rank at which the query plausibly belongs to the taxon of its best hit
+    fn novelty_rank(&self, prediction: &(Vec<(&String, Option<f64>)>, usize), best_model_name: Option<&String>) -> Option<TaxonomicRank> {
+        let best_score = best_model_name.and_then(|best| prediction.0.iter().find(|(model_name, _)| *model_name == best).and_then(|(_, score)| *score));
+        best_model_name.zip(best_score).and_then(|(model_name, score)| self.models[model_name].get_novelty().and_then(|novelty| novelty.deepest_rank(score)))
+    }
+
+    // The taxonomy given with the model's cluster, or else the one it was trained with
+    fn get_model_taxonomy(&self, model_name: &str) -> Option<&Taxonomy> {
+        self.model2taxonomy.get(model_name).or(self.models.get(model_name).and_then(|model| model.get_kmer_cluster()))
+    }
+
+    /*
+    A JSON object per line, with the number of models left after the %G+C and k-mer filters, the best json_hits scores
+    (all for 0), the best hit with its taxonomy, lineage, confidence and novelty when known, and warnings.
+     */
+    fn print_prediction_json_lines<W: Write>(&self, name: &str, fout: &mut BufWriter<W>, prediction: &(Vec<(&String, Option<f64>)>, usize), json_hits: usize) -> std::io::Result<()> {
+        let mut scores = prediction.0.iter()
+            .filter_map(|&(model_name, score)| score.map(|score| (model_name, score)))
+            .collect::<Vec<(&String, f64)>>();
+        scores.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let best_model_name = Classifier::get_best_model_name(&prediction.0);
+        let taxonomy = best_model_name.and_then(|best| self.get_model_taxonomy(best));
+
+        let mut warnings = Vec::new();
+        if prediction.1 == 0 {
+            warnings.push(String::from("The sequence has no nucleotides"));
+        }
+        if prediction.0.is_empty() {
+            warnings.push(String::from("No model passed the %G+C and k-mer filters"));
+        }
+        if scores.len() < prediction.0.len() {
+            warnings.push(format!("{} models did not score the sequence", prediction.0.len() - scores.len()));
+        }
+        if best_model_name.is_some() && taxonomy.is_none() && (!self.model2taxonomy.is_empty() || self.models.values().any(|model| model.get_kmer_cluster().is_some())) {
+            warnings.push(String::from("The best hit has no taxonomy"));
+        }
+
+        let mut record = serde_json::json!({
+            "name": name,
+            "length": prediction.1,
+            "models": self.models.len(),
+            "candidates": prediction.0.len(),
+            "scores": scores.iter()
+                .take(if json_hits == 0 { scores.len() } else { json_hits })
+                .map(|(model_name, score)| serde_json::json!({"model": model_name, "score": score}))
+                .collect::<Vec<_>>(),
+            "best_hit": best_model_name,
+            "taxonomy": taxonomy.map(|t| t.to_string()),
+        });
+        if let Some(lineage) = best_model_name.and_then(|best| self.model2lineage.get(best)) {
+            record["taxid"] = serde_json::json!(lineage.get_taxid());
+            record["lineage"] = serde_json::json!(lineage.to_string());
+        }
+        if let Some(calibration) = &self.calibration {
+            record["confidence"] = match prediction_margin(&prediction.0) {
+                Some(margin) => calibration.confidence(margin, prediction.1).into_iter()
+                    .map(|(rank, confidence)| (rank.to_string(), serde_json::json!(confidence)))
+                    .collect::<serde_json::Map<String, serde_json::Value>>()
+                    .into(),
+                None => serde_json::Value::Null,
+            };
+        }
+        if self.has_novelty() {
+            let novelty_rank = self.novelty_rank(prediction, best_model_name);
+            if best_model_name.is_some() && novelty_rank.is_none() {
+                warnings.push(String::from("The sequence is novel at every rank of its best hit"));
+            }
+            record["novelty_rank"] = serde_json::json!(novelty_rank.map(|rank| rank.to_string()));
+        }
+        record["warnings"] = serde_json::json!(warnings);
+
+        serde_json::to_writer(&mut *fout, &record)?;
+        writeln!(fout)
     }
     fn print_prediction_lz_matrix<W: Write>(&self, name: &str, fout: &mut BufWriter<W>, prediction: &Vec<(&String, Option<f64>)>) -> std::io::Result<()> {
         write!(fout, "{name}")?;
@@ -162,16 +359,27 @@ impl Classifier {
             Err(e) => panic!("E: tried to create k({})-mer for genome {}, but got {:?}, quitting", kmer_cluster_limit, sequence_stream, e),
         };
 
-        let best_kmer_cluster = models_to_check.into_par_iter()
-            .map(|&model_name | self.get_model_kmer_correlation(model_name, &genome_kmer, sequence_stream.clone()))
+        let best_model_name = models_to_check.into_par_iter()
+            .map(|&model_name | (model_name, self.get_model_kmer_correlation(model_name, &genome_kmer, sequence_stream.clone()).1))
             .max_by(|pair_a, pair_b| pair_a.1.total_cmp(&pair_b.1) ).unwrap().0;
 
         models_to_check.iter()
-            .filter(|&&model_name| self.models[model_name].get_kmer_cluster().unwrap_or_else(||panic!("E: tried to filter by kmer but model {} has no kmer cluster, quitting", model_name)).equal_to_rank(best_kmer_cluster, &TaxonomicRank::Genus))
+            .filter(|&&model_name| self.same_genus(model_name, best_model_name))
             .copied()
             .collect::<HashSet<&String>>()
     }
 
+    // Models trained with a taxid are compared by their NCBI lineages, whose genus may be missing from the GTDB form (e.g. under a 'no rank' node)
+    fn same_genus(&self, model_name: &str, other_model_name: &str) -> bool {
+        match (self.model2lineage.get(model_name), self.model2lineage.get(other_model_name)) {
+            (Some(lineage), Some(other_lineage)) => lineage.equal_to_rank(other_lineage, GENUS_RANK),
+            _ => {
+                let kmer_cluster = |name: &str| self.models[name].get_kmer_cluster().unwrap_or_else(||panic!("E: tried to filter by kmer but model {} has no kmer cluster, quitting", name));
+                kmer_cluster(model_name).equal_to_rank(kmer_cluster(other_model_name), &TaxonomicRank::Genus)
+            },
+        }
+    }
+
 
     pub fn predict<I>(&self, sequence: I, gc_limit: Option<f64>, kmer_cluster_limit: &Option<usize>, reflect: bool) -> (Vec<(&String, Option<f64>)>, usize)
     where I: IntoIterator<Item=u8> + Clone + Display + Sync {
@@ -216,6 +424,7 @@ impl Classifier {
             match oft {
                 OutputFileType::BaseGz => self.print_header_base_gz_file(fout)?,
                 OutputFileType::LzValues => self.print_header_lz_matrix(fout)?,
+                OutputFileType::Krona | OutputFileType::Biom | OutputFileType::JsonLines | OutputFileType::LzParquet => (),
             }
         }
 
@@ -223,8 +432,22 @@ impl Classifier {
     }
 
     fn print_header_base_gz_file<W: Write>(&self, fout: &mut BufWriter<W>) -> std::io::Result<()> {
-        writeln!(fout, "Genome_name\tLength\tBest_hit")?;
-        Ok(())
+        write!(fout, "Genome_name\tLength\tBest_hit")?;
+        if !self.model2taxonomy.is_empty() {
+            write!(fout, "\tBest_hit_taxonomy")?;
+        }
+        if !self.model2lineage.is_empty() {
+            write!(fout, "\tBest_hit_taxid\tBest_hit_lineage\tBest_hit_taxid_lineage")?;
+        }
+        if let Some(calibration) = &self.calibration {
+            for rank in calibration.get_ranks() {
+                write!(fout, "\tConfidence_{}", rank.get_rank())?;
+            }
+        }
+        if self.has_novelty() {
+            write!(fout, "\tNovelty_rank")?;
+        }
+        writeln!(fout)
     }
     fn print_header_lz_matrix<W: Write>(&self, fout: &mut BufWriter<W>) -> std::io::Result<()> {
         write!(fout, "Genome_name")?;
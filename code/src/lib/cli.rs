@@ -1,8 +1,15 @@
 //  Created by Or Leibovich, Yochai Meir, and Itai Sharon
 
 use std::path::{Path, PathBuf};
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use crate::ani_calculator_tool::AniCalculatorTool;
+use crate::evaluation::{EvaluationParameters, EvaluationSplit};
+use crate::scoring::ScoreParameters;
+use crate::simulation::{LengthDistribution, SimulationParameters};
+use crate::genezip_distance::{MatrixFormat, ScoreMode};
+use crate::phylogeny::TreeMethod;
+use crate::profile::ProfileUnit;
+use crate::taxonomy::Taxonomy;
 /*
 TODO: edit the help strings
 */
@@ -32,7 +39,10 @@ enum Commands {
     /// Build a GeneZip database for repeat prediction
     Build {
         /// A file with the list of fasta files for the cluster models in the format
-        /// <cluster-name>\t<fasta-file>[\t<full taxonomy string>]
+        /// <cluster-name>\t<fasta-file>[\t<full taxonomy string>], or a sample sheet whose header names its columns: name, path and taxonomy,
+        /// optionally gc (a %G+C used instead of the computed one). Sheet paths are relative to the sheet, # starts a comment.
+        /// A model trained on several FASTA files (e.g. a genome and its plasmids) repeats its name over several lines, a sample sheet may also list them in one path separated by commas.
+        /// A directory of FASTA files, or a quoted glob pattern (e.g. 'genomes/*.fna'), may be given instead, each file is a model named by its file stem, without taxonomy (requires --kmer 0)
         #[arg(short = 'i', long = "train", value_name = "training", required = true)]
         training_name2file_file: PathBuf,
 
@@ -40,31 +50,60 @@ enum Commands {
         #[arg(short = 'd', long = "depth", value_name = "depth", default_value_t = 13)]
         max_depth: usize,
 
-        /// Use k-mer frequencies for faster prediction. Value is what k to use, set to 0 to disable. Also requires that taxonomy column is used for model building (see training).
+        /// Use k-mer frequencies for faster prediction. Value is what k to use, set to 0 to disable. Also requires that taxonomy column is used for model building (see training), models with an NCBI taxid are compared by their NCBI genus.
         #[arg(short = 'k', long = "kmer", value_name = "kmer", default_value_t = 4, required = true)]
         kmer_size: usize,
 
         /// Path to create the GeneZip database in
         #[arg(long = "db", value_name = "db", required = true)]
-        db: PathBuf
+        db: PathBuf,
+
+        /// Folder of an NCBI taxdump (nodes.dmp and names.dmp), the taxonomy column may then hold NCBI taxids.
+        /// The lineage and taxids of the best hit are added to the prediction output.
+        #[arg(long = "taxdump", value_name = "taxdump")]
+        taxdump: Option<PathBuf>,
+
+        /// Fill missing taxonomic ranks with names derived from their parent, e.g. Neisseriaceae->UnknownGenus
+        #[arg(long = "fill-ranks", value_name = "fill-ranks", default_value_t = false)]
+        fill_ranks: bool,
+
+        /// Build even if the taxonomies are inconsistent (malformed, with gaps, or with taxa under different parents).
+        /// Samples that can't be read are left out
+        #[arg(long = "lenient", value_name = "lenient", default_value_t = false)]
+        lenient: bool,
+
+        /// Where to write the taxonomy validation report, <db>.taxonomy_report.tsv by default
+        #[arg(long = "taxonomy-report", value_name = "taxonomy-report")]
+        taxonomy_report: Option<PathBuf>,
+
+        /// Score every model on the other references, so predictions report the deepest rank at which
+        /// the query still plausibly belongs to its best hit (Novelty_rank). Needs the taxonomy column, quadratic in the number of models
+        #[arg(long = "novelty", value_name = "novelty", default_value_t = false)]
+        novelty: bool,
     },
     /// Classify sequences using GeneZip, use a database created with build
     DBPredict {
         /// A file with the list of fasta files for prediction, format is
-        /// <cluster-name>\t<fasta-file>
+        /// <cluster-name>\t<fasta-file>, or a sample sheet whose header names its columns (name and path), paths are relative to the sheet.
+        /// A sheet may have a weight column (e.g. read count) scaling the Krona and BIOM abundances, and a group (or bin) column, the BIOM sample of the sequence.
+        /// A directory of FASTA files, or a quoted glob pattern (e.g. 'genomes/*.fna'), may be given instead, each file is named by its file stem. With - each record of a FASTA read from stdin is predicted
         #[arg(short = 't', long = "predict", value_name = "predict", required = true)]
         prediction_name2file_file: PathBuf,
 
-        /// Where to print the output file
+        /// Where to print the output file. Any output path of this command ending with .gz or .zst is compressed on the fly, and - writes it to stdout
         #[arg(short = 'o', long = "output", value_name = "output", required = true)]
         out_file: PathBuf,
 
         /// Path to a file to which the LZ values assigned by the models (columns) to each sequence (rows) will be printed.
         /// Warning: this file can be huge for large reference datasets (e.g. GTDB) and many genomes/sequences.
+        /// If the path ends with .parquet, the values are written as compressed Parquet in long form (query, model, score), without the filtered models.
         /// (optional, default: do not create the file)
         #[arg(long = "lzvalues", value_name = "lzvalues")]
         gz_values_file: Option<PathBuf>,
 
+        #[command(flatten)]
+        optional_outputs: OptionalOutputs,
+
         /// If given, used as output path file for ANI between sequences and their best-hit.
         /// By default, paths to sequences of representatives will be taken from the database, however, if --train is given, it will override database paths.
         #[arg(long = "ani", value_name = "ani")]
@@ -77,7 +116,10 @@ enum Commands {
         /* --------------------------------- */
 
         /// A file with the list of fasta files for the cluster models in the format
-        /// <cluster-name>\t<fasta-file>[\t<full taxonomy string>]
+        /// <cluster-name>\t<fasta-file>[\t<full taxonomy string>], or a sample sheet whose header names its columns: name, path and taxonomy,
+        /// optionally gc (a %G+C used instead of the computed one). Sheet paths are relative to the sheet, # starts a comment.
+        /// A model trained on several FASTA files (e.g. a genome and its plasmids) repeats its name over several lines, a sample sheet may also list them in one path separated by commas.
+        /// A directory of FASTA files, or a quoted glob pattern (e.g. 'genomes/*.fna'), may be given instead, each file is a model named by its file stem, without taxonomy (requires --kmer 0)
         ///
         /// Note: Will only be used to override paths in the database for ANI prediction.
         #[arg(short = 'i', long = "train", value_name = "training")]
@@ -98,20 +140,26 @@ enum Commands {
     /// Classify sequences using GeneZip, build (but don't keep) the database and then predict
     TrainPredict {
         /// A file with the list of fasta files for prediction, format is
-        /// <cluster-name>\t<fasta-file>
+        /// <cluster-name>\t<fasta-file>, or a sample sheet whose header names its columns (name and path), paths are relative to the sheet.
+        /// A sheet may have a weight column (e.g. read count) scaling the Krona and BIOM abundances, and a group (or bin) column, the BIOM sample of the sequence.
+        /// A directory of FASTA files, or a quoted glob pattern (e.g. 'genomes/*.fna'), may be given instead, each file is named by its file stem. With - each record of a FASTA read from stdin is predicted
         #[arg(short = 't', long = "predict", value_name = "predict", required = true)]
         prediction_name2file_file: PathBuf,
 
-        /// Where to print the output file
+        /// Where to print the output file. Any output path of this command ending with .gz or .zst is compressed on the fly, and - writes it to stdout
         #[arg(short = 'o', long = "output", value_name = "output", required = true)]
         out_file: PathBuf,
 
         /// Path to a file to which the LZ values assigned by the models (columns) to each sequence (rows) will be printed.
         /// Warning: this file can be huge for large reference datasets (e.g. GTDB) and many genomes/sequences.
+        /// If the path ends with .parquet, the values are written as compressed Parquet in long form (query, model, score), without the filtered models.
         /// (optional, default: do not create the file)
         #[arg(long = "lzvalues", value_name = "lzvalues")]
         gz_values_file: Option<PathBuf>,
 
+        #[command(flatten)]
+        optional_outputs: OptionalOutputs,
+
         /// If given, used as output path file for ANI between sequences and their best-hit.
         #[arg(long = "ani", value_name = "ani")]
         ani_out_file: Option<PathBuf>,
@@ -135,13 +183,21 @@ enum Commands {
         kmer_size: usize,
 
         /// A file with the list of fasta files for the cluster models in the format
-        /// <cluster-name>\t<fasta-file>[\t<full taxonomy string>]
+        /// <cluster-name>\t<fasta-file>[\t<full taxonomy string>], or a sample sheet whose header names its columns: name, path and taxonomy,
+        /// optionally gc (a %G+C used instead of the computed one). Sheet paths are relative to the sheet, # starts a comment.
+        /// A model trained on several FASTA files (e.g. a genome and its plasmids) repeats its name over several lines, a sample sheet may also list them in one path separated by commas.
+        /// A directory of FASTA files, or a quoted glob pattern (e.g. 'genomes/*.fna'), may be given instead, each file is a model named by its file stem, without taxonomy (requires --kmer 0)
         #[arg(short = 'i', long = "train", value_name = "training", required = true)]
         training_name2file_file: PathBuf,
 
         /// Replace the raw GeneZip value by the following: (GZ(G_1, G_2) + GZ(G_2, G_1)) / ( GZ(G_1, G_1) + GZ(G_2, G_2))
         #[arg(short = 'r', long = "reflect", value_name = "reflect", default_value_t = false, hide = true)]
         reflect: bool,
+
+        /// Folder of an NCBI taxdump (nodes.dmp and names.dmp), the taxonomy column may then hold NCBI taxids.
+        /// The lineage and taxids of the best hit are added to the output.
+        #[arg(long = "taxdump", value_name = "taxdump")]
+        taxdump: Option<PathBuf>,
     },
     /// Print k-mer frequencies to a TSV file
     PrintKmer {
@@ -195,7 +251,7 @@ enum Commands {
         #[arg(short = 't', long = "predict", value_name = "predict", required = true)]
         prediction_name2file_file: PathBuf,
 
-        /// Where to print the output file
+        /// Where to print the output file. An output path ending with .gz or .zst is compressed on the fly, and - writes it to stdout
         #[arg(short = 'o', long = "output", value_name = "output", required = true)]
         out_file: PathBuf,
 
@@ -205,20 +261,24 @@ enum Commands {
     },
     /// Classify sequences from a metagenomic assembly fasta file
     MetaPredict {
-        /// A fasta file
+        /// A fasta file, - to read it from stdin
         #[arg(short = 't', long = "predict", value_name = "predict", required = true)]
         prediction_name2file_file: PathBuf,
 
-        /// Where to print the output file
+        /// Where to print the output file. Any output path of this command ending with .gz or .zst is compressed on the fly, and - writes it to stdout
         #[arg(short = 'o', long = "output", value_name = "output", required = true)]
         out_file: PathBuf,
 
         /// Path to a file to which the LZ values assigned by the models (columns) to each sequence (rows) will be printed.
         /// Warning: this file can be huge for large reference datasets (e.g. GTDB) and many genomes/sequences.
+        /// If the path ends with .parquet, the values are written as compressed Parquet in long form (query, model, score), without the filtered models.
         /// (optional, default: do not create the file)
         #[arg(long = "lzvalues", value_name = "lzvalues")]
         gz_values_file: Option<PathBuf>,
 
+        #[command(flatten)]
+        optional_outputs: OptionalOutputs,
+
         /* --------------------------------- */
 
         /// Maximum depth allowed for the context tree, must be >= 1. Tested up-to 17.
@@ -241,20 +301,222 @@ enum Commands {
         /// Minimal number of genes in a contig for it to be given prediction. Only acive if --genes is set. To disable (by default) set to 0. For example, if set to 3, only contigs that have three or more genes will be evaluated.'
         #[arg(long = "mingenes", value_name = "mingenes", default_value_t = 0)]
         min_genes: usize,
-    }
+
+        /// Folder of an NCBI taxdump (nodes.dmp and names.dmp), the taxonomy column may then hold NCBI taxids.
+        /// The lineage and taxids of the best hit are added to the output.
+        #[arg(long = "taxdump", value_name = "taxdump")]
+        taxdump: Option<PathBuf>,
+    },
+    /// Cross-validate GeneZip on a taxonomy-annotated list, reports the accuracy at every rank
+    Evaluate {
+        /// A file with the list of fasta files in the format
+        /// <genome-name>\t<fasta-file>\t<full taxonomy string>
+        #[arg(short = 'i', long = "train", value_name = "training", required = true)]
+        training_name2file_file: PathBuf,
+
+        /// Output prefix, writes <output>.predictions.tsv, <output>.accuracy.tsv, <output>.confusion.tsv and <output>.json as plain files
+        #[arg(short = 'o', long = "output", value_name = "output", required = true)]
+        out_file: PathBuf,
+
+        /// Number of folds, each fold is held out once and predicted by models trained on the other folds
+        #[arg(long = "folds", value_name = "folds", default_value_t = 5)]
+        folds: usize,
+
+        /// Hold out one genome at a time instead of folds, trains a classifier per genome
+        #[arg(long = "leave-one-out", value_name = "leave-one-out", default_value_t = false, conflicts_with = "folds")]
+        leave_one_out: bool,
+
+        /// Maximum depth allowed for the context tree, must be >= 1. Tested up-to 17.
+        #[arg(short = 'd', long = "depth", value_name = "depth", default_value_t = 13)]
+        max_depth: usize,
+
+        /// Use k-mer frequencies for faster prediction. Value is what k to use, set to 0 to disable.
+        #[arg(short = 'k', long = "kmer", value_name = "kmer", default_value_t = 4)]
+        kmer_size: usize,
+
+        /// Set %G+C distance between model and test genome limit. To disable, set to 100.
+        #[arg(long = "gc", value_name = "gc", default_value_t = 10.0)]
+        gc_limit: f64,
+
+        /// Random state seed used to shuffle genomes into folds
+        #[arg(short = 'r', long = "rss", value_name = "rss", default_value_t = 1)]
+        rss: u64,
+
+        /// Folder of an NCBI taxdump (nodes.dmp and names.dmp), the taxonomy column may then hold NCBI taxids.
+        #[arg(long = "taxdump", value_name = "taxdump")]
+        taxdump: Option<PathBuf>,
+    },
+    /// Score a prediction output against a truth table: precision, recall, F1 and unclassified rate per rank
+    Score {
+        /// Prediction output of GeneZip (db-predict, train-predict or meta-predict), the best hits are read from it
+        #[arg(short = 'p', long = "predictions", value_name = "predictions", required = true)]
+        predictions: PathBuf,
+
+        /// The true taxonomy of the queries, in the format
+        /// <query-name>\t<full taxonomy string>
+        #[arg(short = 't', long = "truth", value_name = "truth", required = true)]
+        truth: PathBuf,
+
+        /// The GeneZip database used for the predictions, the taxonomy of the best hits is taken from it
+        #[arg(long = "db", value_name = "db", required = true)]
+        db: PathBuf,
+
+        /// Where to print the scores, a row per rank. Written as JSON if the path ends with .json
+        #[arg(short = 'o', long = "output", value_name = "output", required = true)]
+        out_file: PathBuf,
+
+        /// Number of bootstrap replicates of the queries used for confidence intervals, set to 0 to disable.
+        #[arg(long = "bootstrap", value_name = "bootstrap", default_value_t = 0)]
+        bootstrap: usize,
+
+        /// Confidence level of the bootstrap intervals
+        #[arg(long = "confidence", value_name = "confidence", default_value_t = 0.95)]
+        confidence: f64,
+
+        /// Random state seed used for bootstrapping
+        #[arg(short = 'r', long = "rss", value_name = "rss", default_value_t = 1)]
+        rss: u64,
+    },
+    /// Cut genomes into random fragments, with a truth table for score, to benchmark the accuracy on short sequences
+    Simulate {
+        /// A file with the list of fasta files to fragment, in the format
+        /// <genome-name>\t<fasta-file>\t<full taxonomy string>
+        #[arg(short = 'i', long = "input", value_name = "input", required = true)]
+        input: PathBuf,
+
+        /// Output prefix, writes <output>.fna, <output>.truth.tsv and <output>.fragments.tsv as plain files
+        #[arg(short = 'o', long = "output", value_name = "output", required = true)]
+        out_file: PathBuf,
+
+        /// Number of fragments per genome
+        #[arg(short = 'n', long = "fragments", value_name = "fragments", default_value_t = 100)]
+        fragments: usize,
+
+        /// Fragment length, the median length for log-normal
+        #[arg(short = 'l', long = "length", value_name = "length", default_value_t = 1000)]
+        length: usize,
+
+        /// Distribution of the fragment lengths
+        #[arg(long = "distribution", value_name = "distribution", default_value_t = LengthDistribution::Fixed)]
+        distribution: LengthDistribution,
+
+        /// Standard deviation of the log of the length, used by log-normal
+        #[arg(long = "sigma", value_name = "sigma", default_value_t = 0.5)]
+        sigma: f64,
+
+        /// Probability of a substitution at each base
+        #[arg(long = "substitution", value_name = "substitution", default_value_t = 0.0)]
+        substitution_rate: f64,
+
+        /// Probability of an insertion or a deletion at each base
+        #[arg(long = "indel", value_name = "indel", default_value_t = 0.0)]
+        indel_rate: f64,
+
+        /// Random state seed
+        #[arg(short = 'r', long = "rss", value_name = "rss", default_value_t = 1)]
+        rss: u64,
+    },
+    /// Calibrate a database on labeled held-out genomes, predictions then report the probability the best hit is correct at each rank
+    Calibrate {
+        /// A file with the list of held-out fasta files, not used to build the database, in the format
+        /// <genome-name>\t<fasta-file>\t<full taxonomy string>
+        #[arg(short = 'i', long = "calibration", value_name = "calibration", required = true)]
+        calibration_name2file_file: PathBuf,
+
+        /// Path a pre-existing GeneZip database to calibrate
+        #[arg(long = "db", value_name = "db", required = true)]
+        db: PathBuf,
+
+        /// Where to save the calibrated database, may be the same as --db
+        #[arg(short = 'o', long = "output", value_name = "output", required = true)]
+        out_file: PathBuf,
+
+        /// Set %G+C distance between model and test genome limit, use the one used for prediction. To disable, set to 100.
+        #[arg(long = "gc", value_name = "gc", default_value_t = 10.0)]
+        gc_limit: f64,
+    },
+    /// Compute the GeneZip score between all pairs of genomes, a model is trained once per genome
+    Distances {
+        /// A file with the list of fasta files in the format
+        /// <genome-name>\t<fasta-file>[\t<full taxonomy string>], the taxonomy is needed with --taxon
+        #[arg(short = 'i', long = "input", value_name = "input", required = true)]
+        input: PathBuf,
+
+        /// Where to print the matrix. An output path ending with .gz or .zst is compressed on the fly, and - writes it to stdout
+        #[arg(short = 'o', long = "output", value_name = "output", required = true)]
+        out_file: PathBuf,
+
+        /// symmetric: (GZ(G_1, G_2) + GZ(G_2, G_1)) / (GZ(G_1, G_1) + GZ(G_2, G_2)), asymmetric: GZ(G_1, G_2) of the model of the row compressing the genome of the column
+        #[arg(long = "mode", value_name = "mode", default_value_t = ScoreMode::Symmetric)]
+        mode: ScoreMode,
+
+        /// Matrix format, phylip writes the symmetric scores minus one as distances (0 between a genome and itself)
+        #[arg(long = "format", value_name = "format", default_value_t = MatrixFormat::Square)]
+        format: MatrixFormat,
+
+        /// Maximum depth allowed for the context tree, must be >= 1. Tested up-to 17.
+        #[arg(short = 'd', long = "depth", value_name = "depth", default_value_t = 13)]
+        max_depth: usize,
+
+        /// Only include genomes within this clade, e.g. 'g__Escherichia' or 'p__Firmicutes;c__Bacilli'. May be given more than once.
+        #[arg(long = "taxon", value_name = "taxon", value_parser = Taxonomy::parse)]
+        taxa: Vec<Taxonomy>,
+
+        /// Also build a tree from the symmetric scores and write it in Newick format to this file
+        #[arg(long = "newick", value_name = "newick")]
+        newick: Option<PathBuf>,
+
+        /// Tree building method, neighbor joining or UPGMA
+        #[arg(long = "tree-method", value_name = "tree-method", default_value_t = TreeMethod::Nj, requires = "newick")]
+        tree_method: TreeMethod,
+
+        /// Label the internal nodes of the tree by the deepest taxon shared by their genomes, requires the taxonomy column
+        #[arg(long = "lca-labels", default_value_t = false, requires = "newick")]
+        lca_labels: bool,
+    },
+    /// Community composition from the predictions of a metagenome, as a CAMI profile and a Kraken-style report
+    Profile {
+        /// Prediction output of GeneZip (db-predict, train-predict or meta-predict), the best hits and lengths are read from it.
+        /// With a Novelty_rank column, sequences are classified down to that rank only
+        #[arg(short = 'p', long = "predictions", value_name = "predictions", required = true)]
+        predictions: PathBuf,
+
+        /// The GeneZip database used for the predictions, the taxonomy of the best hits is taken from it
+        #[arg(long = "db", value_name = "db", required = true)]
+        db: PathBuf,
+
+        /// Output prefix, writes <output>.profile (CAMI), <output>.kreport (Kraken-style) and <output>.models.tsv as plain files
+        #[arg(short = 'o', long = "output", value_name = "output", required = true)]
+        out_file: PathBuf,
+
+        /// Weight of each sequence, e.g. contig coverage or read count, in the format
+        /// <sequence-name>\t<weight>, or a sample sheet with a weight column (e.g. the one the sequences were predicted from). Every predicted sequence needs a weight
+        #[arg(short = 'w', long = "weights", value_name = "weights")]
+        weights: Option<PathBuf>,
+
+        /// bases: each sequence adds its length times its weight, sequences: each sequence adds its weight
+        #[arg(long = "unit", value_name = "unit", default_value_t = ProfileUnit::Bases)]
+        unit: ProfileUnit,
+
+        /// Sample ID of the CAMI profile, the name of the predictions file by default
+        #[arg(long = "sample-id", value_name = "sample-id")]
+        sample_id: Option<String>,
+    },
 }
 
 struct FeatureSettings {
     training_name2file_file: PathBuf,
     max_depth: usize,
     kmer_size: Option<usize>,
+    taxdump: Option<PathBuf>,
 }
 
 impl FeatureSettings {
-    fn new(training_name2file_file: &Path, max_depth: usize, kmer_size: Option<usize>) -> Self {
+    fn new(training_name2file_file: &Path, max_depth: usize, kmer_size: Option<usize>, taxdump: &Option<PathBuf>) -> Self {
         FeatureSettings {
             training_name2file_file: training_name2file_file.to_path_buf(),
             max_depth,
+            taxdump: taxdump.clone(),
             kmer_size: match kmer_size {
                 Some(k) => if k == 0 { None } else { Some(k) },
                 None => None,
@@ -265,18 +527,42 @@ impl FeatureSettings {
 
 struct BuildDBSettings {
     db: PathBuf,
-    feature_settings: FeatureSettings
+    feature_settings: FeatureSettings,
+    validation: ValidationSettings,
+    novelty: bool,
 }
 
 impl BuildDBSettings {
-    fn new(db: &Path, training_name2file_file: &Path, max_depth: usize, kmer_size: usize) -> Self {
+    fn new(db: &Path, training_name2file_file: &Path, max_depth: usize, kmer_size: usize, taxdump: &Option<PathBuf>, validation: ValidationSettings, novelty: bool) -> Self {
         BuildDBSettings {
             db: db.to_path_buf(),
-            feature_settings: FeatureSettings::new(training_name2file_file, max_depth, Some(kmer_size)),
+            feature_settings: FeatureSettings::new(training_name2file_file, max_depth, Some(kmer_size), taxdump),
+            validation,
+            novelty,
         }
     }
 }
 
+pub struct ValidationSettings {
+    fill_ranks: bool,
+    lenient: bool,
+    report: PathBuf,
+}
+
+impl ValidationSettings {
+    fn new(db: &Path, fill_ranks: bool, lenient: bool, report: &Option<PathBuf>) -> Self {
+        ValidationSettings {
+            fill_ranks,
+            lenient,
+            report: report.clone().unwrap_or_else(|| PathBuf::from(format!("{}.taxonomy_report.tsv", db.display()))),
+        }
+    }
+
+    pub fn get_fill_ranks(&self) -> bool { self.fill_ranks }
+    pub fn get_lenient(&self) -> bool { self.lenient }
+    pub fn get_report(&self) -> &Path { &self.report }
+}
+
 struct BuildKmerDBSettings {
     db: PathBuf,
     training_name2file_file: PathBuf,
@@ -311,10 +597,60 @@ impl RunSettings {
     }
 }
 
+// Optional outputs shared by DBPredict, TrainPredict and MetaPredict
+#[derive(Args)]
+pub struct OptionalOutputs {
+    /// Path to a Krona text input (for ktImportText) of the abundance of each best-hit taxonomy.
+    /// (optional, default: do not create the file)
+    #[arg(long = "krona", value_name = "krona")]
+    krona: Option<PathBuf>,
+
+    /// Path to a BIOM (JSON) table of the abundance of each best-hit taxonomy in each sample.
+    /// (optional, default: do not create the file)
+    #[arg(long = "biom", value_name = "biom")]
+    biom: Option<PathBuf>,
+
+    /// Krona and BIOM abundances, sequences: the number of predicted sequences, bases: the sum of their lengths.
+    /// Each sequence is multiplied by its weight in the sample sheet, if any
+    #[arg(long = "unit", value_name = "unit", default_value_t = ProfileUnit::Sequences)]
+    unit: ProfileUnit,
+
+
+    /// Path to a JSON Lines file, an object per sequence with its length, the number of models left after the filters,
+    /// the best scores, the best hit, its taxonomy and any warnings. (optional, default: do not create the file)
+    #[arg(long = "jsonl", value_name = "jsonl")]
+    jsonl: Option<PathBuf>,
+
+    /// Number of best scores per sequence in the JSON Lines output, set to 0 to include every model left after the filters
+    #[arg(long = "jsonl-hits", value_name = "jsonl-hits", default_value_t = 10)]
+    jsonl_hits: usize,
+}
+
+impl Default for OptionalOutputs {
+    fn default() -> Self {
+        OptionalOutputs {
+            krona: None,
+            biom: None,
+            unit: ProfileUnit::Sequences,
+            jsonl: None,
+            jsonl_hits: 0,
+        }
+    }
+}
+
+impl OptionalOutputs {
+    pub fn get_krona(&self) -> Option<&Path> { self.krona.as_deref() }
+    pub fn get_biom(&self) -> Option<&Path> { self.biom.as_deref() }
+    pub fn get_unit(&self) -> ProfileUnit { self.unit }
+    pub fn get_jsonl(&self) -> Option<&Path> { self.jsonl.as_deref() }
+    pub fn get_jsonl_hits(&self) -> usize { self.jsonl_hits }
+}
+
 struct PredictionSettings {
     prediction_name2file_file: PathBuf,
     out_file: PathBuf,
     gz_values_file: Option<PathBuf>,
+    optional_outputs: OptionalOutputs,
     ani_out_file: Option<PathBuf>,
     gc_limit: Option<f64>,
     reflect: bool,
@@ -322,11 +658,13 @@ struct PredictionSettings {
 }
 
 impl PredictionSettings {
-    fn new(prediction_name2file_file: &Path, out_file: &Path, gz_values_file: &Option<PathBuf>, ani_out_file: &Option<PathBuf>, gc_limit: f64, reflect: bool, ani_calculator_tool: Option<AniCalculatorTool>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    fn new(prediction_name2file_file: &Path, out_file: &Path, gz_values_file: &Option<PathBuf>, optional_outputs: OptionalOutputs, ani_out_file: &Option<PathBuf>, gc_limit: f64, reflect: bool, ani_calculator_tool: Option<AniCalculatorTool>) -> Self {
         PredictionSettings {
             prediction_name2file_file: prediction_name2file_file.to_path_buf(),
             out_file: out_file.to_path_buf(),
             gz_values_file: gz_values_file.clone(),
+            optional_outputs,
             ani_out_file: ani_out_file.clone(),
             gc_limit: if gc_limit == 100.0 {
                 None
@@ -364,17 +702,19 @@ struct MetaPrediction {
     prediction_name2file_file: PathBuf,
     out_file: PathBuf,
     gz_values_file: Option<PathBuf>,
+    optional_outputs: OptionalOutputs,
     genes: bool,
     min_genes: usize,
     gc_limit: Option<f64>,
 }
 
 impl MetaPrediction {
-    fn new(prediction_name2file_file: &Path, out_file: &Path, gz_values_file: &Option<PathBuf>, genes: bool, min_genes: usize, gc_limit: f64) -> Self {
+    fn new(prediction_name2file_file: &Path, out_file: &Path, gz_values_file: &Option<PathBuf>, optional_outputs: OptionalOutputs, genes: bool, min_genes: usize, gc_limit: f64) -> Self {
         Self {
             prediction_name2file_file: prediction_name2file_file.to_path_buf(),
             out_file: out_file.to_path_buf(),
             gz_values_file: gz_values_file.clone(),
+            optional_outputs,
             genes,
             min_genes,
             gc_limit: if gc_limit == 100.0 {
@@ -386,6 +726,183 @@ impl MetaPrediction {
     }
 }
 
+struct EvaluationSettings {
+    out_file: PathBuf,
+    split: EvaluationSplit,
+    gc_limit: Option<f64>,
+    random_state: u64,
+}
+
+impl EvaluationSettings {
+    fn new(out_file: &Path, folds: usize, leave_one_out: bool, gc_limit: f64, random_state: u64) -> Self {
+        Self {
+            out_file: out_file.to_path_buf(),
+            split: if leave_one_out { EvaluationSplit::LeaveOneOut } else { EvaluationSplit::KFold(folds) },
+            gc_limit: if gc_limit == 100.0 {
+                None
+            } else {
+                Some(gc_limit)
+            },
+            random_state,
+        }
+    }
+}
+
+pub struct ScoreSettings {
+    db: PathBuf,
+    predictions: PathBuf,
+    truth: PathBuf,
+    out_file: PathBuf,
+    bootstrap: usize,
+    confidence: f64,
+    random_state: u64,
+}
+
+impl ScoreSettings {
+    fn new(db: &Path, predictions: &Path, truth: &Path, out_file: &Path, bootstrap: usize, confidence: f64, random_state: u64) -> Self {
+        ScoreSettings {
+            db: db.to_path_buf(),
+            predictions: predictions.to_path_buf(),
+            truth: truth.to_path_buf(),
+            out_file: out_file.to_path_buf(),
+            bootstrap,
+            confidence,
+            random_state,
+        }
+    }
+
+    pub fn get_predictions(&self) -> &Path { &self.predictions }
+    pub fn get_truth(&self) -> &Path { &self.truth }
+    pub fn get_parameters(&self) -> ScoreParameters {
+        ScoreParameters {
+            bootstrap: self.bootstrap,
+            confidence: self.confidence,
+            random_state: self.random_state,
+        }
+    }
+}
+
+pub struct SimulationSettings {
+    input: PathBuf,
+    out_file: PathBuf,
+    fragments: usize,
+    length: usize,
+    distribution: LengthDistribution,
+    sigma: f64,
+    substitution_rate: f64,
+    indel_rate: f64,
+    random_state: u64,
+}
+
+impl SimulationSettings {
+    #[allow(clippy::too_many_arguments)]
+    fn new(input: &Path, out_file: &Path, fragments: usize, length: usize, distribution: LengthDistribution, sigma: f64, substitution_rate: f64, indel_rate: f64, random_state: u64) -> Self {
+        SimulationSettings {
+            input: input.to_path_buf(),
+            out_file: out_file.to_path_buf(),
+            fragments,
+            length,
+            distribution,
+            sigma,
+            substitution_rate,
+            indel_rate,
+            random_state,
+        }
+    }
+
+    pub fn get_input(&self) -> &Path { &self.input }
+    pub fn get_parameters(&self, buffer_size: usize) -> SimulationParameters {
+        SimulationParameters {
+            fragments: self.fragments,
+            length: self.length,
+            distribution: self.distribution,
+            sigma: self.sigma,
+            substitution_rate: self.substitution_rate,
+            indel_rate: self.indel_rate,
+            buffer_size,
+            random_state: self.random_state,
+        }
+    }
+}
+
+struct CalibrationSettings {
+    calibration_name2file_file: PathBuf,
+    out_file: PathBuf,
+    gc_limit: Option<f64>,
+}
+
+impl CalibrationSettings {
+    fn new(calibration_name2file_file: &Path, out_file: &Path, gc_limit: f64) -> Self {
+        CalibrationSettings {
+            calibration_name2file_file: calibration_name2file_file.to_path_buf(),
+            out_file: out_file.to_path_buf(),
+            gc_limit: if gc_limit == 100.0 {
+                None
+            } else {
+                Some(gc_limit)
+            },
+        }
+    }
+}
+
+pub struct DistanceSettings {
+    out_file: PathBuf,
+    mode: ScoreMode,
+    format: MatrixFormat,
+    taxa: Vec<Taxonomy>,
+    newick: Option<PathBuf>,
+    tree_method: TreeMethod,
+    lca_labels: bool,
+}
+
+impl DistanceSettings {
+    fn new(out_file: &Path, mode: ScoreMode, format: MatrixFormat, taxa: Vec<Taxonomy>, newick: Option<PathBuf>, tree_method: TreeMethod, lca_labels: bool) -> Self {
+        DistanceSettings {
+            out_file: out_file.to_path_buf(),
+            mode,
+            format,
+            taxa,
+            newick,
+            tree_method,
+            lca_labels,
+        }
+    }
+
+    pub fn get_mode(&self) -> ScoreMode { self.mode }
+    pub fn get_format(&self) -> MatrixFormat { self.format }
+    pub fn get_taxa(&self) -> &[Taxonomy] { &self.taxa }
+    pub fn get_newick(&self) -> Option<&PathBuf> { self.newick.as_ref() }
+    pub fn get_tree_method(&self) -> TreeMethod { self.tree_method }
+    pub fn get_lca_labels(&self) -> bool { self.lca_labels }
+}
+
+pub struct ProfileSettings {
+    db: PathBuf,
+    predictions: PathBuf,
+    out_file: PathBuf,
+    weights: Option<PathBuf>,
+    unit: ProfileUnit,
+    sample_id: String,
+}
+
+impl ProfileSettings {
+    fn new(db: &Path, predictions: &Path, out_file: &Path, weights: &Option<PathBuf>, unit: ProfileUnit, sample_id: Option<String>) -> Self {
+        ProfileSettings {
+            db: db.to_path_buf(),
+            predictions: predictions.to_path_buf(),
+            out_file: out_file.to_path_buf(),
+            weights: weights.clone(),
+            unit,
+            sample_id: sample_id.unwrap_or_else(|| predictions.file_stem().map_or(String::from("sample"), |stem| stem.to_string_lossy().to_string())),
+        }
+    }
+
+    pub fn get_predictions(&self) -> &Path { &self.predictions }
+    pub fn get_weights(&self) -> Option<&Path> { self.weights.as_deref() }
+    pub fn get_unit(&self) -> ProfileUnit { self.unit }
+    pub fn get_sample_id(&self) -> &str { &self.sample_id }
+}
+
 enum Task {
     BuildDB(BuildDBSettings),
     DBPredict(PathBuf, Option<PathBuf>, PredictionSettings),
@@ -394,22 +911,29 @@ enum Task {
     BuildKmer(BuildKmerDBSettings),
     KMerPredict(FeatureSettings, PredictionSettings),
     MetaPredict(FeatureSettings, MetaPrediction),
+    Evaluate(FeatureSettings, EvaluationSettings),
+    Score(ScoreSettings),
+    Simulate(SimulationSettings),
+    Calibrate(PathBuf, CalibrationSettings),
+    Distances(FeatureSettings, DistanceSettings),
+    Profile(ProfileSettings),
 }
 
 impl From<Commands> for Task {
     fn from(commands: Commands) -> Self {
         match commands {
-            Commands::Build {training_name2file_file, max_depth, kmer_size, db} => {
-                Task::BuildDB(BuildDBSettings::new(&db, &training_name2file_file, max_depth, kmer_size))
+            Commands::Build {training_name2file_file, max_depth, kmer_size, db, taxdump, fill_ranks, lenient, taxonomy_report, novelty} => {
+                let validation = ValidationSettings::new(&db, fill_ranks, lenient, &taxonomy_report);
+                Task::BuildDB(BuildDBSettings::new(&db, &training_name2file_file, max_depth, kmer_size, &taxdump, validation, novelty))
             },
-            Commands::DBPredict {prediction_name2file_file, out_file, gz_values_file, ani_out_file, anitool, training_name2file_file, gc_limit, db, reflect} => {
+            Commands::DBPredict {prediction_name2file_file, out_file, gz_values_file, optional_outputs, ani_out_file, anitool, training_name2file_file, gc_limit, db, reflect} => {
                 Task::DBPredict(db,
                                 training_name2file_file,
-                                PredictionSettings::new(&prediction_name2file_file, &out_file, &gz_values_file, &ani_out_file, gc_limit, reflect, anitool))
+                                PredictionSettings::new(&prediction_name2file_file, &out_file, &gz_values_file, optional_outputs, &ani_out_file, gc_limit, reflect, anitool))
             },
-            Commands::TrainPredict {prediction_name2file_file, out_file, gz_values_file, ani_out_file, anitool, max_depth, gc_limit, kmer_size, training_name2file_file, reflect} => {
-                Task::Predict(FeatureSettings::new(&training_name2file_file, max_depth, Some(kmer_size)),
-                              PredictionSettings::new(&prediction_name2file_file, &out_file, &gz_values_file, &ani_out_file, gc_limit, reflect, anitool))
+            Commands::TrainPredict {prediction_name2file_file, out_file, gz_values_file, optional_outputs, ani_out_file, anitool, max_depth, gc_limit, kmer_size, training_name2file_file, reflect, taxdump} => {
+                Task::Predict(FeatureSettings::new(&training_name2file_file, max_depth, Some(kmer_size), &taxdump),
+                              PredictionSettings::new(&prediction_name2file_file, &out_file, &gz_values_file, optional_outputs, &ani_out_file, gc_limit, reflect, anitool))
             },
             Commands::PrintKmer {input, output, k, ratio, meta} => {
                 Task::PrintKmer(PrintKmerSettings::new(&input, &output, k, ratio, meta))
@@ -418,13 +942,37 @@ impl From<Commands> for Task {
                 Task::BuildKmer(BuildKmerDBSettings::new(&db, &training_name2file_file, kmer_size))
             },
             Commands::KMerPredict {training_name2file_file, prediction_name2file_file, out_file, kmer_size} => {
-                Task::KMerPredict(FeatureSettings::new(&training_name2file_file, 13, Some(kmer_size)),
-                                  PredictionSettings::new(&prediction_name2file_file, &out_file, &None, &None, 100.0, false, None))
+                Task::KMerPredict(FeatureSettings::new(&training_name2file_file, 13, Some(kmer_size), &None),
+                                  PredictionSettings::new(&prediction_name2file_file, &out_file, &None, OptionalOutputs::default(), &None, 100.0, false, None))
+            },
+            Commands::MetaPredict {prediction_name2file_file, out_file, gz_values_file, optional_outputs, max_depth, training_name2file_file, genes, min_genes, gc_limit, taxdump} => {
+                Task::MetaPredict(FeatureSettings::new(&training_name2file_file, max_depth, None, &taxdump),
+                                   MetaPrediction::new(&prediction_name2file_file, &out_file, &gz_values_file, optional_outputs, genes, min_genes, gc_limit))
+            },
+            Commands::Evaluate {training_name2file_file, out_file, folds, leave_one_out, max_depth, kmer_size, gc_limit, rss, taxdump} => {
+                Task::Evaluate(FeatureSettings::new(&training_name2file_file, max_depth, Some(kmer_size), &taxdump),
+                               EvaluationSettings::new(&out_file, folds, leave_one_out, gc_limit, rss))
+            },
+            Commands::Score {predictions, truth, db, out_file, bootstrap, confidence, rss} => {
+                Task::Score(ScoreSettings::new(&db, &predictions, &truth, &out_file, bootstrap, confidence, rss))
+            },
+            Commands::Simulate {input, out_file, fragments, length, distribution, sigma, substitution_rate, indel_rate, rss} => {
+                Task::Simulate(SimulationSettings::new(&input, &out_file, fragments, length, distribution, sigma, substitution_rate, indel_rate, rss))
+            },
+            Commands::Calibrate {calibration_name2file_file, db, out_file, gc_limit} => {
+                Task::Calibrate(db, CalibrationSettings::new(&calibration_name2file_file, &out_file, gc_limit))
+            },
+            Commands::Distances {input, out_file, mode, format, max_depth, taxa, newick, tree_method, lca_labels} => {
+                // PHYLIP holds distances, which the asymmetric scores are not
+                if let (ScoreMode::Asymmetric, MatrixFormat::Phylip) = (mode, format) {
+                    clap::Error::raw(clap::error::ErrorKind::ArgumentConflict, "--format phylip writes symmetric distances, it can't be used with --mode asymmetric\n").exit();
+                }
+                Task::Distances(FeatureSettings::new(&input, max_depth, None, &None),
+                                DistanceSettings::new(&out_file, mode, format, taxa, newick, tree_method, lca_labels))
+            },
+            Commands::Profile {predictions, db, out_file, weights, unit, sample_id} => {
+                Task::Profile(ProfileSettings::new(&db, &predictions, &out_file, &weights, unit, sample_id))
             },
-            Commands::MetaPredict {prediction_name2file_file, out_file, gz_values_file, max_depth, training_name2file_file, genes, min_genes, gc_limit} => {
-                Task::MetaPredict(FeatureSettings::new(&training_name2file_file, max_depth, None),
-                                   MetaPrediction::new(&prediction_name2file_file, &out_file, &gz_values_file, genes, min_genes, gc_limit))
-            }
         }
     }
 }
@@ -437,6 +985,12 @@ pub enum UserTask {
     BuildKmer,
     KMerPredict,
     MetaPredict,
+    Evaluate,
+    Score,
+    Simulate,
+    Calibrate,
+    Distances,
+    Profile,
 }
 
 impl From<&Task> for UserTask {
@@ -449,6 +1003,12 @@ impl From<&Task> for UserTask {
             Task::BuildKmer(_) => { UserTask::BuildKmer }
             Task::KMerPredict(_, _) => { UserTask::KMerPredict }
             Task::MetaPredict(_, _) => { UserTask::MetaPredict }
+            Task::Evaluate(_, _) => { UserTask::Evaluate }
+            Task::Score(_) => { UserTask::Score }
+            Task::Simulate(_) => { UserTask::Simulate }
+            Task::Calibrate(_, _) => { UserTask::Calibrate }
+            Task::Distances(_, _) => { UserTask::Distances }
+            Task::Profile(_) => { UserTask::Profile }
         }
     }
 }
@@ -483,6 +1043,12 @@ impl Usage {
             Task::BuildKmer(s) => Some(&s.db),
             Task::KMerPredict(_, _) => None,
             Task::MetaPredict(_, _) => None,
+            Task::Evaluate(_, _) => None,
+            Task::Score(s) => Some(&s.db),
+            Task::Simulate(_) => None,
+            Task::Calibrate(s, _) => Some(s),
+            Task::Distances(_, _) => None,
+            Task::Profile(s) => Some(&s.db),
         }
     }
     pub fn get_task(&self) -> UserTask { UserTask::from(&self.task) }
@@ -495,6 +1061,12 @@ impl Usage {
             Task::BuildKmer(s) => Some(&s.training_name2file_file),
             Task::KMerPredict(s, _) => Some(&s.training_name2file_file),
             Task::MetaPredict(s, _) => Some(&s.training_name2file_file),
+            Task::Evaluate(s, _) => Some(&s.training_name2file_file),
+            Task::Score(_) => None,
+            Task::Simulate(_) => None,
+            Task::Calibrate(_, _) => None,
+            Task::Distances(s, _) => Some(&s.training_name2file_file),
+            Task::Profile(_) => None,
         }
     }
     pub fn get_prediction_name2file_file(&self) -> Option<&Path> {
@@ -506,6 +1078,12 @@ impl Usage {
             Task::BuildKmer(_) => None,
             Task::KMerPredict(_, s) => Some(&s.prediction_name2file_file),
             Task::MetaPredict(_, s) => Some(&s.prediction_name2file_file),
+            Task::Evaluate(_, _) => None,
+            Task::Score(_) => None,
+            Task::Simulate(_) => None,
+            Task::Calibrate(_, _) => None,
+            Task::Distances(_, _) => None,
+            Task::Profile(_) => None,
         }
     }
     pub fn get_out_file(&self) -> Option<&Path> {
@@ -517,6 +1095,21 @@ impl Usage {
             Task::BuildKmer(_) => None,
             Task::KMerPredict(_, s) => Some(&s.out_file),
             Task::MetaPredict(_, s) => Some(&s.out_file),
+            Task::Evaluate(_, s) => Some(&s.out_file),
+            Task::Score(s) => Some(&s.out_file),
+            Task::Simulate(s) => Some(&s.out_file),
+            Task::Calibrate(_, s) => Some(&s.out_file),
+            Task::Distances(_, s) => Some(&s.out_file),
+            Task::Profile(s) => Some(&s.out_file),
+        }
+    }
+
+    pub fn get_optional_outputs(&self) -> Option<&OptionalOutputs> {
+        match &self.task {
+            Task::DBPredict(_, _, s) => Some(&s.optional_outputs),
+            Task::Predict(_, s) => Some(&s.optional_outputs),
+            Task::MetaPredict(_, s) => Some(&s.optional_outputs),
+            _ => None,
         }
     }
 
@@ -529,6 +1122,12 @@ impl Usage {
             Task::BuildKmer(_) => None,
             Task::KMerPredict(_, s) => s.gz_values_file.as_deref(),
             Task::MetaPredict(_, s) => s.gz_values_file.as_deref(),
+            Task::Evaluate(_, _) => None,
+            Task::Score(_) => None,
+            Task::Simulate(_) => None,
+            Task::Calibrate(_, _) => None,
+            Task::Distances(_, _) => None,
+            Task::Profile(_) => None,
         }
     }
     pub fn get_max_depth(&self) -> Option<usize> {
@@ -540,9 +1139,39 @@ impl Usage {
                 Task::BuildKmer(_) => None,
                 Task::KMerPredict(_, _) => None,
                 Task::MetaPredict(s, _) => Some(s.max_depth),
+                Task::Evaluate(s, _) => Some(s.max_depth),
+                Task::Score(_) => None,
+                Task::Simulate(_) => None,
+                Task::Calibrate(_, _) => None,
+                Task::Distances(s, _) => Some(s.max_depth),
+                Task::Profile(_) => None,
             }
     }
     
+    pub fn get_validation_settings(&self) -> Option<&ValidationSettings> {
+        match &self.task {
+            Task::BuildDB(s) => Some(&s.validation),
+            _ => None,
+        }
+    }
+
+    pub fn get_novelty(&self) -> bool {
+        match &self.task {
+            Task::BuildDB(s) => s.novelty,
+            _ => false,
+        }
+    }
+
+    pub fn get_taxdump(&self) -> Option<&Path> {
+        match &self.task {
+            Task::BuildDB(s) => s.feature_settings.taxdump.as_deref(),
+            Task::Predict(s, _) => s.taxdump.as_deref(),
+            Task::MetaPredict(s, _) => s.taxdump.as_deref(),
+            Task::Evaluate(s, _) => s.taxdump.as_deref(),
+            _ => None,
+        }
+    }
+
     pub fn get_ani_calculator_tool(&self) -> AniCalculatorTool {
         match &self.task {
             Task::BuildDB(_) => None,
@@ -551,7 +1180,13 @@ impl Usage {
             Task::PrintKmer(_) => None,
             Task::BuildKmer(_) => None,
             Task::KMerPredict(_, _) => None,
-            Task::MetaPredict(_, _) => None
+            Task::MetaPredict(_, _) => None,
+            Task::Evaluate(_, _) => None,
+            Task::Score(_) => None,
+            Task::Simulate(_) => None,
+            Task::Calibrate(_, _) => None,
+            Task::Distances(_, _) => None,
+            Task::Profile(_) => None,
         }.unwrap_or_default()
     }
     pub fn get_version(&self) -> &str { self.run_settings.version }
@@ -567,6 +1202,12 @@ impl Usage {
             Task::BuildKmer(_) => None,
             Task::KMerPredict(_, _) => None,
             Task::MetaPredict(_, s) => s.gc_limit,
+            Task::Evaluate(_, s) => s.gc_limit,
+            Task::Score(_) => None,
+            Task::Simulate(_) => None,
+            Task::Calibrate(_, s) => s.gc_limit,
+            Task::Distances(_, _) => None,
+            Task::Profile(_) => None,
         }
     }
     pub fn get_ani_out_file(&self) -> Option<&Path> {
@@ -584,6 +1225,12 @@ impl Usage {
             Task::BuildKmer(_) => None,
             Task::KMerPredict(_, _) => None,
             Task::MetaPredict(_, _) => None,
+            Task::Evaluate(_, _) => None,
+            Task::Score(_) => None,
+            Task::Simulate(_) => None,
+            Task::Calibrate(_, _) => None,
+            Task::Distances(_, _) => None,
+            Task::Profile(_) => None,
         }
     }
     pub fn get_kmer_size(&self) -> Option<usize> {
@@ -595,6 +1242,12 @@ impl Usage {
             Task::BuildKmer(s) => Some(s.kmer_size),
             Task::KMerPredict(s, _) => s.kmer_size,
             Task::MetaPredict(_, _) => None,
+            Task::Evaluate(s, _) => s.kmer_size,
+            Task::Score(_) => None,
+            Task::Simulate(_) => None,
+            Task::Calibrate(_, _) => None,
+            Task::Distances(_, _) => None,
+            Task::Profile(_) => None,
         }
     }
 
@@ -627,6 +1280,55 @@ impl Usage {
         }
     }
 
+    pub fn get_evaluation_parameters(&self) -> Option<EvaluationParameters> {
+        match &self.task {
+            Task::Evaluate(f, s) => Some(EvaluationParameters {
+                split: s.split,
+                max_depth: f.max_depth,
+                buffer_size: self.run_settings.buffer_size,
+                kmer_size: f.kmer_size,
+                gc_limit: s.gc_limit,
+                random_state: s.random_state,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn get_score_settings(&self) -> Option<&ScoreSettings> {
+        match &self.task {
+            Task::Score(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn get_simulation_settings(&self) -> Option<&SimulationSettings> {
+        match &self.task {
+            Task::Simulate(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn get_calibration_name2file_file(&self) -> Option<&Path> {
+        match &self.task {
+            Task::Calibrate(_, s) => Some(&s.calibration_name2file_file),
+            _ => None,
+        }
+    }
+
+    pub fn get_profile_settings(&self) -> Option<&ProfileSettings> {
+        match &self.task {
+            Task::Profile(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn get_distance_settings(&self) -> Option<&DistanceSettings> {
+        match &self.task {
+            Task::Distances(_, s) => Some(s),
+            _ => None,
+        }
+    }
+
     pub fn get_meta(&self) -> bool {
         match &self.task {
             Task::PrintKmer(s) => s.meta,
@@ -1,15 +1,31 @@
 //  Created by Or Leibovich, Yochai Meir, and Itai Sharon, last updated on 2023/08/31
 
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use std::path::Path;
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use crate::calibration::Calibration;
 use crate::classifier::Classifier;
+use crate::ncbi_taxdump::TaxonLineage;
+use crate::novelty::NoveltyProfile;
+use crate::taxonomy::Taxonomy;
+use hashbrown::HashMap;
 use serde::{Serialize, Deserialize};
 use gzp::{ZWriter, deflate::Bgzf, Compression};
 use gzp::par::compress::{ParCompress, ParCompressBuilder};
 use gzp::par::decompress::{ParDecompressBuilder, ParDecompress};
 
+// A section written after the classifier, None if the database ends before it
+fn deserialize_section<T: serde::de::DeserializeOwned, R: std::io::Read>(reader: &mut R) -> Result<Option<T>, Box<dyn std::error::Error>> {
+    match bincode::deserialize_from::<_, T>(reader) {
+        Ok(section) => Ok(Some(section)),
+        Err(e) => match *e {
+            bincode::ErrorKind::Io(ref io_error) if io_error.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            _ => Err(e),
+        },
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct DatabaseInternal {
     classifier: Classifier,
@@ -32,15 +48,42 @@ impl DatabaseInternal {
             .num_threads(threads_limit)?
             .from_writer(BufWriter::new(File::create(destination)?));
         bincode::serialize_into(&mut gzw, &self)?;
+        // Written after the classifier, so databases without it can still be read
+        bincode::serialize_into(&mut gzw, self.classifier.get_model2taxonomy())?;
+        bincode::serialize_into(&mut gzw, self.classifier.get_model2lineage())?;
+        bincode::serialize_into(&mut gzw, &self.classifier.get_calibration())?;
+        bincode::serialize_into(&mut gzw, &self.classifier.get_model2novelty())?;
+        bincode::serialize_into(&mut gzw, &self.classifier.get_model2other_fasta_paths())?;
         gzw.finish()?;
         Ok(())
     }
 
     fn load(source: &Path, threads_limit: usize) -> Result<Self, Box<dyn std::error::Error>> {
-        let bin_self_reader: ParDecompress<Bgzf> = ParDecompressBuilder::new()
+        let mut bin_self_reader: ParDecompress<Bgzf> = ParDecompressBuilder::new()
             .num_threads(threads_limit)?
             .from_reader(BufReader::new(File::open(source)?));
-        Ok(bincode::deserialize_from(bin_self_reader)?)
+        let mut database: Self = bincode::deserialize_from(&mut bin_self_reader)?;
+        // Databases created before models had taxonomies end here
+        if let Some(model2taxonomy) = deserialize_section::<HashMap<String, Taxonomy>, _>(&mut bin_self_reader)? {
+            database.classifier.set_model2taxonomy(model2taxonomy);
+        }
+        // And these before models had NCBI lineages
+        if let Some(model2lineage) = deserialize_section::<HashMap<String, TaxonLineage>, _>(&mut bin_self_reader)? {
+            database.classifier.set_model2lineage(model2lineage);
+        }
+        // And these before calibration
+        if let Some(calibration) = deserialize_section::<Option<Calibration>, _>(&mut bin_self_reader)? {
+            database.classifier.set_calibration(calibration);
+        }
+        // And these before novelty profiles
+        if let Some(model2novelty) = deserialize_section::<HashMap<String, NoveltyProfile>, _>(&mut bin_self_reader)? {
+            database.classifier.set_model2novelty(model2novelty);
+        }
+        // And these before models trained on several FASTA files
+        if let Some(model2other_fasta_paths) = deserialize_section::<HashMap<String, Vec<PathBuf>>, _>(&mut bin_self_reader)? {
+            database.classifier.set_model2other_fasta_paths(model2other_fasta_paths);
+        }
+        Ok(database)
     }
 }
 
@@ -85,4 +128,34 @@ impl Database {
     pub fn get_classifier(&self) -> &Classifier { &self.classifier }
 
     pub fn arc_classifier(&self) -> Arc<Classifier> { self.classifier.clone() }
+
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        Arc::get_mut(&mut self.classifier)
+            .expect("E: Trying to calibrate a classifier that is in use. This should never happen.")
+            .set_calibration(Some(calibration));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use crate::database::Database;
+    use crate::samples_file_reader::Sample;
+    use crate::use_classifier::create_lz_classifier;
+
+    #[test]
+    fn model_fasta_paths() {
+        let database_path = PathBuf::from("../tests/multi_fasta_training.gzdb");
+        let classifier = create_lz_classifier(None, 12, &PathBuf::from("../tests/multi_fasta_training.txt"), 512, &None, None);
+        Database::new(classifier, 12, None).save(&database_path, 1).unwrap();
+
+        // Every FASTA of a model, e.g. for novelty and evaluation, not only the first
+        let database = Database::load(&database_path, 1).unwrap();
+        let mut models = database.get_classifier().into_iter().collect::<Vec<Sample>>();
+        models.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+        assert_eq!(models.iter().map(|s| (s.get_name(), s.get_paths().len())).collect::<Vec<_>>(),
+                   vec![("Actinomyces_graevenitzii", 2), ("Neisseria_flavescens_A", 2)]);
+        assert_eq!(models[0].get_paths()[1], Path::new("../tests/../data/FerrettiP_2018__CA_C10002MS2015SA_t0M15__bin.17_genes.fna.gz"));
+        std::fs::remove_file(&database_path).unwrap();
+    }
 }
\ No newline at end of file
@@ -0,0 +1,345 @@
+// Cross-validation of GeneZip on a taxonomy-annotated sample list.
+// Each fold trains a classifier on the other folds, and the held-out genomes are assigned the taxonomy of their best hit.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use serde::Serialize;
+use crate::cached_fasta_nucleutide_iterator::CachedFastaNucltudiesIterator;
+use crate::classifier::Classifier;
+use crate::lz78::LenBases;
+use crate::samples_file_reader::Sample;
+use crate::taxonomy::{TaxonomicRank, Taxonomy};
+
+#[derive(Clone, Copy, Debug)]
+pub enum EvaluationSplit {
+    // Genomes are shuffled and dealt into k folds
+    KFold(usize),
+    // Every genome is a fold of its own
+    LeaveOneOut,
+}
+
+impl Display for EvaluationSplit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvaluationSplit::KFold(k) => write!(f, "{}-fold", k),
+            EvaluationSplit::LeaveOneOut => write!(f, "leave-one-out"),
+        }
+    }
+}
+
+pub struct EvaluationParameters {
+    pub split: EvaluationSplit,
+    pub max_depth: usize,
+    pub buffer_size: usize,
+    pub kmer_size: Option<usize>,
+    pub gc_limit: Option<f64>,
+    pub random_state: u64,
+}
+
+// A held-out genome and the taxonomy of its best hit, None if no model was scored (e.g. all filtered by %GC)
+#[derive(Serialize)]
+pub struct HeldOutPrediction {
+    fold: usize,
+    genome: String,
+    taxonomy: String,
+    best_hit: Option<String>,
+    predicted_taxonomy: Option<String>,
+    // The deepest rank both taxonomies share
+    shared_rank: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RankAccuracy {
+    rank: String,
+    // Genomes whose taxonomy names this rank
+    evaluated: usize,
+    correct: usize,
+    accuracy: Option<f64>,
+}
+
+impl RankAccuracy {
+    pub fn get_rank(&self) -> &str { &self.rank }
+    pub fn get_evaluated(&self) -> usize { self.evaluated }
+    pub fn get_correct(&self) -> usize { self.correct }
+    pub fn get_accuracy(&self) -> Option<f64> { self.accuracy }
+}
+
+#[derive(Serialize)]
+pub struct FoldResult {
+    fold: usize,
+    genomes: usize,
+    accuracy: Vec<RankAccuracy>,
+}
+
+// How many genomes of a true taxon were assigned to a predicted one, at one rank
+#[derive(Serialize)]
+pub struct Confusion {
+    rank: String,
+    truth: String,
+    predicted: String,
+    count: usize,
+}
+
+#[derive(Serialize)]
+pub struct EvaluationReport {
+    split: String,
+    genomes: usize,
+    accuracy: Vec<RankAccuracy>,
+    folds: Vec<FoldResult>,
+    confusion: Vec<Confusion>,
+    predictions: Vec<HeldOutPrediction>,
+}
+
+struct Outcome {
+    fold: usize,
+    truth: Taxonomy,
+    best_hit: Option<String>,
+    predicted: Option<Taxonomy>,
+}
+
+// Indices of the held-out samples of each fold
+fn make_folds(samples: usize, split: EvaluationSplit, random_state: u64) -> Vec<Vec<usize>> {
+    match split {
+        EvaluationSplit::LeaveOneOut => (0..samples).map(|i| vec![i]).collect(),
+        EvaluationSplit::KFold(k) => {
+            let mut order = (0..samples).collect::<Vec<usize>>();
+            order.shuffle(&mut StdRng::seed_from_u64(random_state));
+            let mut folds = vec![Vec::new(); k];
+            for (i, sample) in order.into_iter().enumerate() {
+                folds[i % k].push(sample);
+            }
+            folds.iter_mut().for_each(|fold| fold.sort());
+            folds
+        },
+    }
+}
+
+fn rank_accuracy<'a>(outcomes: impl Iterator<Item=&'a Outcome> + Clone) -> Vec<RankAccuracy> {
+    TaxonomicRank::down_iterator()
+        .map(|rank| {
+            let evaluated = outcomes.clone().filter(|o| o.truth.has_rank(&rank)).collect::<Vec<&Outcome>>();
+            let correct = evaluated.iter()
+                .filter(|o| o.predicted.as_ref().is_some_and(|p| p.has_rank(&rank) && p.equal_to_rank(&o.truth, &rank)))
+                .count();
+            RankAccuracy {
+                rank: rank.to_string(),
+                evaluated: evaluated.len(),
+                correct,
+                accuracy: if evaluated.is_empty() { None } else { Some(correct as f64 / evaluated.len() as f64) },
+            }
+        })
+        .collect()
+}
+
+fn confusion(outcomes: &[Outcome]) -> Vec<Confusion> {
+    let mut counts: BTreeMap<(TaxonomicRank, String, String), usize> = BTreeMap::new();
+    for outcome in outcomes {
+        for rank in TaxonomicRank::down_iterator().filter(|rank| outcome.truth.has_rank(rank)) {
+            let truth = outcome.truth.get_taxa(&rank).cloned().unwrap_or_default();
+            let predicted = outcome.predicted.as_ref()
+                .and_then(|p| p.get_taxa(&rank))
+                .filter(|name| !name.is_empty())
+                .cloned()
+                .unwrap_or(String::from("NA"));
+            *counts.entry((rank, truth, predicted)).or_default() += 1;
+        }
+    }
+
+    // Top-down, as TaxonomicRank orders the species first
+    let mut confusion = counts.into_iter().collect::<Vec<_>>();
+    confusion.sort_by(|((ra, ta, pa), _), ((rb, tb, pb), _)| rb.cmp(ra).then(ta.cmp(tb)).then(pa.cmp(pb)));
+    confusion.into_iter()
+        .map(|((rank, truth, predicted), count)| Confusion { rank: rank.to_string(), truth, predicted, count })
+        .collect()
+}
+
+fn check_samples(samples: &[Sample], split: EvaluationSplit) -> std::io::Result<()> {
+    if let Some(sample) = samples.iter().find(|s| s.get_taxonomy().is_none()) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("E: sample '{}' has no taxonomy, evaluation needs the taxonomy of every sample", sample.get_name())));
+    }
+    let mut names = HashSet::new();
+    if let Some(sample) = samples.iter().find(|s| !names.insert(s.get_name())) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("E: sample name '{}' is used more than once, names must be unique as each sample is a model", sample.get_name())));
+    }
+    match split {
+        EvaluationSplit::KFold(k) if k < 2 || k > samples.len() =>
+            Err(Error::new(ErrorKind::InvalidInput, format!("E: can't split {} samples into {} folds, use 2 to {} folds", samples.len(), k, samples.len()))),
+        EvaluationSplit::LeaveOneOut if samples.len() < 2 =>
+            Err(Error::new(ErrorKind::InvalidInput, "E: leave-one-out needs at least 2 samples")),
+        _ => Ok(()),
+    }
+}
+
+pub fn evaluate(samples: &[Sample], parameters: &EvaluationParameters) -> std::io::Result<EvaluationReport> {
+    check_samples(samples, parameters.split)?;
+    let name2taxonomy = samples.iter()
+        .filter_map(|s| s.get_taxonomy().as_ref().map(|t| (s.get_name(), t)))
+        .collect::<HashMap<&str, &Taxonomy>>();
+
+    let folds = make_folds(samples.len(), parameters.split, parameters.random_state);
+    let mut outcomes = Vec::new();
+    for (fold, held_out) in folds.iter().enumerate() {
+        let training = (0..samples.len())
+            .filter(|i| held_out.binary_search(i).is_err())
+            .map(|i| samples[i].clone())
+            .collect::<Vec<Sample>>();
+        let mut classifier = Classifier::new(LenBases::new(parameters.max_depth));
+        classifier.batch_add_samples(&training, parameters.max_depth, parameters.buffer_size, &parameters.kmer_size);
+
+        let fold_outcomes = held_out.par_iter()
+            .map(|&i| {
+                let sample = &samples[i];
+                let sequence = CachedFastaNucltudiesIterator::from_paths(sample.get_paths(), parameters.buffer_size);
+                let prediction = classifier.predict(sequence, parameters.gc_limit, &parameters.kmer_size, false);
+                let best_hit = Classifier::get_best_model_name(&prediction.0).cloned();
+                Outcome {
+                    fold: fold + 1,
+                    truth: sample.get_taxonomy().clone().unwrap_or_else(|| unreachable!("samples without taxonomy are refused")),
+                    predicted: best_hit.as_ref().and_then(|name| name2taxonomy.get(name.as_str())).map(|&t| t.clone()),
+                    best_hit,
+                }
+            })
+            .collect::<Vec<Outcome>>();
+        outcomes.extend(fold_outcomes);
+    }
+
+    let held_out_names = folds.iter().flatten().map(|&i| samples[i].get_name());
+    let predictions = outcomes.iter()
+        .zip(held_out_names)
+        .map(|(outcome, genome)| HeldOutPrediction {
+            fold: outcome.fold,
+            genome: genome.to_string(),
+            taxonomy: outcome.truth.to_string(),
+            best_hit: outcome.best_hit.clone(),
+            predicted_taxonomy: outcome.predicted.as_ref().map(|t| t.to_string()),
+            shared_rank: outcome.predicted.as_ref().and_then(|p| p.lcu(&outcome.truth)).map(|r| r.to_string()),
+        })
+        .collect();
+
+    Ok(EvaluationReport {
+        split: parameters.split.to_string(),
+        genomes: samples.len(),
+        accuracy: rank_accuracy(outcomes.iter()),
+        folds: (1..=folds.len())
+            .map(|fold| FoldResult {
+                fold,
+                genomes: folds[fold - 1].len(),
+                accuracy: rank_accuracy(outcomes.iter().filter(move |o| o.fold == fold)),
+            })
+            .collect(),
+        confusion: confusion(&outcomes),
+        predictions,
+    })
+}
+
+fn na<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map_or(String::from("NA"), |v| v.to_string())
+}
+
+impl EvaluationReport {
+    pub fn get_accuracy(&self) -> &[RankAccuracy] { &self.accuracy }
+    pub fn get_folds(&self) -> &[FoldResult] { &self.folds }
+    pub fn get_predictions(&self) -> &[HeldOutPrediction] { &self.predictions }
+
+    pub fn get_paths(prefix: &Path) -> [PathBuf; 4] {
+        ["predictions.tsv", "accuracy.tsv", "confusion.tsv", "json"]
+            .map(|suffix| PathBuf::from(format!("{}.{}", prefix.display(), suffix)))
+    }
+
+    /*
+    Writes <prefix>.predictions.tsv, <prefix>.accuracy.tsv (overall, as fold 'all', and per fold),
+    <prefix>.confusion.tsv and everything together as <prefix>.json
+     */
+    pub fn write(&self, prefix: &Path) -> std::io::Result<()> {
+        let [predictions_path, accuracy_path, confusion_path, json_path] = Self::get_paths(prefix);
+
+        let mut stream = BufWriter::new(File::create(predictions_path)?);
+        writeln!(stream, "fold\tgenome\ttaxonomy\tbest_hit\tpredicted_taxonomy\tshared_rank")?;
+        for p in &self.predictions {
+            writeln!(stream, "{}\t{}\t{}\t{}\t{}\t{}", p.fold, p.genome, p.taxonomy, na(&p.best_hit), na(&p.predicted_taxonomy), na(&p.shared_rank))?;
+        }
+        stream.flush()?;
+
+        let mut stream = BufWriter::new(File::create(accuracy_path)?);
+        writeln!(stream, "fold\tgenomes\trank\tevaluated\tcorrect\taccuracy")?;
+        let overall = std::iter::once((String::from("all"), self.genomes, &self.accuracy));
+        for (fold, genomes, accuracy) in overall.chain(self.folds.iter().map(|f| (f.fold.to_string(), f.genomes, &f.accuracy))) {
+            for a in accuracy {
+                writeln!(stream, "{}\t{}\t{}\t{}\t{}\t{}", fold, genomes, a.rank, a.evaluated, a.correct, a.accuracy.map_or(String::from("NA"), |v| format!("{:.5}", v)))?;
+            }
+        }
+        stream.flush()?;
+
+        let mut stream = BufWriter::new(File::create(confusion_path)?);
+        writeln!(stream, "rank\ttruth\tpredicted\tcount")?;
+        for c in &self.confusion {
+            writeln!(stream, "{}\t{}\t{}\t{}", c.rank, c.truth, c.predicted, c.count)?;
+        }
+        stream.flush()?;
+
+        let mut stream = BufWriter::new(File::create(json_path)?);
+        serde_json::to_writer_pretty(&mut stream, self).map_err(Error::other)?;
+        writeln!(stream)?;
+        stream.flush()
+    }
+}
+
+impl Display for EvaluationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} evaluation of {} genomes", self.split, self.genomes)?;
+        for a in &self.accuracy {
+            writeln!(f, "{}\t{}/{}\t{}", a.rank, a.correct, a.evaluated, a.accuracy.map_or(String::from("NA"), |v| format!("{:.3}", v)))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::evaluation::{evaluate, make_folds, EvaluationParameters, EvaluationSplit};
+    use crate::samples_file_reader::{Sample, SampleSource};
+
+    #[test]
+    fn folds() {
+        let folds = make_folds(7, EvaluationSplit::KFold(3), 1);
+        assert_eq!(folds.iter().map(|f| f.len()).collect::<Vec<usize>>(), vec![3, 2, 2]);
+        let mut all = folds.concat();
+        all.sort();
+        assert_eq!(all, (0..7).collect::<Vec<usize>>());
+        assert_eq!(folds, make_folds(7, EvaluationSplit::KFold(3), 1));
+        assert_eq!(make_folds(3, EvaluationSplit::LeaveOneOut, 1), vec![vec![0], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn leave_one_out() {
+        let samples = SampleSource::new(Path::new("../tests/taxonomy_test_training.txt"), true)
+            .into_iter()
+            .collect::<Result<Vec<Sample>, _>>()
+            .unwrap();
+        let parameters = EvaluationParameters {
+            split: EvaluationSplit::LeaveOneOut,
+            max_depth: 11,
+            buffer_size: 512,
+            kmer_size: None,
+            gc_limit: None,
+            random_state: 1,
+        };
+        let report = evaluate(&samples, &parameters).unwrap();
+        assert_eq!(report.get_predictions().len(), 3);
+        assert_eq!(report.get_folds().len(), 3);
+        // All genomes are bacteria, but no held-out species is left in its training set
+        assert_eq!(report.get_accuracy()[0].get_rank(), "Domain");
+        assert_eq!((report.get_accuracy()[0].get_evaluated(), report.get_accuracy()[0].get_correct()), (3, 3));
+        assert_eq!(report.get_accuracy()[6].get_correct(), 0);
+
+        assert!(evaluate(&samples, &EvaluationParameters { split: EvaluationSplit::KFold(4), ..parameters }).is_err());
+    }
+}
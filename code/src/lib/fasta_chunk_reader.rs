@@ -0,0 +1,198 @@
+// Reads a FASTA in contiguous chunks of cleaned nucleotides, rather than a byte at a time as FastaNucltudiesIterator does.
+// Sequence lines are copied in bulk from the (decompressed) stream buffer, without line endings and whitespace, in upper-case.
+// Record boundaries are reported apart from the nucleotides, where FastaNucltudiesIterator yields an 'N'.
+// Several FASTA files may be read one after the other, as MultiFastaNucltudiesIterator does.
+
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use crate::fasta_nucleutide_iterator::FastaNucltudiesIterator;
+
+pub enum FastaChunk<'a> {
+    /// A record starts, with its ID (the header up to the first whitespace)
+    Record(&'a [u8]),
+    /// Nucleotides of the current record, a record may span several chunks
+    Sequence(&'a [u8]),
+}
+
+// What the next chunk is, decided before borrowing the chunk or the header
+enum NextChunk {
+    End,
+    Record(usize),
+    Sequence,
+}
+
+pub struct FastaChunkReader {
+    path: PathBuf,
+    // The FASTA files read after the current one
+    next_paths: VecDeque<PathBuf>,
+    stream: Box<dyn BufRead + Sync>,
+    chunk_size: usize,
+    chunk: Vec<u8>,
+    header: Vec<u8>,
+    line_start: bool,
+}
+
+impl FastaChunkReader {
+    pub fn new(fasta: &Path, chunk_size: usize) -> Self {
+        Self::from_paths(&[fasta.to_path_buf()], chunk_size)
+    }
+
+    // The FASTA files of a sample, each starts with a header so a record boundary separates them
+    pub fn from_paths(fastas: &[PathBuf], chunk_size: usize) -> Self {
+        let chunk_size = chunk_size.max(1);
+        let mut paths = VecDeque::from(fastas.to_vec());
+        let fasta = paths.pop_front().unwrap_or_else(|| panic!("E: Tried to read FASTA chunks without any FASTA file. This should never happen."));
+        FastaChunkReader {
+            stream: FastaNucltudiesIterator::open_fasta(&fasta),
+            path: fasta,
+            next_paths: paths,
+            chunk_size,
+            chunk: Vec::with_capacity(chunk_size),
+            header: Vec::new(),
+            line_start: true,
+        }
+    }
+
+    pub fn get_path(&self) -> &Path { &self.path }
+
+    // The rest of the header line, its '>' was already consumed
+    fn read_header(&mut self) {
+        self.header.clear();
+        loop {
+            let buffer = match self.stream.fill_buf() {
+                Ok(buffer) => buffer,
+                Err(e) => panic!("ERROR: Failed reading fasta file at '{}', got {:?}", self.path.display(), e),
+            };
+            if buffer.is_empty() {
+                break;
+            }
+            match buffer.iter().position(|&c| c == b'\n') {
+                Some(end) => {
+                    self.header.extend_from_slice(&buffer[..end]);
+                    self.stream.consume(end + 1);
+                    break;
+                },
+                None => {
+                    let read = buffer.len();
+                    self.header.extend_from_slice(buffer);
+                    self.stream.consume(read);
+                },
+            }
+        }
+        self.line_start = true;
+    }
+
+    fn advance(&mut self) -> NextChunk {
+        self.chunk.clear();
+        loop {
+            let chunk_size = self.chunk_size;
+            let room = chunk_size - self.chunk.len();
+            let line_start = self.line_start;
+            let buffer = match self.stream.fill_buf() {
+                Ok(buffer) => buffer,
+                Err(e) => panic!("ERROR: Failed reading fasta file at '{}', got {:?}", self.path.display(), e),
+            };
+            if buffer.is_empty() {
+                if !self.chunk.is_empty() {
+                    return NextChunk::Sequence;
+                }
+                match self.next_paths.pop_front() {
+                    Some(fasta) => {
+                        self.stream = FastaNucltudiesIterator::open_fasta(&fasta);
+                        self.path = fasta;
+                        self.line_start = true;
+                        continue;
+                    },
+                    None => return NextChunk::End,
+                }
+            }
+            if line_start && buffer[0] == b'>' {
+                if !self.chunk.is_empty() {
+                    return NextChunk::Sequence;
+                }
+                self.stream.consume(1);
+                self.read_header();
+                return NextChunk::Record(self.header.iter().position(|c| c.is_ascii_whitespace()).unwrap_or(self.header.len()));
+            }
+
+            // Up to the end of the line, so a header is always seen at the start of a buffer
+            let (taken, line_end) = match buffer.iter().take(room).position(|&c| c == b'\n') {
+                Some(end) => (end + 1, true),
+                None => (buffer.len().min(room), false),
+            };
+            self.chunk.extend(buffer[..taken].iter()
+                .filter(|c| !c.is_ascii_whitespace())
+                .map(|c| c & 0b11011111)); // this maps low case letters to capital, and capital to self.
+            self.stream.consume(taken);
+            self.line_start = line_end;
+            if self.chunk.len() >= chunk_size {
+                return NextChunk::Sequence;
+            }
+        }
+    }
+
+    /// The next chunk, None at the end of the FASTA. Sequence chunks hold up-to chunk_size nucleotides.
+    pub fn next_chunk(&mut self) -> Option<FastaChunk<'_>> {
+        match self.advance() {
+            NextChunk::End => None,
+            NextChunk::Record(id_end) => Some(FastaChunk::Record(&self.header[..id_end])),
+            NextChunk::Sequence => Some(FastaChunk::Sequence(&self.chunk)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use crate::fasta_chunk_reader::{FastaChunk, FastaChunkReader};
+    use crate::fasta_nucleutide_iterator::{FastaNucltudiesIterator, MultiFastaNucltudiesIterator};
+
+    #[test]
+    fn chunks_match_nucleotides() {
+        for fasta in ["../tests/presentation_test_two_seq.fna", "../tests/gc_tests.fna", "../data/HMP_2012__SRS019219__bin.33_genes.fna.gz"] {
+            let fasta = Path::new(fasta);
+            // A record boundary is an 'N' for the byte iterator
+            let mut nucleotides = Vec::new();
+            let mut records = 0;
+            let mut reader = FastaChunkReader::new(fasta, 5);
+            while let Some(chunk) = reader.next_chunk() {
+                match chunk {
+                    FastaChunk::Record(_) => {
+                        records += 1;
+                        nucleotides.push(b'N');
+                    },
+                    FastaChunk::Sequence(sequence) => {
+                        assert!(!sequence.is_empty() && sequence.len() <= 5);
+                        nucleotides.extend_from_slice(sequence);
+                    },
+                }
+            }
+            assert!(records > 0);
+            assert_eq!(nucleotides, FastaNucltudiesIterator::new(fasta, 1024).collect::<Vec<u8>>(), "{}", fasta.display());
+        }
+
+        let mut reader = FastaChunkReader::new(Path::new("../tests/presentation_test_two_seq.fna"), 1024);
+        let mut ids = Vec::new();
+        while let Some(chunk) = reader.next_chunk() {
+            if let FastaChunk::Record(id) = chunk {
+                ids.push(String::from_utf8_lossy(id).to_string());
+            }
+        }
+        assert_eq!(ids, vec!["test", "test2"]);
+    }
+
+    #[test]
+    fn several_fastas() {
+        let paths = [PathBuf::from("../tests/presentation_test_two_seq.fna"), PathBuf::from("../tests/gc_tests.fna")];
+        let mut nucleotides = Vec::new();
+        let mut reader = FastaChunkReader::from_paths(&paths, 3);
+        while let Some(chunk) = reader.next_chunk() {
+            match chunk {
+                FastaChunk::Record(_) => nucleotides.push(b'N'),
+                FastaChunk::Sequence(sequence) => nucleotides.extend_from_slice(sequence),
+            }
+        }
+        assert_eq!(nucleotides, MultiFastaNucltudiesIterator::new(&paths, 1024).collect::<Vec<u8>>());
+    }
+}
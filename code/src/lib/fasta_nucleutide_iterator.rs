@@ -1,11 +1,42 @@
 //  Created by Or Leibovich, Yochai Meir, and Itai Sharon, last updated on 2023/08/31
 
-use std::ffi::OsStr;
 use std::fmt::{Display, Formatter};
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
+use std::io::{BufRead, BufReader, Cursor, Read};
 use std::path::{Path, PathBuf};
+use bzip2::bufread::MultiBzDecoder;
 use flate2::bufread::MultiGzDecoder;
+use xz2::bufread::XzDecoder;
+use crate::samples_file_reader::is_stdin;
+
+// Compression of a FASTA file, detected by its magic bytes so misnamed files are read as well
+#[derive(Debug, PartialEq)]
+enum FastaCompression {
+    Plain,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+// Bytes needed to tell the compression, the longest magic number is xz's
+const MAGIC_LENGTH: u64 = 6;
+
+impl FastaCompression {
+    fn detect(header: &[u8]) -> Self {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            FastaCompression::Gzip
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            FastaCompression::Zstd
+        } else if header.starts_with(b"BZh") {
+            FastaCompression::Bzip2
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            FastaCompression::Xz
+        } else {
+            FastaCompression::Plain
+        }
+    }
+}
 
 pub struct FastaNucltudiesIterator {
     path: PathBuf,
@@ -32,15 +63,32 @@ impl FastaNucltudiesIterator {
         }
     }
 
-    fn open_fasta(fasta: &Path) -> Box<dyn BufRead + Sync> {
-        let f = BufReader::new(match File::open(fasta) {
-            Err(e) => panic!("E: Can not open fasta file at '{}', got '{}'", fasta.display(), e),
-            Ok(f) => f,
-        });
-        if Some(OsStr::new("gz")) == fasta.extension() {
-            Box::new(BufReader::new(MultiGzDecoder::new(f)))
+    pub(crate) fn open_fasta(fasta: &Path) -> Box<dyn BufRead + Sync> {
+        let f: Box<dyn BufRead + Sync> = if is_stdin(fasta) {
+            Box::new(BufReader::new(std::io::stdin()))
         } else {
-            Box::new(f)
+            Box::new(BufReader::new(match File::open(fasta) {
+                Err(e) => panic!("E: Can not open fasta file at '{}', got '{}'", fasta.display(), e),
+                Ok(f) => f,
+            }))
+        };
+        Self::decompress(f, fasta)
+    }
+
+    // A pipe may return less than the magic number in a single read, so it is read in full and put back in front of the stream
+    fn decompress(mut f: Box<dyn BufRead + Sync>, fasta: &Path) -> Box<dyn BufRead + Sync> {
+        let mut magic = Vec::with_capacity(MAGIC_LENGTH as usize);
+        if let Err(e) = (&mut f).take(MAGIC_LENGTH).read_to_end(&mut magic) {
+            panic!("E: Can not read fasta file at '{}', got '{}'", fasta.display(), e);
+        }
+        let compression = FastaCompression::detect(&magic);
+        let f: Box<dyn BufRead + Sync> = Box::new(Cursor::new(magic).chain(f));
+        match compression {
+            FastaCompression::Plain => f,
+            FastaCompression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(f))),
+            FastaCompression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(f).unwrap_or_else(|e| panic!("E: Can not decompress the zstd fasta file at '{}', got '{}'", fasta.display(), e)))),
+            FastaCompression::Bzip2 => Box::new(BufReader::new(MultiBzDecoder::new(f))),
+            FastaCompression::Xz => Box::new(BufReader::new(XzDecoder::new_multi_decoder(f))),
         }
     }
 
@@ -117,4 +165,104 @@ impl Display for FastaNucltudiesIterator {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.path.display())
     }
-}
\ No newline at end of file
+}
+
+// Several FASTA files read as one sequence, each file starts with a header so the files are separated by an 'N'.
+pub struct MultiFastaNucltudiesIterator {
+    paths: Vec<PathBuf>,
+    buffer_size: usize,
+    current_index: usize,
+    current: Option<FastaNucltudiesIterator>,
+}
+
+impl MultiFastaNucltudiesIterator {
+    pub fn new(fastas: &[PathBuf], buffer_size: usize) -> Self {
+        MultiFastaNucltudiesIterator {
+            paths: fastas.to_vec(),
+            buffer_size,
+            current_index: 0,
+            current: fastas.first().map(|fasta| FastaNucltudiesIterator::new(fasta, buffer_size)),
+        }
+    }
+
+    pub fn get_paths(&self) -> &[PathBuf] { &self.paths }
+}
+
+impl Clone for MultiFastaNucltudiesIterator {
+    fn clone(&self) -> Self {
+        Self::new(&self.paths, self.buffer_size)
+    }
+}
+
+impl Iterator for MultiFastaNucltudiesIterator {
+    type Item = u8;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.current.as_mut()?.next() {
+                Some(nuc) => return Some(nuc),
+                None => {
+                    self.current_index += 1;
+                    self.current = self.paths.get(self.current_index).map(|fasta| FastaNucltudiesIterator::new(fasta, self.buffer_size));
+                },
+            }
+        }
+    }
+}
+
+impl Display for MultiFastaNucltudiesIterator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.paths.iter().map(|p| p.display().to_string()).collect::<Vec<String>>().join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Cursor, Read, Write};
+    use std::path::PathBuf;
+    use crate::fasta_nucleutide_iterator::{FastaCompression, FastaNucltudiesIterator, MultiFastaNucltudiesIterator};
+
+    #[test]
+    fn multi_fasta() {
+        let paths = vec![PathBuf::from("../tests/presentation_train.fna"), PathBuf::from("../tests/paper_train.fna")];
+        let expected = FastaNucltudiesIterator::new(&paths[0], 7).chain(FastaNucltudiesIterator::new(&paths[1], 7)).collect::<Vec<u8>>();
+        assert_eq!(MultiFastaNucltudiesIterator::new(&paths, 7).collect::<Vec<u8>>(), expected);
+        assert_eq!(MultiFastaNucltudiesIterator::new(&paths[1..], 7).filter(|&n| n == b'N').count(), 1);
+        assert_eq!(MultiFastaNucltudiesIterator::new(&[], 7).count(), 0);
+    }
+    #[test]
+    fn compressed_fasta() {
+        let plain = std::fs::read("../tests/gc_tests.fna").unwrap();
+        let expected = FastaNucltudiesIterator::new(&PathBuf::from("../tests/gc_tests.fna"), 7).collect::<Vec<u8>>();
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&plain).unwrap();
+        let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bzip2.write_all(&plain).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(&plain).unwrap();
+        let compressed = [
+            (FastaCompression::Gzip, gzip.finish().unwrap()),
+            (FastaCompression::Zstd, zstd::encode_all(plain.as_slice(), 0).unwrap()),
+            (FastaCompression::Bzip2, bzip2.finish().unwrap()),
+            (FastaCompression::Xz, xz.finish().unwrap()),
+        ];
+        for (compression, bytes) in compressed {
+            assert_eq!(FastaCompression::detect(&bytes), compression);
+            // Named as a plain FASTA, the content tells the compression
+            let path = std::env::temp_dir().join(format!("genezip_{:?}_{}.fna", compression, std::process::id()));
+            std::fs::write(&path, &bytes).unwrap();
+            let nucleotides = FastaNucltudiesIterator::new(&path, 7).collect::<Vec<u8>>();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(nucleotides, expected, "{:?}", compression);
+
+            // A pipe that returns a byte at a time
+            let mut decompressed = Vec::new();
+            FastaNucltudiesIterator::decompress(Box::new(BufReader::with_capacity(1, Cursor::new(bytes))), &path)
+                .read_to_end(&mut decompressed).unwrap();
+            assert_eq!(decompressed, plain, "{:?}", compression);
+        }
+        assert_eq!(FastaCompression::detect(&plain), FastaCompression::Plain);
+    }
+}
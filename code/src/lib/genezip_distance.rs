@@ -0,0 +1,273 @@
+// In-process replacement for an external ANI tool, based on the symmetric GeneZip score:
+//      (GZ(G_1, G_2) + GZ(G_2, G_1)) / (GZ(G_1, G_1) + GZ(G_2, G_2))
+// where GZ(A, B) is the average log-loss of a model trained on A when compressing B.
+
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use clap::ValueEnum;
+use ndarray::Array2;
+use polars::datatypes::{DataType, Field, PlSmallStr};
+use polars::prelude::{LazyCsvReader, Schema};
+use rayon::prelude::*;
+use crate::ani_calculator::AniCalculator;
+use crate::fasta_chunk_reader::FastaChunkReader;
+use crate::lz78::LenBases;
+use crate::reference_sequence::ReferenceSequence;
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum ScoreMode {
+    // The reflected score, identical for (i, j) and (j, i)
+    Symmetric,
+    // GZ(G_i, G_j), the model of the row compressing the genome of the column
+    Asymmetric,
+}
+
+impl Display for ScoreMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            ScoreMode::Symmetric => "symmetric",
+            ScoreMode::Asymmetric => "asymmetric",
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum MatrixFormat {
+    // Relaxed PHYLIP: the number of genomes, then a row per genome of its name and values
+    Phylip,
+    // TSV with a header of genome names and a row per genome
+    Square,
+    // TSV with a row per pair of genomes
+    Long,
+}
+
+impl Display for MatrixFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            MatrixFormat::Phylip => "phylip",
+            MatrixFormat::Square => "square",
+            MatrixFormat::Long => "long",
+        })
+    }
+}
+
+// Asymmetric GeneZip values between a set of genomes, cell (i, j) holds GZ(G_i, G_j).
+// A genome may have several FASTA files, e.g. a chromosome and its plasmids.
+pub struct GeneZipScores {
+    genomes: Vec<Vec<PathBuf>>,
+    scores: Array2<f64>,
+}
+
+impl GeneZipScores {
+    pub fn new(genomes: &[Vec<PathBuf>], max_depth: usize, buffer_size: usize) -> Self {
+        let len_bases = LenBases::new(max_depth);
+        let models = genomes.par_iter()
+            .map(|fastas| ReferenceSequence::from_fastas(fastas, &fastas[0].display().to_string(), &None, buffer_size, len_bases.clone(), max_depth, &None))
+            .collect::<Vec<ReferenceSequence>>();
+
+        let n = genomes.len();
+        let mut scores = Array2::zeros((n, n));
+        let values = (0..n * n).into_par_iter()
+            .map(|cell| {
+                let (i, j) = (cell / n, cell % n);
+                if i == j {
+                    models[i].get_self_value()
+                } else {
+                    models[i].get_prediction_model().average_log_score_chunks(FastaChunkReader::from_paths(&genomes[j], buffer_size))
+                }
+            })
+            .collect::<Vec<f64>>();
+        for (cell, value) in values.into_iter().enumerate() {
+            scores[[cell / n, cell % n]] = value;
+        }
+
+        Self {
+            genomes: genomes.to_vec(),
+            scores,
+        }
+    }
+
+    pub fn len(&self) -> usize { self.genomes.len() }
+    pub fn is_empty(&self) -> bool { self.genomes.is_empty() }
+    pub fn get_genomes(&self) -> &[Vec<PathBuf>] { &self.genomes }
+
+    // GZ(G_i, G_j), the model of i compressing the sequence of j.
+    pub fn asymmetric(&self, i: usize, j: usize) -> f64 { self.scores[[i, j]] }
+
+    // The reflected score, 1.0 for identical genomes and growing as they diverge.
+    pub fn symmetric(&self, i: usize, j: usize) -> f64 {
+        (self.scores[[i, j]] + self.scores[[j, i]]) / (self.scores[[i, i]] + self.scores[[j, j]])
+    }
+
+    // Symmetric scores minus one, a distance of 0.0 between a genome and itself, for tree building.
+    pub fn distances(&self) -> Array2<f64> {
+        Array2::from_shape_fn((self.len(), self.len()), |(i, j)| if i == j { 0.0 } else { (self.symmetric(i, j) - 1.0).max(0.0) })
+    }
+
+    pub fn score(&self, i: usize, j: usize, mode: ScoreMode) -> f64 {
+        match mode {
+            ScoreMode::Symmetric => self.symmetric(i, j),
+            ScoreMode::Asymmetric => self.asymmetric(i, j),
+        }
+    }
+
+    /*
+    Writes the scores of all pairs, rows and columns in the order of the genomes and named by names.
+    PHYLIP holds the distances instead, whatever the mode, as tree building tools expect.
+    Whitespace in PHYLIP names is replaced by '_', as it separates the name from the values.
+     */
+    pub fn write_matrix<W: Write>(&self, names: &[String], mode: ScoreMode, format: MatrixFormat, stream: W) -> std::io::Result<()> {
+        let mut stream = BufWriter::new(stream);
+        let n = self.len();
+        match format {
+            MatrixFormat::Phylip => {
+                let distances = self.distances();
+                writeln!(stream, "{}", n)?;
+                for (i, name) in names.iter().enumerate() {
+                    write!(stream, "{}", name.replace(char::is_whitespace, "_"))?;
+                    for j in 0..n {
+                        write!(stream, " {:.6}", distances[[i, j]])?;
+                    }
+                    writeln!(stream)?;
+                }
+            },
+            MatrixFormat::Square => {
+                writeln!(stream, "genome\t{}", names.join("\t"))?;
+                for (i, name) in names.iter().enumerate() {
+                    write!(stream, "{}", name)?;
+                    for j in 0..n {
+                        write!(stream, "\t{:.6}", self.score(i, j, mode))?;
+                    }
+                    writeln!(stream)?;
+                }
+            },
+            MatrixFormat::Long => {
+                writeln!(stream, "genome1\tgenome2\t{}_score", mode)?;
+                for i in 0..n {
+                    for j in 0..n {
+                        writeln!(stream, "{}\t{}\t{:.6}", names[i], names[j], self.score(i, j, mode))?;
+                    }
+                }
+            },
+        }
+        stream.flush()
+    }
+}
+
+// Places the symmetric score on the same 0-100 scale as ANI, so a merge distance means the same
+// thing regardless of the tool: 100 - ani = 100 * (score - 1).
+pub fn symmetric_score2ani(score: f64) -> f64 {
+    (100.0 * (2.0 - score)).min(100.0)
+}
+
+pub struct GeneZipDistance {
+    results: PathBuf,
+}
+
+impl GeneZipDistance {
+    /*
+    Calculate all pairwise distances between the genomes listed (one path per line) in input
+     */
+    pub fn run(input: &Path, output: &Path, max_depth: usize, buffer_size: usize) -> Self {
+        let genomes = match File::open(input) {
+            Err(e) => panic!("E: failed to open '{}' due to '{}'", input.display(), e),
+            Ok(f) => BufReader::new(f).lines()
+                .map(|line| line.unwrap_or_else(|e| panic!("E: failed to read '{}' due to '{}'", input.display(), e)))
+                .filter(|line| !line.is_empty())
+                .map(|line| vec![PathBuf::from(line)])
+                .collect::<Vec<Vec<PathBuf>>>(),
+        };
+
+        let scores = GeneZipScores::new(&genomes, max_depth, buffer_size);
+        if let Err(e) = Self::write_results(&scores, output) {
+            panic!("E: failed to write GeneZip distances into '{}' due to '{}'", output.display(), e);
+        }
+
+        Self {
+            results: output.to_path_buf(),
+        }
+    }
+
+    /*
+    Use a precalculated distance table
+     */
+    pub fn pre_calculated(results: &Path) -> Self {
+        Self {
+            results: results.to_path_buf()
+        }
+    }
+
+    // Each genome of the list is a single FASTA file, named by its path as in the tables of the ANI tools
+    fn write_results(scores: &GeneZipScores, output: &Path) -> std::io::Result<()> {
+        let mut stream = BufWriter::new(File::create(output)?);
+        for i in 0..scores.len() {
+            for j in 0..scores.len() {
+                let score = scores.symmetric(i, j);
+                writeln!(stream, "{}\t{}\t{}\t{}", scores.get_genomes()[i][0].display(), scores.get_genomes()[j][0].display(), symmetric_score2ani(score), score)?;
+            }
+        }
+        stream.flush()
+    }
+}
+
+impl AniCalculator for GeneZipDistance {
+    fn get_data_frame(&self) -> LazyCsvReader {
+        let schema = Schema::from_iter(vec![
+            Field::new(PlSmallStr::from("g1"), DataType::String),
+            Field::new(PlSmallStr::from("g2"), DataType::String),
+            Field::new(PlSmallStr::from("ani"), DataType::Float64),
+            Field::new(PlSmallStr::from("gz_score"), DataType::Float64),
+        ]);
+
+        LazyCsvReader::new(self.results.as_path())
+            .with_has_header(false)
+            .with_separator(b'\t')
+            .with_schema(Some(Arc::new(schema)))
+    }
+
+    fn results_path(&self) -> &Path { self.results.as_path() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use crate::genezip_distance::{symmetric_score2ani, GeneZipScores, MatrixFormat, ScoreMode};
+
+    #[test]
+    fn symmetric_scores() {
+        let genomes = vec![vec![PathBuf::from("../tests/presentation_train.fna")], vec![PathBuf::from("../tests/paper_train.fna")]];
+        let scores = GeneZipScores::new(&genomes, 13, 512);
+
+        assert_eq!(scores.len(), 2);
+        assert_eq!(scores.symmetric(0, 0), 1.0);
+        assert_eq!(scores.symmetric(1, 1), 1.0);
+        assert_eq!(scores.symmetric(0, 1), scores.symmetric(1, 0));
+        assert!(scores.symmetric(0, 1) > 1.0);
+        assert_eq!(symmetric_score2ani(scores.symmetric(0, 0)), 100.0);
+        assert!(symmetric_score2ani(scores.symmetric(0, 1)) < 100.0);
+    }
+
+    #[test]
+    fn matrix_formats() {
+        let genomes = vec![vec![PathBuf::from("../tests/presentation_train.fna")], vec![PathBuf::from("../tests/paper_train.fna")]];
+        let scores = GeneZipScores::new(&genomes, 13, 512);
+        let names = vec![String::from("presentation train"), String::from("paper")];
+
+        let mut phylip = Vec::new();
+        scores.write_matrix(&names, ScoreMode::Symmetric, MatrixFormat::Phylip, &mut phylip).unwrap();
+        let phylip = String::from_utf8(phylip).unwrap();
+        let lines = phylip.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], "2");
+        assert_eq!(lines[1], format!("presentation_train 0.000000 {:.6}", scores.symmetric(0, 1) - 1.0));
+        assert_eq!(lines[2], format!("paper {:.6} 0.000000", scores.symmetric(1, 0) - 1.0));
+
+        let mut long = Vec::new();
+        scores.write_matrix(&names, ScoreMode::Asymmetric, MatrixFormat::Long, &mut long).unwrap();
+        let long = String::from_utf8(long).unwrap();
+        assert_eq!(long.lines().count(), 5);
+        assert_eq!(long.lines().nth(2).unwrap(), format!("presentation train\tpaper\t{:.6}", scores.asymmetric(0, 1)));
+    }
+}
@@ -0,0 +1,219 @@
+// Per-genome quality scores, used to prefer complete, uncontaminated genomes as species representatives.
+// The metadata table is tab-separated with a header, the first column identifies the genome by its
+// sample name, path or file name. Other columns are matched by name (case-insensitive).
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind};
+use std::path::Path;
+use clap::ValueEnum;
+use rand::prelude::IndexedRandom;
+use rand::Rng;
+use crate::samples_file_reader::Sample;
+
+const COMPLETENESS_COLUMNS: [&str; 2] = ["completeness", "checkm_completeness"];
+const CONTAMINATION_COLUMNS: [&str; 2] = ["contamination", "checkm_contamination"];
+const N50_COLUMNS: [&str; 3] = ["n50", "n50_contigs", "contig_n50"];
+const FASTA_EXTENSIONS: [&str; 9] = [".gz", ".bz2", ".xz", ".zst", ".fna", ".fasta", ".fa", ".fas", "_genomic"];
+
+#[derive(Copy, Clone, Default, PartialEq, ValueEnum)]
+pub enum RepresentativeStrategy {
+    /// Uniform sample of the genomes of each species
+    #[default]
+    Random,
+    /// The genomes with the highest scores, ties are broken by the input order
+    TopScore,
+    /// Sample with probability proportional to the score, genomes with missing or negative scores
+    /// are only used to fill the sample
+    Weighted,
+}
+
+impl Display for RepresentativeStrategy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            RepresentativeStrategy::Random => "random",
+            RepresentativeStrategy::TopScore => "top-score",
+            RepresentativeStrategy::Weighted => "weighted",
+        })
+    }
+}
+
+pub struct GenomeQuality {
+    scores: HashMap<String, f64>,
+}
+
+// Missing values (empty, NA, etc.) are None
+fn parse_value(value: Option<&&str>) -> Option<f64> {
+    value.and_then(|v| v.trim().parse::<f64>().ok()).filter(|v| v.is_finite())
+}
+
+fn find_column(header: &[String], names: &[&str]) -> Option<usize> {
+    header.iter().position(|column| names.contains(&column.as_str()))
+}
+
+// The file name without its compression and FASTA extensions, e.g. GCF_000005845.2_genomic.fna.gz -> GCF_000005845.2
+pub fn genome_id_from_path(path: &Path) -> Option<String> {
+    let mut name = path.file_name()?.to_str()?;
+    while let Some(extension) = FASTA_EXTENSIONS.iter().find(|&e| name.ends_with(e)) {
+        name = &name[..name.len() - extension.len()];
+    }
+    Some(name.to_string())
+}
+
+impl GenomeQuality {
+    /*
+    Load scores from a metadata table, taking them from score_column if provided.
+    Otherwise, the dRep-like score is used: completeness - 5 * contamination + 0.5 * log10(N50),
+    where the N50 term is optional.
+     */
+    pub fn load(path: &Path, score_column: Option<&str>) -> std::io::Result<Self> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header = match lines.next() {
+            Some(line) => line?.trim_end_matches('\r').split('\t').map(|c| c.trim().to_lowercase()).collect::<Vec<String>>(),
+            None => return Err(invalid(String::from("empty metadata table, expected a header"))),
+        };
+
+        let (score_index, completeness_index, contamination_index, n50_index) = match score_column {
+            Some(column) => match find_column(&header, &[column.to_lowercase().as_str()]) {
+                Some(i) => (Some(i), None, None, None),
+                None => return Err(invalid(format!("missing score column '{}'", column))),
+            },
+            None => match (find_column(&header, &COMPLETENESS_COLUMNS), find_column(&header, &CONTAMINATION_COLUMNS)) {
+                (Some(completeness), Some(contamination)) => (None, Some(completeness), Some(contamination), find_column(&header, &N50_COLUMNS)),
+                _ => return Err(invalid(String::from("expected completeness and contamination columns, or set a score column"))),
+            },
+        };
+
+        let mut scores = HashMap::new();
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+            let fields = line.split('\t').collect::<Vec<&str>>();
+            if fields.len() < header.len() {
+                return Err(invalid(format!("line {} has {} fields, expected {}", i + 2, fields.len(), header.len())));
+            }
+
+            let value = match score_index {
+                Some(index) => parse_value(fields.get(index)),
+                None => match (completeness_index.and_then(|c| parse_value(fields.get(c))), contamination_index.and_then(|c| parse_value(fields.get(c)))) {
+                    (Some(completeness), Some(contamination)) => {
+                        let n50 = n50_index.and_then(|c| parse_value(fields.get(c))).filter(|&n50| n50 > 0.0);
+                        Some(completeness - 5.0 * contamination + n50.map_or(0.0, |n50| 0.5 * n50.log10()))
+                    },
+                    _ => None,
+                },
+            };
+            if let Some(value) = value {
+                scores.insert(fields[0].trim().to_string(), value);
+            }
+        }
+
+        Ok(Self { scores })
+    }
+
+    pub fn len(&self) -> usize { self.scores.len() }
+    pub fn is_empty(&self) -> bool { self.scores.is_empty() }
+
+    // By the name of the sample, then by any of its FASTA files
+    pub fn get_score(&self, sample: &Sample) -> Option<f64> {
+        let path_keys = |path: &Path| [Some(path.display().to_string()),
+                                        path.file_name().map(|f| f.to_string_lossy().to_string()),
+                                        genome_id_from_path(path)];
+        std::iter::once(Some(sample.get_name().to_string()))
+            .chain(sample.get_paths().iter().flat_map(|path| path_keys(path)))
+            .flatten()
+            .find_map(|key| self.scores.get(&key).cloned())
+    }
+
+    // Pick up to n samples using the given strategy.
+    pub fn pick<R: Rng + ?Sized>(&self, samples: &[Sample], n: usize, strategy: RepresentativeStrategy, random_state: &mut R) -> Vec<Sample> {
+        match strategy {
+            RepresentativeStrategy::Random => samples.choose_multiple(random_state, n).cloned().collect(),
+            RepresentativeStrategy::TopScore => {
+                let mut ordered = samples.iter().map(|s| (self.get_score(s), s)).collect::<Vec<(Option<f64>, &Sample)>>();
+                // Stable sort, scored genomes first
+                ordered.sort_by(|(a, _), (b, _)| match (a, b) {
+                    (Some(a), Some(b)) => b.total_cmp(a),
+                    (a, b) => b.is_some().cmp(&a.is_some()),
+                });
+                ordered.into_iter().take(n).map(|(_, s)| s.clone()).collect()
+            },
+            RepresentativeStrategy::Weighted => {
+                let weight = |s: &Sample| self.get_score(s).unwrap_or(0.0).max(0.0);
+                let mut picked = samples.choose_multiple_weighted(random_state, n, weight)
+                    .unwrap_or_else(|e| panic!("E: failed to sample representatives by score due to '{}'", e))
+                    .cloned()
+                    .collect::<Vec<Sample>>();
+                if picked.len() < n {
+                    let rest = samples.iter().filter(|s| weight(s) == 0.0).cloned().collect::<Vec<Sample>>();
+                    picked.extend(rest.choose_multiple(random_state, n - picked.len()).cloned());
+                }
+                picked
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::genome_quality::{genome_id_from_path, GenomeQuality, RepresentativeStrategy};
+    use crate::samples_file_reader::{merge_repeated_samples, Sample};
+
+    fn samples() -> Vec<Sample> {
+        vec![Sample::new("a", "genomes/GCF_000001.1_genomic.fna.gz", None, 0),
+             Sample::new("b", "genomes/b.fna", None, 1),
+             Sample::new("c", "genomes/c.fna", None, 2),
+             Sample::new("d", "genomes/d.fna", None, 3)]
+    }
+
+    #[test]
+    fn drep_score() {
+        let quality = GenomeQuality::load(Path::new("../tests/genome_quality.tsv"), None).unwrap();
+        let samples = samples();
+        assert_eq!(quality.len(), 3);
+        assert_eq!(quality.get_score(&samples[0]), Some(90.0 - 5.0 * 2.0 + 0.5 * 5.0));
+        assert_eq!(quality.get_score(&samples[1]), Some(99.0 - 5.0 * 0.5));
+        assert_eq!(quality.get_score(&samples[2]), Some(50.0 - 5.0 * 10.0 + 0.5 * 4.0));
+        assert_eq!(quality.get_score(&samples[3]), None);
+        // Any FASTA file of a genome of several
+        let genome = merge_repeated_samples(&[Sample::new("x", "genomes/x.fna", None, 0), Sample::new("x", "genomes/c.fna", None, 1)]).unwrap();
+        assert_eq!(quality.get_score(&genome[0]), quality.get_score(&samples[2]));
+        assert_eq!(genome_id_from_path(Path::new("x/GCA_1.2_genomic.fna.gz")), Some(String::from("GCA_1.2")));
+    }
+
+    #[test]
+    fn score_column() {
+        let quality = GenomeQuality::load(Path::new("../tests/genome_quality.tsv"), Some("Custom")).unwrap();
+        assert_eq!(quality.get_score(&samples()[3]), Some(7.0));
+        assert!(GenomeQuality::load(Path::new("../tests/genome_quality.tsv"), Some("missing")).is_err());
+    }
+
+    #[test]
+    fn strategies() {
+        let quality = GenomeQuality::load(Path::new("../tests/genome_quality.tsv"), None).unwrap();
+        let samples = samples();
+        let mut random_state = StdRng::seed_from_u64(1);
+
+        let top = quality.pick(&samples, 2, RepresentativeStrategy::TopScore, &mut random_state);
+        assert_eq!(top.iter().map(|s| s.get_name()).collect::<Vec<&str>>(), vec!["b", "a"]);
+
+        // a, b and c are drawn by their scores, c's 2.0 is low but still a weight, d has no score so it is only picked to fill the sample
+        let mut weighted = quality.pick(&samples, 3, RepresentativeStrategy::Weighted, &mut random_state).iter().map(|s| s.get_name().to_string()).collect::<Vec<String>>();
+        weighted.sort();
+        assert_eq!(weighted, vec!["a", "b", "c"]);
+        let weighted = quality.pick(&samples, 4, RepresentativeStrategy::Weighted, &mut random_state);
+        assert_eq!(weighted.len(), 4);
+        let weighted = quality.pick(&samples, 2, RepresentativeStrategy::Weighted, &mut random_state);
+        assert!(weighted.iter().all(|s| s.get_name() != "d"));
+
+        assert_eq!(quality.pick(&samples, 3, RepresentativeStrategy::Random, &mut random_state).len(), 3);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::fasta_chunk_reader::{FastaChunk, FastaChunkReader};
+
 // Returns GC%, a value between 0 and 100
 pub fn calc_gc<I>(genome: I) -> f64
 where I: IntoIterator<Item=u8> {
@@ -23,16 +25,45 @@ where I: IntoIterator<Item=u8> {
     }
 }
 
+// Same as calc_gc, reading the FASTA in chunks, see FastaChunkReader
+pub fn calc_gc_chunks(mut fasta: FastaChunkReader) -> f64 {
+    let mut gc_counter = 0_usize;
+    let mut length_counter = 0_usize;
+
+    while let Some(chunk) = fasta.next_chunk() {
+        if let FastaChunk::Sequence(sequence) = chunk {
+            for &c in sequence {
+                match c {
+                    b'G' | b'C' => {
+                        gc_counter += 1;
+                        length_counter += 1;
+                    },
+                    b'A' | b'T' => length_counter += 1,
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    if length_counter > 0 {
+        (gc_counter as f64 / length_counter as f64) * 100.0
+    } else {
+        0_f64
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
+    use crate::fasta_chunk_reader::FastaChunkReader;
     use crate::fasta_nucleutide_iterator::FastaNucltudiesIterator;
-    use crate::get_gc::calc_gc;
+    use crate::get_gc::{calc_gc, calc_gc_chunks};
 
     #[test]
     fn gc_test_file() {
         let dna_test_path = PathBuf::from("../tests/gc_tests.fna");
         assert_eq!(calc_gc(FastaNucltudiesIterator::new(&dna_test_path, 10)), 30.23255813953488);
+        assert_eq!(calc_gc_chunks(FastaChunkReader::new(&dna_test_path, 10)), 30.23255813953488);
     }
 }
\ No newline at end of file
@@ -3,6 +3,7 @@ use ndarray::prelude::*;
 use lazy_static::*;
 use rayon::prelude::*;
 use std::path::Path;
+use crate::fasta_chunk_reader::{FastaChunk, FastaChunkReader};
 use crate::fasta_nucleutide_iterator::FastaNucltudiesIterator;
 
 lazy_static! {
@@ -115,40 +116,85 @@ pub fn create_printed_vector(k: usize, alphabet_values: &HashMap<u8, u8>, rev_al
 
 
 /// Helper function to get_fasta_vector, allow abstraction of BufRead to open text file, gz file etc...
-fn get_fasta_vector<I>(k: usize, fasta: I, alphabet_map: &HashMap<u8, u8>, alphabet_values: &HashMap<u8, usize>) -> Result<Array1<f64>, String>
-    where I: IntoIterator<Item=u8> {
-    let mut vector = Array::zeros(alphabet_values.len().pow(k as u32));
-    let mask = KMASK[&k];
-    let mut index: usize = 0; // left to right word index
-    let mut findex: usize = 0; // flipped word index
-    let mut found = 0; // how many nucleotides read so far, we own't start counting before k
+// Counts the words of a sequence, each with its reverse-complement, a nucleotide at a time
+struct KmerCounter<'a> {
+    k: usize,
+    mask: usize,
+    alphabet_map: &'a HashMap<u8, u8>,
+    alphabet_values: &'a HashMap<u8, usize>,
+    vector: Array1<f64>,
+    index: usize, // left to right word index
+    findex: usize, // flipped word index
+    found: usize, // how many nucleotides read so far, we won't start counting before k
+}
 
-    for nucleotide in fasta {
-        if alphabet_map.keys().any(|x| *x == nucleotide) {
+impl<'a> KmerCounter<'a> {
+    fn new(k: usize, alphabet_map: &'a HashMap<u8, u8>, alphabet_values: &'a HashMap<u8, usize>) -> Self {
+        KmerCounter {
+            k,
+            mask: KMASK[&k],
+            alphabet_map,
+            alphabet_values,
+            vector: Array::zeros(alphabet_values.len().pow(k as u32)),
+            index: 0,
+            findex: 0,
+            found: 0,
+        }
+    }
+
+    #[inline]
+    fn add(&mut self, nucleotide: u8) {
+        if self.alphabet_map.keys().any(|x| *x == nucleotide) {
             // Handle first vector
-            index = (index << 2) | (alphabet_values[&nucleotide]);
-            index &= mask;
+            self.index = (self.index << 2) | (self.alphabet_values[&nucleotide]);
+            self.index &= self.mask;
 
             // Handle flipped-words vector
-            findex = (findex >> 2) | ((alphabet_values[&alphabet_map[&nucleotide]]) << (2 * (k - 1)));
+            self.findex = (self.findex >> 2) | ((self.alphabet_values[&self.alphabet_map[&nucleotide]]) << (2 * (self.k - 1)));
 
 
-            if found < k {
-                found += 1;
+            if self.found < self.k {
+                self.found += 1;
             }
-            if k <= found {
-                if index < findex {
-                    vector[index] += 1.0; // Count the found word
+            if self.k <= self.found {
+                if self.index < self.findex {
+                    self.vector[self.index] += 1.0; // Count the found word
                 } else {
-                    vector[findex] += 1.0; // Count the flipped word
+                    self.vector[self.findex] += 1.0; // Count the flipped word
                 }
             }
         } else if nucleotide == b'N' {
-            found = 0;
+            self.restart();
         }
     }
 
-    Ok(vector)
+    // Words do not span an 'N' or two records
+    #[inline]
+    fn restart(&mut self) {
+        self.found = 0;
+    }
+}
+
+fn get_fasta_vector<I>(k: usize, fasta: I, alphabet_map: &HashMap<u8, u8>, alphabet_values: &HashMap<u8, usize>) -> Result<Array1<f64>, String>
+    where I: IntoIterator<Item=u8> {
+    let mut counter = KmerCounter::new(k, alphabet_map, alphabet_values);
+    for nucleotide in fasta {
+        counter.add(nucleotide);
+    }
+
+    Ok(counter.vector)
+}
+
+fn get_chunks_vector(k: usize, mut fasta: FastaChunkReader, alphabet_map: &HashMap<u8, u8>, alphabet_values: &HashMap<u8, usize>) -> Result<Array1<f64>, String> {
+    let mut counter = KmerCounter::new(k, alphabet_map, alphabet_values);
+    while let Some(chunk) = fasta.next_chunk() {
+        match chunk {
+            FastaChunk::Record(_) => counter.restart(),
+            FastaChunk::Sequence(sequence) => sequence.iter().for_each(|&nucleotide| counter.add(nucleotide)),
+        }
+    }
+
+    Ok(counter.vector)
 }
 
 /// Minimizes the given vector by dropping the reverse-complement words.
@@ -215,6 +261,17 @@ pub fn create_normalized_profile<I, N: ?Sized>(k: usize, fasta: I, id: &N) -> (&
     (id, Ok(vector))
 }
 
+// Same as create_normalized_profile, reading the FASTA in chunks, see FastaChunkReader
+pub fn create_normalized_profile_from_chunks(k: usize, fasta: FastaChunkReader) -> Result<Array1<f64>, String> {
+    if k > usize::MAX / 2 {  // We use 2 bits per nucleotide
+        panic!("K-mer value is too high, value must be <= {} on your system", usize::MAX / 2);
+    }
+
+    let mut vector = minimize_vector(&mut get_chunks_vector(k, fasta, &ALPHABET_MAP, &ALPHABET_VALUES)?, k);
+    normalize_vector(&mut vector);
+    Ok(vector)
+}
+
 /// Translates the given genomes into k genomic-profile HashMap using up-to ncores but not less then 1.
 /// Errors will be returned per-given fasta to avoid stopping the whole computation over one bad file.
 ///
@@ -241,8 +298,9 @@ pub fn generate_kmers<'a>(genomes: &Vec<&'a Path>, k: usize, use_multithreading:
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
+    use crate::fasta_chunk_reader::FastaChunkReader;
     use crate::fasta_nucleutide_iterator::FastaNucltudiesIterator;
-    use crate::kmer::{ALPHABET_MAP, ALPHABET_VALUES, complement_index, create_normalized_profile, create_printed_vector, get_fasta_vector, minimize_vector, REV_ALPHABET_VALUES};
+    use crate::kmer::{ALPHABET_MAP, ALPHABET_VALUES, complement_index, create_normalized_profile, create_normalized_profile_from_chunks, create_printed_vector, get_fasta_vector, minimize_vector, REV_ALPHABET_VALUES};
 
     #[test]
     fn one_mer() {
@@ -302,4 +360,15 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn chunks_profile() {
+        for fasta in ["../tests/gc_tests.fna", "../tests/presentation_test_add_N.fna", "../data/HMP_2012__SRS019219__bin.33_genes.fna.gz"] {
+            let fasta = PathBuf::from(fasta);
+            for k in [1, 4] {
+                let vector = create_normalized_profile(k, FastaNucltudiesIterator::new(fasta.as_path(), 510), &false).1.unwrap();
+                assert_eq!(vector, create_normalized_profile_from_chunks(k, FastaChunkReader::new(fasta.as_path(), 11)).unwrap(), "{}", fasta.display());
+            }
+        }
+    }
 }
\ No newline at end of file
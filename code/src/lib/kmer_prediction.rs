@@ -1,14 +1,14 @@
 use std::io::{BufWriter, Write};
-use std::path::{Path};
+use std::path::{Path, PathBuf};
 use hashbrown::HashMap;
 use ndarray::stack;
 use ndarray_stats::CorrelationExt;
 use rayon::iter::{IntoParallelRefIterator, ParallelBridge};
 use rayon::iter::ParallelIterator;
-use crate::fasta_nucleutide_iterator::FastaNucltudiesIterator;
-use crate::kmer::create_normalized_profile;
+use crate::fasta_chunk_reader::FastaChunkReader;
+use crate::kmer::create_normalized_profile_from_chunks;
 use crate::samples_file_reader;
-use crate::samples_file_reader::SampleError;
+use crate::samples_file_reader::{merge_repeated_samples, SampleError};
 
 pub struct KmerClassifier {
     models: Box<HashMap<String, ndarray::Array1<f64>>>,
@@ -38,12 +38,14 @@ impl KmerClassifier {
         self.models.insert(name.to_string(), model.clone());
     }
 
+    // A model repeating its name over several lines is profiled over all its FASTA files
     pub fn batch_add_model(&mut self, name2file: &Path, buffer_size: usize) -> Result<(), SampleError> {
-        let mut models: Vec<_> = samples_file_reader::SampleSource::new(name2file, false)
+        let samples = samples_file_reader::SampleSource::new(name2file, false)
             .into_iter()
-            .collect::<Result<Vec<_>,SampleError>>()?
+            .collect::<Result<Vec<_>,SampleError>>()?;
+        let mut models: Vec<_> = merge_repeated_samples(&samples)?
             .par_iter()
-            .map(|sample| (sample.get_name().to_string(), create_normalized_profile(self.kmer_size, FastaNucltudiesIterator::new(sample.get_path(), buffer_size), &false).1.unwrap_or_else(|_| panic!("ERROR: failed to create kmer for {}, quitting", sample.get_path().display()))))
+            .map(|sample| (sample.get_name().to_string(), create_normalized_profile_from_chunks(self.kmer_size, FastaChunkReader::from_paths(sample.get_paths(), buffer_size)).unwrap_or_else(|_| panic!("ERROR: failed to create kmer for {}, quitting", sample.get_name()))))
             .collect();
 
         models.iter_mut()
@@ -87,10 +89,11 @@ impl KmerClassifier {
         best_model_name
     }
 
-    pub fn predict(&self, file_path: &Path, buffer_size: usize) -> Vec<(&String, f64)> {
-        let genome_kmer = match create_normalized_profile(self.kmer_size, FastaNucltudiesIterator::new(file_path, buffer_size), &false).1 {
+    // The genome may have several FASTA files
+    pub fn predict(&self, file_paths: &[PathBuf], buffer_size: usize) -> Vec<(&String, f64)> {
+        let genome_kmer = match create_normalized_profile_from_chunks(self.kmer_size, FastaChunkReader::from_paths(file_paths, buffer_size)) {
             Ok(vector) => vector,
-            Err(e) => panic!("E: tried to create k({})-mer for genome {}, but got {:?}, quitting", self.kmer_size, file_path.display(), e),
+            Err(e) => panic!("E: tried to create k({})-mer for genome {}, but got {:?}, quitting", self.kmer_size, file_paths[0].display(), e),
         };
 
         self.models_order.iter()
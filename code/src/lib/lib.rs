@@ -24,4 +24,19 @@ pub mod static_graph;
 pub mod ani_calculator;
 pub mod fastani;
 pub mod skani;
-pub mod ani_calculator_tool;
\ No newline at end of file
+pub mod ani_calculator_tool;
+pub mod genezip_distance;
+pub mod linkage;
+pub mod genome_quality;
+pub mod checkpoint;
+pub mod ncbi_taxdump;
+pub mod reference_import;
+pub mod taxonomy_validation;
+pub mod evaluation;
+pub mod scoring;
+pub mod simulation;
+pub mod calibration;
+pub mod phylogeny;
+pub mod novelty;
+pub mod profile;
+pub mod fasta_chunk_reader;
\ No newline at end of file
@@ -0,0 +1,294 @@
+// Clustering strategies used to merge taxa by pairwise ANI.
+// The graph vertices are taxa, and the edges hold the ANI values measured between their genomes.
+
+use std::fmt::{Display, Formatter};
+use clap::ValueEnum;
+use crate::graph::Graph;
+
+#[derive(Copy, Clone, Debug, Default, ValueEnum)]
+pub enum Linkage {
+    /// Merge clusters if any pair of members is close enough, may chain distant taxa together
+    #[default]
+    Single,
+    /// Merge clusters if the mean ANI between their members is high enough (UPGMA)
+    Average,
+    /// Merge clusters only if all pairs of members are close enough
+    Complete,
+    /// Repeatedly pick the taxon with the most close neighbours and cluster them around it
+    GreedyCentroid,
+}
+
+impl Display for Linkage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Linkage::Single => "single",
+            Linkage::Average => "average",
+            Linkage::Complete => "complete",
+            Linkage::GreedyCentroid => "greedy-centroid",
+        })
+    }
+}
+
+// All ANI values measured between the genomes of two taxa.
+// A taxon may have several representatives, and ANI tools report both directions of each pair.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AniSummary {
+    sum: f64,
+    count: usize,
+    min: f64,
+    max: f64,
+}
+
+impl AniSummary {
+    pub fn new(ani: f64) -> Self {
+        Self {
+            sum: ani,
+            count: 1,
+            min: ani,
+            max: ani,
+        }
+    }
+
+    // One more ANI value of the same pair
+    pub fn add(&mut self, ani: f64) {
+        self.sum += ani;
+        self.count += 1;
+        self.min = self.min.min(ani);
+        self.max = self.max.max(ani);
+    }
+
+    // Matches the edge collision signature of Graph::add_edge
+    pub fn merge(a: Option<Self>, b: Option<Self>) -> Option<Self> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(Self {
+                sum: a.sum + b.sum,
+                count: a.count + b.count,
+                min: a.min.min(b.min),
+                max: a.max.max(b.max),
+            }),
+            (a, None) => a,
+            (None, b) => b,
+        }
+    }
+
+    pub fn mean(&self) -> f64 { self.sum / self.count as f64 }
+    pub fn get_min(&self) -> f64 { self.min }
+    pub fn get_max(&self) -> f64 { self.max }
+    pub fn get_count(&self) -> usize { self.count }
+}
+
+// ANI values within one cluster, min and mean are None if no ANI was measured between its genomes.
+#[derive(Clone, Debug, Default)]
+pub struct ClusterDiagnostics {
+    comparisons: usize,
+    missing_pairs: usize,
+    min_ani: Option<f64>,
+    mean_ani: Option<f64>,
+}
+
+impl ClusterDiagnostics {
+    pub fn new<G>(graph: &G, members: &[&G::VertexType]) -> Self
+    where G: Graph<EdgeData = AniSummary> {
+        let mut summary = None;
+        let mut missing_pairs = 0;
+        for (i, u) in members.iter().enumerate() {
+            for v in members.iter().skip(i) {
+                match graph.get_edge_data(u, v) {
+                    Some(data) => summary = AniSummary::merge(summary, Some(*data)),
+                    None => if u != v {
+                        missing_pairs += 1;
+                    },
+                }
+            }
+        }
+
+        Self {
+            comparisons: summary.map_or(0, |s| s.get_count()),
+            missing_pairs,
+            min_ani: summary.map(|s| s.get_min()),
+            mean_ani: summary.map(|s| s.mean()),
+        }
+    }
+
+    pub fn get_comparisons(&self) -> usize { self.comparisons }
+    pub fn get_missing_pairs(&self) -> usize { self.missing_pairs }
+    pub fn get_min_ani(&self) -> Option<f64> { self.min_ani }
+    pub fn get_mean_ani(&self) -> Option<f64> { self.mean_ani }
+}
+
+// Similarity between every two vertices, from the ANI values between their genomes:
+// the best pair for single linkage and greedy centroid (any close pair of genomes links two taxa), the worst pair for complete linkage and the mean for average linkage.
+// Pairs without ANI are taken as 0.0, ANI tools do not report values for distant genomes.
+fn similarity_matrix<G>(graph: &G, vertices: &[&G::VertexType], linkage: Linkage) -> Vec<Vec<f64>>
+where G: Graph<EdgeData = AniSummary> {
+    let similarity = |data: AniSummary| match linkage {
+        Linkage::Single | Linkage::GreedyCentroid => data.get_max(),
+        Linkage::Complete => data.get_min(),
+        Linkage::Average => data.mean(),
+    };
+    vertices.iter()
+        .map(|u| vertices.iter()
+            .map(|v| graph.get_edge_data(u, v).map_or(0.0, similarity))
+            .collect())
+        .collect()
+}
+
+// Agglomerative clustering, using Lance-Williams updates of the similarity between clusters.
+fn agglomerative(mut similarity: Vec<Vec<f64>>, linkage: Linkage, min_ani: f64) -> Vec<Vec<usize>> {
+    let mut clusters: Vec<Option<Vec<usize>>> = (0..similarity.len()).map(|i| Some(vec![i])).collect();
+
+    loop {
+        let mut best: Option<(usize, usize, f64)> = None;
+        for i in 0..clusters.len() {
+            if clusters[i].is_none() {
+                continue;
+            }
+            for j in (i + 1)..clusters.len() {
+                if clusters[j].is_some() && best.is_none_or(|(_, _, s)| similarity[i][j] > s) {
+                    best = Some((i, j, similarity[i][j]));
+                }
+            }
+        }
+
+        let (i, j) = match best {
+            Some((i, j, s)) if s >= min_ani => (i, j),
+            _ => break,
+        };
+
+        let size_i = clusters[i].as_ref().map_or(0, |c| c.len()) as f64;
+        let size_j = clusters[j].as_ref().map_or(0, |c| c.len()) as f64;
+        for k in 0..clusters.len() {
+            if k != i && k != j && clusters[k].is_some() {
+                let merged = match linkage {
+                    Linkage::Single => similarity[i][k].max(similarity[j][k]),
+                    Linkage::Complete => similarity[i][k].min(similarity[j][k]),
+                    Linkage::Average => (size_i * similarity[i][k] + size_j * similarity[j][k]) / (size_i + size_j),
+                    Linkage::GreedyCentroid => unreachable!("greedy centroid is not an agglomerative linkage"),
+                };
+                similarity[i][k] = merged;
+                similarity[k][i] = merged;
+            }
+        }
+
+        let merged_members = clusters[j].take().unwrap_or_default();
+        if let Some(c) = clusters[i].as_mut() {
+            c.extend(merged_members);
+        }
+    }
+
+    clusters.into_iter().flatten().collect()
+}
+
+fn greedy_centroid(similarity: &[Vec<f64>], min_ani: f64) -> Vec<Vec<usize>> {
+    let close = |i: usize, j: usize| i != j && similarity[i][j] >= min_ani;
+    let mut order = (0..similarity.len()).collect::<Vec<usize>>();
+    // Most connected first, ties are broken by the vertex order to keep runs repeatable
+    order.sort_by_key(|&i| std::cmp::Reverse((0..similarity.len()).filter(|&j| close(i, j)).count()));
+
+    let mut assigned = vec![false; similarity.len()];
+    let mut clusters = Vec::new();
+    for &centroid in &order {
+        if assigned[centroid] {
+            continue;
+        }
+        assigned[centroid] = true;
+        let mut cluster = vec![centroid];
+        for &member in &order {
+            if !assigned[member] && close(centroid, member) {
+                assigned[member] = true;
+                cluster.push(member);
+            }
+        }
+        clusters.push(cluster);
+    }
+
+    clusters
+}
+
+// Clusters all the vertices of the graph, every vertex shows up in exactly one cluster.
+pub fn cluster<G>(graph: &G, linkage: Linkage, min_ani: f64) -> Vec<Vec<&G::VertexType>>
+where G: Graph<EdgeData = AniSummary> {
+    let vertices = graph.vertices();
+    let similarity = similarity_matrix(graph, &vertices, linkage);
+
+    let clusters = match linkage {
+        Linkage::GreedyCentroid => greedy_centroid(&similarity, min_ani),
+        _ => agglomerative(similarity, linkage, min_ani),
+    };
+
+    clusters.iter()
+        .map(|c| c.iter().map(|&i| vertices[i]).collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graph::Graph;
+    use crate::linkage::{cluster, AniSummary, ClusterDiagnostics, Linkage};
+    use crate::static_graph::StaticGraph;
+
+    // A chain: a-b and b-c are close, a-c are not
+    fn chain_graph() -> StaticGraph<char, AniSummary, u8> {
+        let mut graph = StaticGraph::new(None, 0);
+        graph.batch_add_vertex(&['a', 'b', 'c'],
+                               &[('a', 'b', Some(AniSummary::new(96.0))),
+                                 ('b', 'a', Some(AniSummary::new(97.0))),
+                                 ('b', 'c', Some(AniSummary::new(96.0))),
+                                 ('a', 'c', Some(AniSummary::new(90.0)))],
+                               AniSummary::merge);
+        graph
+    }
+
+    fn sorted_clusters(graph: &StaticGraph<char, AniSummary, u8>, linkage: Linkage) -> Vec<Vec<char>> {
+        let mut clusters = cluster(graph, linkage, 95.0).iter()
+            .map(|c| {
+                let mut c = c.iter().map(|&&v| v).collect::<Vec<char>>();
+                c.sort();
+                c
+            })
+            .collect::<Vec<Vec<char>>>();
+        clusters.sort();
+        clusters
+    }
+
+    #[test]
+    fn linkage_strategies() {
+        let graph = chain_graph();
+        assert_eq!(sorted_clusters(&graph, Linkage::Single), vec![vec!['a', 'b', 'c']]);
+        assert_eq!(sorted_clusters(&graph, Linkage::Complete), vec![vec!['a', 'b'], vec!['c']]);
+        assert_eq!(sorted_clusters(&graph, Linkage::Average), vec![vec!['a', 'b'], vec!['c']]);
+        assert_eq!(sorted_clusters(&graph, Linkage::GreedyCentroid), vec![vec!['a', 'b', 'c']]);
+    }
+
+    #[test]
+    fn single_linkage_best_pair() {
+        // Two representatives each, only one pair of genomes is close, the mean is 92.5
+        let mut graph = StaticGraph::new(None, 0);
+        graph.batch_add_vertex(&['a', 'b'],
+                               &[('a', 'b', Some(AniSummary::new(96.0))),
+                                 ('a', 'b', Some(AniSummary::new(89.0)))],
+                               AniSummary::merge);
+        assert_eq!(sorted_clusters(&graph, Linkage::Single), vec![vec!['a', 'b']]);
+        assert_eq!(sorted_clusters(&graph, Linkage::Average), vec![vec!['a'], vec!['b']]);
+        assert_eq!(sorted_clusters(&graph, Linkage::Complete), vec![vec!['a'], vec!['b']]);
+
+        // Summarizing the pair before adding it as a single edge is the same
+        let mut summary = AniSummary::new(96.0);
+        summary.add(89.0);
+        assert_eq!(Some(summary), AniSummary::merge(Some(AniSummary::new(96.0)), Some(AniSummary::new(89.0))));
+    }
+
+    #[test]
+    fn cluster_diagnostics() {
+        let graph = chain_graph();
+        let diagnostics = ClusterDiagnostics::new(&graph, &[&'a', &'b', &'c']);
+        assert_eq!(diagnostics.get_comparisons(), 4);
+        assert_eq!(diagnostics.get_missing_pairs(), 0);
+        assert_eq!(diagnostics.get_min_ani(), Some(90.0));
+        assert_eq!(diagnostics.get_mean_ani(), Some(94.75));
+
+        let diagnostics = ClusterDiagnostics::new(&graph, &[&'c']);
+        assert_eq!(diagnostics.get_comparisons(), 0);
+        assert_eq!(diagnostics.get_min_ani(), None);
+    }
+}
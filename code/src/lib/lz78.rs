@@ -5,6 +5,39 @@
 //
 
 use serde::{Serialize, Deserialize};
+use crate::fasta_chunk_reader::{FastaChunk, FastaChunkReader};
+
+// Where a walk down the tree is, the depth and the nucleotides read from the root
+struct TreeWalk {
+    depth: usize, // len is at least 1
+    sequence: usize, // sequence value.
+}
+
+impl TreeWalk {
+    fn new() -> Self {
+        TreeWalk { depth: 1, sequence: 0 }
+    }
+
+    // Back to the root, after a leaf, an 'N' or at a new record
+    #[inline]
+    fn restart(&mut self) {
+        self.depth = 1;
+        self.sequence = 0;
+    }
+
+    #[inline]
+    fn descend(&mut self) {
+        self.sequence <<= 2;
+        self.depth += 1;
+    }
+}
+
+#[derive(Default)]
+struct ScoreCounts {
+    nchars: usize,
+    actual_nchars: usize, // Up-to the last leaf, a partial path is not scored
+    leaf_count: usize,
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct LenBases {
@@ -59,6 +92,26 @@ impl LZ78 {
 
     pub fn new<I>(max_depth: usize, len_bases: LenBases, sequence_stream: I) -> Self
     where I: IntoIterator<Item=u8> + Clone {
+        let mut lz = Self::empty(max_depth, len_bases);
+        lz.build(sequence_stream);
+        lz
+    }
+
+    // Same as new, reading the FASTA in chunks, see FastaChunkReader
+    pub fn from_chunks(max_depth: usize, len_bases: LenBases, mut fasta: FastaChunkReader) -> Self {
+        let mut lz = Self::empty(max_depth, len_bases);
+        let mut walk = TreeWalk::new();
+        while let Some(chunk) = fasta.next_chunk() {
+            match chunk {
+                FastaChunk::Record(_) => walk.restart(),
+                FastaChunk::Sequence(sequence) => sequence.iter().for_each(|&p| lz.build_step(p, &mut walk)),
+            }
+        }
+        lz.update_full_depth();
+        lz
+    }
+
+    fn empty(max_depth: usize, len_bases: LenBases) -> Self {
         let mem_size = calc_mem_size(&len_bases, max_depth);
 
         let mut lz = LZ78 {
@@ -71,7 +124,6 @@ impl LZ78 {
             len_bases,
         };
         lz.num_nodes_in_depth[0] = 1;
-        lz.build(sequence_stream);
         lz
     }
 
@@ -81,51 +133,52 @@ impl LZ78 {
     // There is some bug here
     fn build<I>(&mut self, sequence: I)
     where I: IntoIterator<Item=u8> {
-        let mut curr_depth = 1; // len is at least 1
-        let mut curr_sequence: usize = 0_usize;  // sequence value.
-
+        let mut walk = TreeWalk::new();
         for p in sequence {
-            if p == b'N' {
-                curr_depth = 1;
-                curr_sequence = 0;
-                continue;
-            }
+            self.build_step(p, &mut walk);
+        }
+        self.update_full_depth();
+    }
 
-            // For (p >> 1) this is what we get:
-            // A = 0b100000
-            // C = 0b100001
-            // G = 0b100011
-            // T = 0b101010
-            // Order will be ACTG
-            curr_sequence |= ((p >> 1) & 3) as usize;
-
-            // As far as I understand this can only happen if self.max_depth == 1
-            if curr_depth > self.max_depth - 1 {
-                curr_depth = 1;
-                curr_sequence = 0;
-                continue;
-            }
+    #[inline]
+    fn build_step(&mut self, p: u8, walk: &mut TreeWalk) {
+        if p == b'N' {
+            walk.restart();
+            return;
+        }
 
-            //
-            let current_index = self.len_bases.bases()[curr_depth - 1] + curr_sequence;
-            assert!((current_index >> 3) < self.mem_size);
-            if !self.check_bit(current_index) {
-                self.add_node(current_index, curr_depth);
-                curr_depth = 1;
-                curr_sequence = 0;
-                continue;
-            }
+        // For (p >> 1) this is what we get:
+        // A = 0b100000
+        // C = 0b100001
+        // G = 0b100011
+        // T = 0b101010
+        // Order will be ACTG
+        walk.sequence |= ((p >> 1) & 3) as usize;
+
+        // As far as I understand this can only happen if self.max_depth == 1
+        if walk.depth > self.max_depth - 1 {
+            walk.restart();
+            return;
+        }
 
-            if curr_depth == self.max_depth - 1 {
-                curr_depth = 1;
-                curr_sequence = 0;
-            } else {
-                curr_sequence <<= 2;
-                curr_depth += 1;
-            }
+        //
+        let current_index = self.len_bases.bases()[walk.depth - 1] + walk.sequence;
+        assert!((current_index >> 3) < self.mem_size);
+        if !self.check_bit(current_index) {
+            self.add_node(current_index, walk.depth);
+            walk.restart();
+            return;
         }
 
-        // Check what is the maximum level with all nodes
+        if walk.depth == self.max_depth - 1 {
+            walk.restart();
+        } else {
+            walk.descend();
+        }
+    }
+
+    // Check what is the maximum level with all nodes
+    fn update_full_depth(&mut self) {
         self.full_depth = 0;
         while self.full_depth + 1 < self.max_depth && self.num_nodes_in_depth[self.full_depth + 1] == (4 << (2 * self.full_depth)) {
             self.full_depth += 1;
@@ -142,47 +195,58 @@ impl LZ78 {
 
     pub fn average_log_score<I>(&self, sequence: I) -> f64
     where I: IntoIterator<Item=u8> {
-
-        let mut nchars= 0;
-        let mut actual_nchars: usize = 0;
-        let mut leaf_count: usize = 0;
-
-        let mut curr_depth: usize = 1;
-        let mut curr_sequence: usize = 0;
-
+        let mut walk = TreeWalk::new();
+        let mut counts = ScoreCounts::default();
         for p in sequence {
-            if p == b'N' {
-                curr_depth = 1;
-                curr_sequence = 0;
-                continue;
-            }
+            self.score_step(p, &mut walk, &mut counts);
+        }
+        self.score(&counts)
+    }
 
-            // For (p >> 1) this is what we get:
-            // A = 0b100000
-            // C = 0b100001
-            // G = 0b100011
-            // T = 0b101010
-            // Order will be ACTG
-            let i = (p as usize >> 1) & 3;
-            nchars += 1;
-            curr_sequence |= i;
-            let current_index = self.len_bases.bases()[curr_depth - 1] + curr_sequence;
-
-            // The first part is an optimization: no need to check if the node
-            // exists for depths with all inner nodes present
-            if curr_depth <= self.full_depth || (curr_depth < self.max_depth && self.check_bit(current_index)) {
-                curr_sequence <<= 2;
-                curr_depth += 1;
-            } else {
-                leaf_count += 1;
-                curr_depth = 1;
-                curr_sequence = 0;
-                actual_nchars = nchars;
-                continue;
+    // Same as average_log_score, reading the FASTA in chunks, see FastaChunkReader
+    pub fn average_log_score_chunks(&self, mut fasta: FastaChunkReader) -> f64 {
+        let mut walk = TreeWalk::new();
+        let mut counts = ScoreCounts::default();
+        while let Some(chunk) = fasta.next_chunk() {
+            match chunk {
+                FastaChunk::Record(_) => walk.restart(),
+                FastaChunk::Sequence(sequence) => sequence.iter().for_each(|&p| self.score_step(p, &mut walk, &mut counts)),
             }
         }
+        self.score(&counts)
+    }
+
+    #[inline]
+    fn score_step(&self, p: u8, walk: &mut TreeWalk, counts: &mut ScoreCounts) {
+        if p == b'N' {
+            walk.restart();
+            return;
+        }
+
+        // For (p >> 1) this is what we get:
+        // A = 0b100000
+        // C = 0b100001
+        // G = 0b100011
+        // T = 0b101010
+        // Order will be ACTG
+        let i = (p as usize >> 1) & 3;
+        counts.nchars += 1;
+        walk.sequence |= i;
+        let current_index = self.len_bases.bases()[walk.depth - 1] + walk.sequence;
+
+        // The first part is an optimization: no need to check if the node
+        // exists for depths with all inner nodes present
+        if walk.depth <= self.full_depth || (walk.depth < self.max_depth && self.check_bit(current_index)) {
+            walk.descend();
+        } else {
+            counts.leaf_count += 1;
+            walk.restart();
+            counts.actual_nchars = counts.nchars;
+        }
+    }
 
-        (self.leaf_count as f64).log2() * leaf_count as f64 / actual_nchars as f64
+    fn score(&self, counts: &ScoreCounts) -> f64 {
+        (self.leaf_count as f64).log2() * counts.leaf_count as f64 / counts.actual_nchars as f64
     }
 
     fn num_inner_nodes(&self) -> usize {
@@ -237,6 +301,7 @@ impl std::fmt::Display for LZ78 {
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
+    use crate::fasta_chunk_reader::FastaChunkReader;
     use crate::fasta_nucleutide_iterator::FastaNucltudiesIterator;
     use crate::lz78::{LenBases, LZ78};
 
@@ -298,6 +363,23 @@ mod tests {
         assert!(prediction < 2.229715809318649);
         assert!(prediction > 2.229715809318647);
     }
+
+    #[test]
+    fn chunks_match_nucleotides() {
+        let max_depth = 13;
+        for (train_path, test_path) in [("../tests/presentation_train.fna", "../tests/presentation_test_two_seq.fna"),
+                                        ("../tests/presentation_train.fna", "../tests/presentation_test_add_N.fna"),
+                                        ("../data/HMP_2012__SRS019219__bin.33_genes.fna.gz", "../tests/gc_tests.fna")] {
+            let (train_path, test_path) = (PathBuf::from(train_path), PathBuf::from(test_path));
+            let model = LZ78::new(max_depth, LenBases::new(max_depth), FastaNucltudiesIterator::new(train_path.as_path(), 1024));
+            let chunks_model = LZ78::from_chunks(max_depth, LenBases::new(max_depth), FastaChunkReader::new(train_path.as_path(), 7));
+            for path in [&train_path, &test_path] {
+                let prediction = model.average_log_score(FastaNucltudiesIterator::new(path.as_path(), 1024));
+                assert_eq!(prediction, chunks_model.average_log_score_chunks(FastaChunkReader::new(path.as_path(), 7)), "{}", path.display());
+                assert_eq!(prediction, model.average_log_score_chunks(FastaChunkReader::new(path.as_path(), 1024)), "{}", path.display());
+            }
+        }
+    }
 }
 
 
@@ -0,0 +1,242 @@
+// The NCBI taxonomy tree, read from the nodes.dmp and names.dmp files of a local taxdump.
+// Lines in both files are fields separated by "\t|\t" and terminated by "\t|".
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use crate::taxonomy::Taxonomy;
+
+pub type TaxId = u32;
+
+pub const ROOT_TAXID: TaxId = 1;
+
+// Rank of the k-mer filter, models of the genus of the closest k-mer profile are scored
+pub const GENUS_RANK: &str = "genus";
+
+// NCBI ranks matching the seven GTDB ranks, the domain was named superkingdom in older taxdumps
+const GTDB_RANKS: [(char, &[&str]); 7] = [('d', &["domain", "superkingdom", "realm"]),
+                                          ('p', &["phylum"]),
+                                          ('c', &["class"]),
+                                          ('o', &["order"]),
+                                          ('f', &["family"]),
+                                          ('g', &["genus"]),
+                                          ('s', &["species"])];
+
+// A node of the taxonomy tree, with its NCBI rank (e.g. species, strain, no rank)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Taxon {
+    taxid: TaxId,
+    rank: String,
+    name: String,
+}
+
+impl Taxon {
+    pub fn get_taxid(&self) -> TaxId { self.taxid }
+    pub fn get_rank(&self) -> &str { &self.rank }
+    pub fn get_name(&self) -> &str { &self.name }
+}
+
+/*
+The path from the root (excluded) down to a taxon, keeping every rank of the taxdump.
+Unlike Taxonomy, it is not limited to the seven GTDB ranks, so strains, subspecies or viral realms are kept.
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TaxonLineage {
+    taxa: Vec<Taxon>,
+}
+
+impl TaxonLineage {
+    pub fn get_taxa(&self) -> &[Taxon] { &self.taxa }
+    // The taxid of the lineage end, e.g. the strain of a genome
+    pub fn get_taxid(&self) -> Option<TaxId> { self.taxa.last().map(|t| t.taxid) }
+    pub fn get_taxon(&self, rank: &str) -> Option<&Taxon> { self.taxa.iter().find(|t| t.rank == rank) }
+    pub fn contains(&self, taxid: TaxId) -> bool { self.taxa.iter().any(|t| t.taxid == taxid) }
+
+    // The deepest taxon shared by both lineages, None if they only share the root
+    pub fn lcu(&self, other: &TaxonLineage) -> Option<&Taxon> {
+        self.taxa.iter()
+            .zip(other.taxa.iter())
+            .take_while(|(a, b)| a.taxid == b.taxid)
+            .last()
+            .map(|(a, _)| a)
+    }
+
+    pub fn lca(&self, other: &TaxonLineage) -> Option<TaxonLineage> {
+        self.lcu(other).and_then(|taxon| self.limit2taxid(taxon.taxid))
+    }
+
+    // True if both lineages share the same taxon at the given rank (any rank name of the taxdump)
+    pub fn equal_to_rank(&self, other: &TaxonLineage, rank: &str) -> bool {
+        self.get_taxon(rank).is_some_and(|taxon| other.contains(taxon.taxid))
+    }
+
+    // The stem of the lineage, up-to, and including, the given rank. None if the lineage has no such rank.
+    pub fn limit2rank(&self, rank: &str) -> Option<TaxonLineage> {
+        self.get_taxon(rank).and_then(|taxon| self.limit2taxid(taxon.taxid))
+    }
+
+    fn limit2taxid(&self, taxid: TaxId) -> Option<TaxonLineage> {
+        self.taxa.iter().position(|t| t.taxid == taxid).map(|i| TaxonLineage { taxa: self.taxa[..=i].to_vec() })
+    }
+
+    // In the GTDB string form, e.g. d__Bacteria;p__...;s__Escherichia coli. Ranks missing from the lineage are left empty.
+    pub fn to_gtdb_string(&self) -> String {
+        GTDB_RANKS.iter()
+            .map(|(prefix, ranks)| {
+                let name = self.taxa.iter()
+                    .find(|t| ranks.contains(&t.rank.as_str()))
+                    .map_or("", |t| t.name.as_str());
+                format!("{}__{}", prefix, name.replace(';', ","))
+            })
+            .collect::<Vec<String>>()
+            .join(";")
+    }
+
+    pub fn to_taxonomy(&self) -> Taxonomy { Taxonomy::from(self.to_gtdb_string().as_str()) }
+
+    pub fn get_taxid_path(&self) -> String {
+        self.taxa.iter().map(|t| t.taxid.to_string()).collect::<Vec<String>>().join(";")
+    }
+}
+
+// The names of the lineage, root first, e.g. Bacteria;Pseudomonadota;...;Escherichia coli K-12
+impl Display for TaxonLineage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.taxa.iter().map(|t| t.name.replace(';', ",")).collect::<Vec<String>>().join(";"))
+    }
+}
+
+struct TaxNode {
+    parent: TaxId,
+    rank: String,
+}
+
+pub struct TaxDump {
+    nodes: HashMap<TaxId, TaxNode>,
+    names: HashMap<TaxId, String>,
+}
+
+fn dmp_fields(line: &str) -> Vec<&str> {
+    line.trim_end_matches(['\r', '\n']).trim_end_matches("\t|").split("\t|\t").collect()
+}
+
+fn parse_taxid(value: &str, path: &Path, line_number: usize) -> std::io::Result<TaxId> {
+    value.trim().parse::<TaxId>()
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("E: invalid taxid '{}' in line {} of '{}' ({})", value, line_number, path.display(), e)))
+}
+
+impl TaxDump {
+    // Loads nodes.dmp and names.dmp from the taxdump folder
+    pub fn load(taxdump: &Path) -> std::io::Result<Self> {
+        Self::from_files(taxdump.join("nodes.dmp").as_path(), taxdump.join("names.dmp").as_path())
+    }
+
+    pub fn from_files(nodes_path: &Path, names_path: &Path) -> std::io::Result<Self> {
+        let mut nodes = HashMap::new();
+        for (i, line) in BufReader::new(File::open(nodes_path)?).lines().enumerate() {
+            let line = line?;
+            let fields = dmp_fields(&line);
+            if fields.len() < 3 {
+                return Err(Error::new(ErrorKind::InvalidData, format!("E: line {} of '{}' has less than 3 fields", i + 1, nodes_path.display())));
+            }
+            nodes.insert(parse_taxid(fields[0], nodes_path, i + 1)?,
+                         TaxNode { parent: parse_taxid(fields[1], nodes_path, i + 1)?, rank: fields[2].trim().to_string() });
+        }
+
+        let mut names = HashMap::new();
+        for (i, line) in BufReader::new(File::open(names_path)?).lines().enumerate() {
+            let line = line?;
+            let fields = dmp_fields(&line);
+            if fields.len() >= 4 && fields[3].trim() == "scientific name" {
+                names.insert(parse_taxid(fields[0], names_path, i + 1)?, fields[1].trim().to_string());
+            }
+        }
+
+        Ok(Self { nodes, names })
+    }
+
+    pub fn len(&self) -> usize { self.nodes.len() }
+    pub fn is_empty(&self) -> bool { self.nodes.is_empty() }
+    pub fn contains(&self, taxid: TaxId) -> bool { self.nodes.contains_key(&taxid) }
+    pub fn get_name(&self, taxid: TaxId) -> Option<&str> { self.names.get(&taxid).map(|n| n.as_str()) }
+    pub fn get_rank(&self, taxid: TaxId) -> Option<&str> { self.nodes.get(&taxid).map(|n| n.rank.as_str()) }
+    pub fn get_parent(&self, taxid: TaxId) -> Option<TaxId> {
+        self.nodes.get(&taxid).map(|n| n.parent).filter(|&parent| parent != taxid)
+    }
+
+    // The path from the root (excluded) down to taxid, empty if taxid is unknown
+    pub fn lineage(&self, taxid: TaxId) -> Vec<TaxId> {
+        let mut lineage = Vec::new();
+        let mut current = Some(taxid).filter(|&t| self.contains(t));
+        while let Some(t) = current {
+            if t == ROOT_TAXID || lineage.len() > self.nodes.len() {
+                break;
+            }
+            lineage.push(t);
+            current = self.get_parent(t);
+        }
+        lineage.reverse();
+        lineage
+    }
+
+    // The lineage with names and ranks, None if taxid is unknown
+    pub fn get_lineage(&self, taxid: TaxId) -> Option<TaxonLineage> {
+        let taxa = self.lineage(taxid).into_iter()
+            .map(|t| Taxon {
+                taxid: t,
+                rank: self.get_rank(t).unwrap_or_default().to_string(),
+                name: self.get_name(t).map_or(t.to_string(), |n| n.to_string()),
+            })
+            .collect::<Vec<Taxon>>();
+        if taxa.is_empty() { None } else { Some(TaxonLineage { taxa }) }
+    }
+
+    // The lineage in the GTDB string form, see TaxonLineage::to_gtdb_string
+    pub fn gtdb_taxonomy(&self, taxid: TaxId) -> Option<String> {
+        self.get_lineage(taxid).map(|lineage| lineage.to_gtdb_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::ncbi_taxdump::TaxDump;
+
+    #[test]
+    fn taxdump_lineage() {
+        let taxdump = TaxDump::load(Path::new("../tests/import/taxdump")).unwrap();
+        assert_eq!(taxdump.get_name(562), Some("Escherichia coli"));
+        assert_eq!(taxdump.get_rank(562), Some("species"));
+        assert_eq!(taxdump.lineage(562), vec![131567, 2, 1224, 1236, 91347, 543, 561, 562]);
+        assert_eq!(taxdump.gtdb_taxonomy(562).unwrap(),
+                   "d__Bacteria;p__Pseudomonadota;c__Gammaproteobacteria;o__Enterobacterales;f__Enterobacteriaceae;g__Escherichia;s__Escherichia coli");
+        // Strains are placed under their species
+        assert_eq!(taxdump.gtdb_taxonomy(83333), taxdump.gtdb_taxonomy(562));
+        assert!(taxdump.gtdb_taxonomy(999999).is_none());
+    }
+
+    #[test]
+    fn lineage_ranks() {
+        let taxdump = TaxDump::load(Path::new("../tests/import/taxdump")).unwrap();
+        let k12 = taxdump.get_lineage(83333).unwrap();
+        let subtilis = taxdump.get_lineage(224308).unwrap();
+        assert_eq!(k12.get_taxid(), Some(83333));
+        assert_eq!(k12.get_taxon("strain").map(|t| t.get_name()), Some("Escherichia coli K-12"));
+        assert_eq!(k12.get_taxid_path(), "131567;2;1224;1236;91347;543;561;562;83333");
+
+        // Ranks below species are kept, and shared taxa are found whatever their rank
+        assert_eq!(k12.lcu(&subtilis).map(|t| t.get_taxid()), Some(2));
+        assert_eq!(k12.lca(&subtilis).unwrap().to_string(), "cellular organisms;Bacteria");
+        assert!(k12.equal_to_rank(&taxdump.get_lineage(562).unwrap(), "species"));
+        assert!(!k12.equal_to_rank(&taxdump.get_lineage(562).unwrap(), "strain"));
+        assert!(k12.equal_to_rank(&subtilis, "superkingdom"));
+        assert!(!k12.equal_to_rank(&subtilis, "phylum"));
+        assert_eq!(k12.limit2rank("genus").and_then(|l| l.get_taxid()), Some(561));
+        assert!(k12.limit2rank("realm").is_none());
+
+        assert_eq!(k12.to_taxonomy().to_string(), taxdump.gtdb_taxonomy(562).unwrap());
+    }
+}
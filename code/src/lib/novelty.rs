@@ -0,0 +1,137 @@
+// Novelty of a query relative to the references, e.g. a new genus rather than a poor match to a known one.
+// Each model keeps the distribution of its scores on the other references, grouped by the deepest rank they share.
+// A query belongs at a rank while its best-hit score is within that rank's distribution.
+
+use std::io::{Error, ErrorKind};
+use hashbrown::HashMap;
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use crate::cached_fasta_nucleutide_iterator::CachedFastaNucltudiesIterator;
+use crate::classifier::Classifier;
+use crate::samples_file_reader::Sample;
+use crate::taxonomy::{TaxonomicRank, Taxonomy};
+
+// Scores up-to this many standard deviations above the mean of a rank are still within it
+const PLAUSIBLE_SDS: f64 = 2.0;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RankDistribution {
+    rank: TaxonomicRank,
+    count: usize,
+    mean: f64,
+    sd: f64,
+}
+
+impl RankDistribution {
+    fn new(rank: TaxonomicRank, scores: &[f64]) -> Self {
+        let count = scores.len();
+        let mean = scores.iter().sum::<f64>() / count as f64;
+        let sd = (scores.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / count as f64).sqrt();
+        Self { rank, count, mean, sd }
+    }
+
+    pub fn get_rank(&self) -> TaxonomicRank { self.rank }
+    pub fn get_count(&self) -> usize { self.count }
+    pub fn get_mean(&self) -> f64 { self.mean }
+    pub fn get_sd(&self) -> f64 { self.sd }
+
+    // Lower scores are better, so only scores too high are outside the rank
+    fn is_plausible(&self, score: f64) -> bool {
+        score <= self.mean + PLAUSIBLE_SDS * self.sd
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NoveltyProfile {
+    // From the domain down, only ranks shared with at least one other reference
+    ranks: Vec<RankDistribution>,
+}
+
+impl NoveltyProfile {
+    /*
+    Profile of a model with the given taxonomy, from its scores on the other references.
+    A reference sharing the genus but not the species adds its score to the genus, and so on.
+     */
+    pub fn new(taxonomy: &Taxonomy, others: &[(&Taxonomy, f64)]) -> Self {
+        let mut rank2scores: std::collections::HashMap<TaxonomicRank, Vec<f64>> = std::collections::HashMap::new();
+        for (other, score) in others {
+            if let Some(rank) = taxonomy.lcu(other) {
+                rank2scores.entry(rank).or_default().push(*score);
+            }
+        }
+        Self {
+            ranks: TaxonomicRank::down_iterator()
+                .filter_map(|rank| rank2scores.get(&rank).map(|scores| RankDistribution::new(rank, scores)))
+                .collect(),
+        }
+    }
+
+    pub fn get_ranks(&self) -> &[RankDistribution] { &self.ranks }
+
+    /*
+    The deepest rank at which a query with this best-hit score still plausibly belongs to the taxon of the model,
+    None if it is outside every rank, or the model shares no rank with any other reference.
+     */
+    pub fn deepest_rank(&self, score: f64) -> Option<TaxonomicRank> {
+        self.ranks.iter()
+            .take_while(|distribution| distribution.is_plausible(score))
+            .last()
+            .map(|distribution| distribution.rank)
+    }
+}
+
+/*
+Scores every model on the FASTA of every other reference and summarizes them per model.
+Quadratic in the number of references. Scores are the raw (not reflected) ones.
+ */
+pub fn novelty_profiles(classifier: &Classifier, buffer_size: usize) -> std::io::Result<HashMap<String, NoveltyProfile>> {
+    let model2taxonomy = classifier.collect_model2taxonomy();
+    let references = classifier.into_iter().collect::<Vec<Sample>>();
+    if let Some(reference) = references.iter().find(|r| !model2taxonomy.contains_key(r.get_name())) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("E: model '{}' has no taxonomy, novelty needs the taxonomy of every model", reference.get_name())));
+    }
+
+    let reference2scores = references.par_iter()
+        .map(|reference| {
+            let sequence = CachedFastaNucltudiesIterator::from_paths(reference.get_paths(), buffer_size);
+            let scores = classifier.predict(sequence, None, &None, false).0.into_iter()
+                .filter_map(|(model, score)| score.map(|score| (model.clone(), score)))
+                .collect::<Vec<(String, f64)>>();
+            (reference.get_name().to_string(), scores)
+        })
+        .collect::<Vec<_>>();
+
+    let mut model2others: HashMap<String, Vec<(&Taxonomy, f64)>> = HashMap::new();
+    for (reference, scores) in &reference2scores {
+        for (model, score) in scores.iter().filter(|(model, _)| model != reference) {
+            model2others.entry(model.clone()).or_default().push((&model2taxonomy[reference], *score));
+        }
+    }
+
+    Ok(model2taxonomy.iter()
+        .map(|(model, taxonomy)| (model.clone(), NoveltyProfile::new(taxonomy, model2others.get(model).map_or(&[], |others| others.as_slice()))))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::novelty::NoveltyProfile;
+    use crate::taxonomy::{TaxonomicRank, Taxonomy};
+
+    #[test]
+    fn deepest_rank() {
+        let model = Taxonomy::from("d__Bacteria;p__Firmicutes;c__Bacilli;o__Lactobacillales;f__Streptococcaceae;g__Streptococcus;s__Streptococcus mitis");
+        let same_genus = Taxonomy::from("d__Bacteria;p__Firmicutes;c__Bacilli;o__Lactobacillales;f__Streptococcaceae;g__Streptococcus;s__Streptococcus oralis");
+        let same_class = Taxonomy::from("d__Bacteria;p__Firmicutes;c__Bacilli;o__Bacillales;f__Bacillaceae;g__Bacillus;s__Bacillus subtilis");
+        let other_domain = Taxonomy::from("d__Archaea;p__Thermoproteota");
+        let others = [(&same_genus, 1.50), (&same_genus, 1.60), (&same_class, 1.85), (&same_class, 1.95), (&other_domain, 2.0)];
+        let profile = NoveltyProfile::new(&model, &others);
+
+        assert_eq!(profile.get_ranks().iter().map(|d| (d.get_rank(), d.get_count())).collect::<Vec<_>>(),
+                   vec![(TaxonomicRank::Class, 2), (TaxonomicRank::Genus, 2)]);
+        assert_eq!(profile.deepest_rank(1.40), Some(TaxonomicRank::Genus));
+        // Too far for the genus, but still within the class
+        assert_eq!(profile.deepest_rank(1.80), Some(TaxonomicRank::Class));
+        assert_eq!(profile.deepest_rank(2.50), None);
+    }
+}
@@ -1,12 +1,81 @@
 use std::collections::hash_map::IterMut;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
 use std::path::{Path, PathBuf};
 use std::fs::File;
-use std::io::{BufWriter, Write};
-use std::io::Error;
+use std::io::{BufWriter, Stdout, Write};
+use std::io::{Error, ErrorKind};
+use chrono::Utc;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use polars::io::parquet::write::BatchedWriter;
+use polars::prelude::{Column, DataFrame, DataType, Field, ParquetCompression, ParquetWriter, Schema};
+use serde_json::json;
+use crate::profile::ProfileUnit;
+use crate::taxonomy::{TaxonomicRank, Taxonomy};
 
-pub type OutputStream = Box<BufWriter<File>>;
+pub type OutputStream = Box<BufWriter<OutputSink>>;
+
+// Output path for writing to stdout instead of a file
+pub const STDOUT_PATH: &str = "-";
+
+/// Where an output stream ends up, a plain file, stdout, or a file compressed on the fly by its extension (.gz or .zst).
+pub enum OutputSink {
+    Plain(File),
+    Stdout(Stdout),
+    Gz(GzEncoder<File>),
+    Zstd(zstd::Encoder<'static, File>),
+}
+
+impl OutputSink {
+    pub fn new(file_path: &Path) -> Result<Self, Error> {
+        if file_path.as_os_str() == STDOUT_PATH {
+            return Ok(OutputSink::Stdout(std::io::stdout()));
+        }
+        let file = File::create(file_path)?;
+        match file_path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Ok(OutputSink::Gz(GzEncoder::new(file, Compression::default()))),
+            Some("zst") => Ok(OutputSink::Zstd(zstd::Encoder::new(file, 0)?)),
+            _ => Ok(OutputSink::Plain(file)),
+        }
+    }
+
+    // Writes the end of the compressed stream, nothing may be written afterwards
+    pub fn try_finish(&mut self) -> std::io::Result<()> {
+        match self {
+            OutputSink::Plain(_) | OutputSink::Stdout(_) => Ok(()),
+            OutputSink::Gz(encoder) => encoder.try_finish(),
+            OutputSink::Zstd(encoder) => encoder.do_finish(),
+        }
+    }
+}
+
+impl Write for OutputSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            OutputSink::Plain(file) => file.write(buf),
+            OutputSink::Stdout(stdout) => stdout.write(buf),
+            OutputSink::Gz(encoder) => encoder.write(buf),
+            OutputSink::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            OutputSink::Plain(file) => file.flush(),
+            OutputSink::Stdout(stdout) => stdout.flush(),
+            OutputSink::Gz(encoder) => encoder.flush(),
+            OutputSink::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+// A compressed stream that was not finished explicitly is still readable, as with flate2's encoders
+impl Drop for OutputSink {
+    fn drop(&mut self) {
+        let _ = self.try_finish();
+    }
+}
 
 #[derive(Eq, PartialEq, Copy, Clone, Hash)]
 pub enum OutputFileType {
@@ -14,6 +83,14 @@ pub enum OutputFileType {
     BaseGz,
     /// Expressive LZ values table
     LzValues,
+    /// Krona text input, the abundance of each taxonomy path over all predictions
+    Krona,
+    /// BIOM (JSON) table of the abundance of each taxonomy (rows) in each sample (columns)
+    Biom,
+    /// A JSON object per prediction, with the best scores, taxonomy and warnings
+    JsonLines,
+    /// LZ values in long form (query, model, score) as Parquet, without the filtered models
+    LzParquet,
 }
 
 impl Display for OutputFileType {
@@ -21,19 +98,177 @@ impl Display for OutputFileType {
         let str = match self {
             OutputFileType::BaseGz => String::from("Base GZ"),
             OutputFileType::LzValues => String::from("LZ Values"),
+            OutputFileType::Krona => String::from("Krona"),
+            OutputFileType::Biom => String::from("BIOM"),
+            OutputFileType::JsonLines => String::from("JSON Lines"),
+            OutputFileType::LzParquet => String::from("LZ Values Parquet"),
         };
         write!(f, "{}", str)
     }
 }
 
+/// Abundance of the best-hit taxonomies per sample, written by the Krona and BIOM streams once all predictions are in.
+struct TaxonomyTally {
+    unit: ProfileUnit,
+    // All predictions are of this sample, e.g. the contigs of a metagenome, else each prediction is its own sample
+    sample: Option<String>,
+    // Weight and group of the current prediction, from the weight and group columns of a sample sheet
+    weight: f64,
+    group: Option<String>,
+    samples: Vec<String>,
+    // Keyed by the taxonomy string, None for predictions with no taxonomy
+    taxa: BTreeMap<String, Option<Taxonomy>>,
+    abundance: HashMap<(String, usize), f64>,
+}
+
+impl TaxonomyTally {
+    fn add(&mut self, name: &str, taxonomy: Option<&Taxonomy>, length: usize) {
+        let sample = self.sample.as_deref().or(self.group.as_deref()).unwrap_or(name);
+        let sample = match self.samples.iter().position(|s| s == sample) {
+            Some(index) => index,
+            None => {
+                self.samples.push(sample.to_string());
+                self.samples.len() - 1
+            },
+        };
+        let key = taxonomy.map_or(String::from(UNCLASSIFIED), |t| t.to_string());
+        self.taxa.entry(key.clone()).or_insert_with(|| taxonomy.cloned());
+        *self.abundance.entry((key, sample)).or_default() += match self.unit {
+            ProfileUnit::Bases => length as f64 * self.weight,
+            ProfileUnit::Sequences => self.weight,
+        };
+    }
+
+    // Named ranks of the taxonomy, as (name, name with the rank prefix)
+    fn lineage(taxonomy: &Option<Taxonomy>) -> Vec<(String, String)> {
+        match taxonomy {
+            Some(taxonomy) => TaxonomicRank::down_iterator()
+                .filter(|rank| taxonomy.has_rank(rank))
+                .map(|rank| (taxonomy.get_taxa(&rank).cloned().unwrap_or_default(), taxonomy.limit2rank(&rank).deepest_taxon().unwrap_or_default()))
+                .collect(),
+            None => vec![(String::from(UNCLASSIFIED), String::from(UNCLASSIFIED))],
+        }
+    }
+
+    // Krona text input (ktImportText), the abundance over all samples followed by the names of the taxonomy path
+    fn write_krona<W: Write>(&self, fout: &mut W) -> std::io::Result<()> {
+        for (key, taxonomy) in &self.taxa {
+            let abundance = (0..self.samples.len()).filter_map(|sample| self.abundance.get(&(key.clone(), sample))).sum::<f64>();
+            let names = TaxonomyTally::lineage(taxonomy).into_iter().map(|(name, _)| name).collect::<Vec<String>>();
+            writeln!(fout, "{}\t{}", abundance, names.join("\t"))?;
+        }
+        Ok(())
+    }
+
+    // BIOM 1.0 (JSON) sparse table, taxonomies are rows and samples are columns
+    fn write_biom<W: Write>(&self, fout: &mut W) -> std::io::Result<()> {
+        let rows = self.taxa.iter()
+            .map(|(key, taxonomy)| json!({
+                "id": key,
+                "metadata": {"taxonomy": TaxonomyTally::lineage(taxonomy).into_iter().map(|(_, prefixed)| prefixed).collect::<Vec<String>>()},
+            }))
+            .collect::<Vec<_>>();
+        let data = self.taxa.keys().enumerate()
+            .flat_map(|(row, key)| (0..self.samples.len())
+                .filter_map(move |column| self.abundance.get(&(key.clone(), column)).map(|abundance| json!([row, column, abundance]))))
+            .collect::<Vec<_>>();
+        let biom = json!({
+            "id": null,
+            "format": "Biological Observation Matrix 1.0.0",
+            "format_url": "http://biom-format.org",
+            "type": "Taxon table",
+            "generated_by": format!("GeneZip {}", option_env!("CARGO_PKG_VERSION").unwrap_or("1.0.0")),
+            "date": Utc::now().to_rfc3339(),
+            "matrix_type": "sparse",
+            "matrix_element_type": "float",
+            "shape": [self.taxa.len(), self.samples.len()],
+            "data": data,
+            "rows": rows,
+            "columns": self.samples.iter().map(|sample| json!({"id": sample, "metadata": null})).collect::<Vec<_>>(),
+        });
+        serde_json::to_writer(&mut *fout, &biom)?;
+        writeln!(fout)
+    }
+}
+
+const UNCLASSIFIED: &str = "Unclassified";
+
+// Rows of each Parquet row group, scores are held in memory only until a group fills up
+const PARQUET_ROW_GROUP: usize = 1 << 20;
+
+/// The LZ values of every prediction in long form, written to Parquet (zstd compressed) a row group at a time.
+struct ParquetScores {
+    writer: BatchedWriter<File>,
+    queries: Vec<String>,
+    models: Vec<String>,
+    scores: Vec<f64>,
+}
+
+impl ParquetScores {
+    fn new(file_path: &Path) -> Result<Self, Error> {
+        let schema = Schema::from_iter([
+            Field::new("query".into(), DataType::String),
+            Field::new("model".into(), DataType::String),
+            Field::new("score".into(), DataType::Float64),
+        ]);
+        let writer = ParquetWriter::new(File::create(file_path)?)
+            .with_compression(ParquetCompression::Zstd(None))
+            .batched(&schema)
+            .map_err(Error::other)?;
+        Ok(Self {
+            writer,
+            queries: Vec::new(),
+            models: Vec::new(),
+            scores: Vec::new(),
+        })
+    }
+
+    // Models without a score, i.e. filtered by %G+C or k-mers, are left out
+    fn add(&mut self, name: &str, prediction: &[(&String, Option<f64>)]) -> std::io::Result<()> {
+        for (model_name, score) in prediction {
+            if let Some(score) = score {
+                self.queries.push(name.to_string());
+                self.models.push(model_name.to_string());
+                self.scores.push(*score);
+            }
+        }
+        if self.scores.len() >= PARQUET_ROW_GROUP {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn write_row_group(&mut self) -> std::io::Result<()> {
+        let row_group = DataFrame::new(vec![
+            Column::new("query".into(), std::mem::take(&mut self.queries)),
+            Column::new("model".into(), std::mem::take(&mut self.models)),
+            Column::new("score".into(), std::mem::take(&mut self.scores)),
+        ]).map_err(Error::other)?;
+        self.writer.write_batch(&row_group).map_err(Error::other)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        if !self.scores.is_empty() {
+            self.write_row_group()?;
+        }
+        self.writer.finish().map_err(Error::other)?;
+        Ok(())
+    }
+}
+
 /// A struct to hold output streams along withValues) the associated paths.
 pub struct OutputStreams {
     streams: HashMap<OutputFileType, OutputStream>,
     paths: HashMap<OutputFileType, PathBuf>,
+    tally: TaxonomyTally,
+    // The LZ values, when written as Parquet rather than a text stream
+    parquet: Option<ParquetScores>,
+    // Number of best scores of each JSON Lines prediction, 0 for all
+    json_hits: usize,
 }
 
 fn create_file(file_path: &Path) -> Result<OutputStream, Error> {
-    File::create(file_path).map(BufWriter::new).map(Box::new)
+    OutputSink::new(file_path).map(BufWriter::new).map(Box::new)
 }
 
 impl OutputStreams {
@@ -41,18 +276,68 @@ impl OutputStreams {
     pub fn new(stream_paths: &HashMap<OutputFileType, &Path>) -> Result<Self, Error> {
         let mut streams = HashMap::new();
         let mut paths = HashMap::new();
+        let mut parquet = None;
 
+        if stream_paths.values().filter(|file_path| file_path.as_os_str() == STDOUT_PATH).count() > 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "E: only a single output can be written to stdout ('-')"));
+        }
         for (file_type, file_path) in stream_paths {
-            streams.insert(*file_type, create_file(file_path)?);
+            match file_type {
+                OutputFileType::LzParquet => parquet = Some(ParquetScores::new(file_path)?),
+                _ => { streams.insert(*file_type, create_file(file_path)?); },
+            }
             paths.insert(*file_type, file_path.to_path_buf());
         }
 
         Ok(Self {
             streams,
-            paths
+            paths,
+            tally: TaxonomyTally {
+                unit: ProfileUnit::Sequences,
+                sample: None,
+                weight: 1.0,
+                group: None,
+                samples: Vec::new(),
+                taxa: BTreeMap::new(),
+                abundance: HashMap::new(),
+            },
+            parquet,
+            json_hits: 0,
         })
     }
 
+    /// Krona and BIOM abundances count the predictions by default, with bases they sum their lengths.
+    pub fn with_unit(mut self, unit: ProfileUnit) -> Self {
+        self.tally.unit = unit;
+        self
+    }
+
+    /// Only the best scores of each JSON Lines prediction are written, all of them for 0.
+    pub fn with_json_hits(mut self, json_hits: usize) -> Self {
+        self.json_hits = json_hits;
+        self
+    }
+
+    pub fn get_json_hits(&self) -> usize { self.json_hits }
+
+    /// Tally every following prediction under this sample, e.g. the contigs of a metagenome.
+    pub fn set_sample(&mut self, sample: &str) {
+        self.tally.sample = Some(sample.to_string());
+    }
+
+    /// Weight (1 if None) and group of the following prediction, from its sample sheet. Grouped predictions are a single BIOM sample.
+    pub fn set_query_columns(&mut self, weight: Option<f64>, group: Option<&str>) {
+        self.tally.weight = weight.unwrap_or(1.0);
+        self.tally.group = group.map(str::to_string);
+    }
+
+    /// Adds a prediction to the Krona and BIOM abundances, if any of them is written.
+    pub fn add_classification(&mut self, name: &str, taxonomy: Option<&Taxonomy>, length: usize) {
+        if self.streams.contains_key(&OutputFileType::Krona) || self.streams.contains_key(&OutputFileType::Biom) {
+            self.tally.add(name, taxonomy, length);
+        }
+    }
+
     pub fn stream(&mut self, file_type: &OutputFileType) -> Option<&mut OutputStream> { self.streams.get_mut(file_type) }
 
     pub fn streams_iter(&mut self) -> IterMut<OutputFileType, OutputStream> {
@@ -65,13 +350,39 @@ impl OutputStreams {
         }
         Ok(())
     }
+
+    /// Adds the LZ values of a prediction to the Parquet output, if it is written.
+    pub fn add_scores(&mut self, name: &str, prediction: &[(&String, Option<f64>)]) -> std::io::Result<()> {
+        match self.parquet.as_mut() {
+            Some(parquet) => parquet.add(name, prediction),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the Krona and BIOM abundances of all predictions, closes the Parquet output, flushes every stream and ends the compressed ones.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        if let Some(parquet) = self.parquet.as_mut() {
+            parquet.finish()?;
+        }
+        if let Some(fout) = self.streams.get_mut(&OutputFileType::Krona) {
+            self.tally.write_krona(fout)?;
+        }
+        if let Some(fout) = self.streams.get_mut(&OutputFileType::Biom) {
+            self.tally.write_biom(fout)?;
+        }
+        self.flush()?;
+        for fout in self.streams.values_mut() {
+            fout.get_mut().try_finish()?;
+        }
+        Ok(())
+    }
 }
 
 impl std::fmt::Display for OutputStreams {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "[")?;
         let mut first = true;
-        for oft in [OutputFileType::BaseGz, OutputFileType::LzValues] {
+        for oft in [OutputFileType::BaseGz, OutputFileType::LzValues, OutputFileType::Krona, OutputFileType::Biom, OutputFileType::JsonLines, OutputFileType::LzParquet] {
             if first {
                first = false;
             } else {
@@ -82,4 +393,81 @@ impl std::fmt::Display for OutputStreams {
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use crate::output_streams::{OutputFileType, OutputStreams, ParquetScores, TaxonomyTally};
+    use polars::prelude::{ParquetReader, SerReader};
+    use crate::profile::ProfileUnit;
+    use crate::taxonomy::Taxonomy;
+
+    #[test]
+    fn krona_and_biom() {
+        let mut tally = TaxonomyTally { unit: ProfileUnit::Bases, sample: None, weight: 1.0, group: None, samples: Vec::new(), taxa: BTreeMap::new(), abundance: HashMap::new() };
+        let taxonomy = Taxonomy::from("d__Bacteria;p__Firmicutes;c__;o__;f__;g__;s__");
+        tally.add("a", Some(&taxonomy), 100);
+        tally.add("b", Some(&taxonomy), 50);
+        tally.add("c", None, 10);
+
+        let mut krona = Vec::new();
+        tally.write_krona(&mut krona).unwrap();
+        assert_eq!(String::from_utf8(krona).unwrap(), "10\tUnclassified\n150\tBacteria\tFirmicutes\n");
+
+        let mut biom = Vec::new();
+        tally.write_biom(&mut biom).unwrap();
+        let biom: serde_json::Value = serde_json::from_slice(&biom).unwrap();
+        assert_eq!(biom["shape"], serde_json::json!([2, 3]));
+        assert_eq!(biom["data"], serde_json::json!([[0, 2, 10.0], [1, 0, 100.0], [1, 1, 50.0]]));
+        assert_eq!(biom["rows"][1]["metadata"]["taxonomy"], serde_json::json!(["d__Bacteria", "p__Firmicutes"]));
+
+        // Weights and groups of a sample sheet, the predictions of a group are a single sample
+        let mut tally = TaxonomyTally { unit: ProfileUnit::Sequences, sample: None, weight: 2.5, group: Some(String::from("metagenome")), samples: Vec::new(), taxa: BTreeMap::new(), abundance: HashMap::new() };
+        tally.add("a", Some(&taxonomy), 100);
+        tally.add("b", Some(&taxonomy), 50);
+        assert_eq!(tally.samples, vec!["metagenome"]);
+        assert_eq!(tally.abundance[&(taxonomy.to_string(), 0)], 5.0);
+    }
+    #[test]
+    fn lz_values_parquet() {
+        let path = std::env::temp_dir().join(format!("genezip_lz_values_{}.parquet", std::process::id()));
+        let (a, b) = (String::from("model_a"), String::from("model_b"));
+        let mut parquet = ParquetScores::new(&path).unwrap();
+        parquet.add("query_1", &[(&a, Some(1.5)), (&b, None)]).unwrap();
+        parquet.add("query_2", &[(&a, Some(1.25)), (&b, Some(2.0))]).unwrap();
+        parquet.finish().unwrap();
+
+        let scores = ParquetReader::new(std::fs::File::open(&path).unwrap()).finish().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(scores.get_column_names_str(), vec!["query", "model", "score"]);
+        // The filtered model is left out
+        assert_eq!(scores.height(), 3);
+        assert_eq!(scores.column("model").unwrap().str().unwrap().into_no_null_iter().collect::<Vec<_>>(), vec!["model_a", "model_a", "model_b"]);
+        assert_eq!(scores.column("score").unwrap().f64().unwrap().into_no_null_iter().collect::<Vec<_>>(), vec![1.5, 1.25, 2.0]);
+    }
+    #[test]
+    fn compressed_streams() {
+        let gz_path = std::env::temp_dir().join(format!("genezip_streams_{}.tsv.gz", std::process::id()));
+        let zst_path = std::env::temp_dir().join(format!("genezip_streams_{}.tsv.zst", std::process::id()));
+        let mut streams = OutputStreams::new(&[(OutputFileType::BaseGz, gz_path.as_path()), (OutputFileType::LzValues, zst_path.as_path())].into_iter().collect()).unwrap();
+        for (_, fout) in streams.streams_iter() {
+            writeln!(fout, "query\tmodel").unwrap();
+        }
+        streams.finish().unwrap();
+        drop(streams);
+
+        let mut gz = String::new();
+        flate2::read::MultiGzDecoder::new(std::fs::File::open(&gz_path).unwrap()).read_to_string(&mut gz).unwrap();
+        let zst = zstd::decode_all(std::fs::File::open(&zst_path).unwrap()).unwrap();
+        std::fs::remove_file(&gz_path).unwrap();
+        std::fs::remove_file(&zst_path).unwrap();
+        assert_eq!(gz, "query\tmodel\n");
+        assert_eq!(String::from_utf8(zst).unwrap(), "query\tmodel\n");
+
+        let stdout = Path::new("-");
+        assert!(OutputStreams::new(&[(OutputFileType::BaseGz, stdout), (OutputFileType::LzValues, stdout)].into_iter().collect()).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+// Distance-based trees (neighbor joining and UPGMA) written as Newick.
+// Used on GeneZip distances, the symmetric score minus one, as a quick alignment-free phylogeny.
+
+use std::fmt::{Display, Formatter};
+use clap::ValueEnum;
+use ndarray::Array2;
+use crate::taxonomy::Taxonomy;
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum TreeMethod {
+    // Neighbor joining, unrooted (written with a trifurcation at the root)
+    Nj,
+    // UPGMA, rooted and ultrametric
+    Upgma,
+}
+
+impl Display for TreeMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            TreeMethod::Nj => "nj",
+            TreeMethod::Upgma => "upgma",
+        })
+    }
+}
+
+struct Node {
+    // Index of the genome for leaves
+    leaf: Option<usize>,
+    // Child nodes and the length of the branch to each
+    children: Vec<(usize, f64)>,
+}
+
+pub struct Tree {
+    nodes: Vec<Node>,
+    root: usize,
+}
+
+impl Tree {
+    fn with_leaves(n: usize) -> Self {
+        Self {
+            nodes: (0..n).map(|i| Node { leaf: Some(i), children: Vec::new() }).collect(),
+            root: 0,
+        }
+    }
+
+    fn join(&mut self, children: Vec<(usize, f64)>) -> usize {
+        self.nodes.push(Node { leaf: None, children });
+        self.nodes.len() - 1
+    }
+
+    // Genomes under the node
+    fn leaves(&self, node: usize) -> Vec<usize> {
+        let mut leaves = Vec::new();
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            leaves.extend(self.nodes[node].leaf);
+            stack.extend(self.nodes[node].children.iter().map(|(child, _)| *child));
+        }
+        leaves
+    }
+
+    /*
+    Newick of the tree, leaves named by names. With taxonomies, internal nodes are labeled by the deepest taxon
+    shared by all their leaves with a taxonomy, and left unlabeled if there is none.
+     */
+    pub fn to_newick(&self, names: &[String], taxonomies: Option<&[Option<Taxonomy>]>) -> String {
+        let mut newick = String::new();
+        self.write_node(self.root, names, taxonomies, &mut newick);
+        newick.push(';');
+        newick
+    }
+
+    fn write_node(&self, node: usize, names: &[String], taxonomies: Option<&[Option<Taxonomy>]>, newick: &mut String) {
+        let Node { leaf, children } = &self.nodes[node];
+        if let Some(leaf) = leaf {
+            newick.push_str(&newick_label(&names[*leaf]));
+            return;
+        }
+        newick.push('(');
+        for (i, (child, length)) in children.iter().enumerate() {
+            if i > 0 {
+                newick.push(',');
+            }
+            self.write_node(*child, names, taxonomies, newick);
+            newick.push_str(&format!(":{:.6}", length));
+        }
+        newick.push(')');
+        if let Some(label) = taxonomies.and_then(|taxonomies| self.lca_label(node, taxonomies)) {
+            newick.push_str(&newick_label(&label));
+        }
+    }
+
+    fn lca_label(&self, node: usize, taxonomies: &[Option<Taxonomy>]) -> Option<String> {
+        let mut lca: Option<Taxonomy> = None;
+        for taxonomy in self.leaves(node).into_iter().filter_map(|leaf| taxonomies[leaf].as_ref()) {
+            lca = Some(match lca {
+                None => taxonomy.clone(),
+                Some(lca) => lca.lca(taxonomy)?,
+            });
+        }
+        lca.and_then(|lca| lca.deepest_taxon())
+    }
+}
+
+// Names with Newick punctuation or whitespace are quoted, with quotes doubled
+fn newick_label(name: &str) -> String {
+    if name.chars().any(|c| c.is_whitespace() || "()[]':;,".contains(c)) {
+        format!("'{}'", name.replace('\'', "''"))
+    } else {
+        name.to_string()
+    }
+}
+
+pub fn upgma(distances: &Array2<f64>) -> Tree {
+    let n = distances.nrows();
+    let mut tree = Tree::with_leaves(n);
+    // Active clusters as (node, size, height)
+    let mut clusters = (0..n).map(|i| (i, 1_usize, 0.0)).collect::<Vec<(usize, usize, f64)>>();
+    let mut d = distances.clone();
+    // Rows of d that belong to the active clusters, in the same order
+    let mut rows = (0..n).collect::<Vec<usize>>();
+
+    while clusters.len() > 1 {
+        let (a, b) = closest_pair(clusters.len(), |i, j| d[[rows[i], rows[j]]]);
+        let height = d[[rows[a], rows[b]]] / 2.0;
+        let ((node_a, size_a, height_a), (node_b, size_b, height_b)) = (clusters[a], clusters[b]);
+        let node = tree.join(vec![(node_a, (height - height_a).max(0.0)), (node_b, (height - height_b).max(0.0))]);
+
+        // The merged cluster takes the row of a, distances averaged by cluster size
+        for k in 0..clusters.len() {
+            if k != a && k != b {
+                let merged = (d[[rows[a], rows[k]]] * size_a as f64 + d[[rows[b], rows[k]]] * size_b as f64) / (size_a + size_b) as f64;
+                d[[rows[a], rows[k]]] = merged;
+                d[[rows[k], rows[a]]] = merged;
+            }
+        }
+        clusters[a] = (node, size_a + size_b, height);
+        clusters.remove(b);
+        rows.remove(b);
+    }
+
+    tree.root = clusters.first().map_or(0, |(node, _, _)| *node);
+    tree
+}
+
+pub fn neighbor_joining(distances: &Array2<f64>) -> Tree {
+    let n = distances.nrows();
+    let mut tree = Tree::with_leaves(n);
+    let mut nodes = (0..n).collect::<Vec<usize>>();
+    let mut d = distances.clone();
+    let mut rows = (0..n).collect::<Vec<usize>>();
+
+    while nodes.len() > 3 {
+        let r = nodes.len();
+        let totals = (0..r).map(|i| (0..r).map(|k| d[[rows[i], rows[k]]]).sum::<f64>()).collect::<Vec<f64>>();
+        let (a, b) = closest_pair(r, |i, j| (r as f64 - 2.0) * d[[rows[i], rows[j]]] - totals[i] - totals[j]);
+        let d_ab = d[[rows[a], rows[b]]];
+        let length_a = d_ab / 2.0 + (totals[a] - totals[b]) / (2.0 * (r as f64 - 2.0));
+        let length_b = d_ab - length_a;
+        let node = tree.join(vec![(nodes[a], length_a.max(0.0)), (nodes[b], length_b.max(0.0))]);
+
+        for k in 0..r {
+            if k != a && k != b {
+                let joined = (d[[rows[a], rows[k]]] + d[[rows[b], rows[k]]] - d_ab) / 2.0;
+                d[[rows[a], rows[k]]] = joined;
+                d[[rows[k], rows[a]]] = joined;
+            }
+        }
+        nodes[a] = node;
+        nodes.remove(b);
+        rows.remove(b);
+    }
+
+    tree.root = match nodes.len() {
+        0 => 0,
+        1 => nodes[0],
+        2 => tree.join(vec![(nodes[0], d[[rows[0], rows[1]]].max(0.0)), (nodes[1], 0.0)]),
+        _ => {
+            // The last three meet at the root
+            let distance = |i: usize, j: usize| d[[rows[i], rows[j]]];
+            let lengths = [
+                (distance(0, 1) + distance(0, 2) - distance(1, 2)) / 2.0,
+                (distance(0, 1) + distance(1, 2) - distance(0, 2)) / 2.0,
+                (distance(0, 2) + distance(1, 2) - distance(0, 1)) / 2.0,
+            ];
+            tree.join((0..3).map(|i| (nodes[i], lengths[i].max(0.0))).collect())
+        },
+    };
+    tree
+}
+
+// The pair (i < j) with the smallest value, the first one on ties
+fn closest_pair<F: Fn(usize, usize) -> f64>(n: usize, value: F) -> (usize, usize) {
+    let mut best = (0, 1);
+    let mut best_value = f64::INFINITY;
+    for i in 0..n {
+        for j in i + 1..n {
+            let v = value(i, j);
+            if v < best_value {
+                best_value = v;
+                best = (i, j);
+            }
+        }
+    }
+    best
+}
+
+pub fn build_tree(distances: &Array2<f64>, method: TreeMethod) -> Tree {
+    match method {
+        TreeMethod::Nj => neighbor_joining(distances),
+        TreeMethod::Upgma => upgma(distances),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+    use crate::phylogeny::{neighbor_joining, upgma};
+    use crate::taxonomy::Taxonomy;
+
+    #[test]
+    fn trees() {
+        // The classic additive example, NJ recovers the branch lengths exactly
+        let distances = array![
+            [0.0, 5.0, 9.0, 9.0, 8.0],
+            [5.0, 0.0, 10.0, 10.0, 9.0],
+            [9.0, 10.0, 0.0, 8.0, 7.0],
+            [9.0, 10.0, 8.0, 0.0, 3.0],
+            [8.0, 9.0, 7.0, 3.0, 0.0],
+        ];
+        let names = ["a", "b", "c", "d", "e"].map(String::from);
+        assert_eq!(neighbor_joining(&distances).to_newick(&names, None),
+                   "(((a:2.000000,b:3.000000):3.000000,c:4.000000):2.000000,d:2.000000,e:1.000000);");
+
+        let distances = array![
+            [0.0, 2.0, 6.0],
+            [2.0, 0.0, 6.0],
+            [6.0, 6.0, 0.0],
+        ];
+        let names = ["a b", "b", "c"].map(String::from);
+        let taxonomies = [
+            Some(Taxonomy::from("d__Bacteria;p__Firmicutes;c__Bacilli")),
+            Some(Taxonomy::from("d__Bacteria;p__Firmicutes;c__Clostridia")),
+            Some(Taxonomy::from("d__Bacteria;p__Proteobacteria")),
+        ];
+        assert_eq!(upgma(&distances).to_newick(&names, Some(&taxonomies)),
+                   "(('a b':1.000000,b:1.000000)p__Firmicutes:2.000000,c:3.000000)d__Bacteria;");
+    }
+}
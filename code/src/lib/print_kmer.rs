@@ -9,10 +9,11 @@ use rayon::prelude::*;
 use tempdir::TempDir;
 
 use crate::contig_naming::sequence_id2str;
+use crate::fasta_chunk_reader::FastaChunkReader;
 use crate::fasta_nucleutide_iterator::FastaNucltudiesIterator;
 use crate::fasta_records_iterator;
-use crate::kmer::{ALPHABET_MAP, create_normalized_profile, create_printed_vector, REV_ALPHABET_VALUES};
-use crate::samples_file_reader::{SampleError, SampleSource};
+use crate::kmer::{ALPHABET_MAP, create_normalized_profile, create_normalized_profile_from_chunks, create_printed_vector, REV_ALPHABET_VALUES};
+use crate::samples_file_reader::{merge_repeated_samples, SampleError, SampleSource};
 
 pub enum PrintKmersError {
     IoError(Error),
@@ -41,12 +42,14 @@ impl Debug for PrintKmersError {
 }
 
 pub type KMerResults = Result<ndarray::Array1<f64>, String>;
+// A sample repeating its name over several lines is profiled over all its FASTA files
 pub fn calc_kmers(input: &Path, k: usize, buffer_size: usize) -> Result<Vec<(String, KMerResults)>, SampleError> {
-    Ok(SampleSource::new(input, false)
+    let samples = SampleSource::new(input, false)
         .into_iter()
-        .collect::<Result<Vec<_>, SampleError>>()?
+        .collect::<Result<Vec<_>, SampleError>>()?;
+    Ok(merge_repeated_samples(&samples)?
         .par_iter()
-        .map(|sample| (sample.get_name().to_string(), create_normalized_profile(k, FastaNucltudiesIterator::new(sample.get_path(), buffer_size), &false).1))
+        .map(|sample| (sample.get_name().to_string(), create_normalized_profile_from_chunks(k, FastaChunkReader::from_paths(sample.get_paths(), buffer_size))))
         .collect())
 }
 
@@ -0,0 +1,388 @@
+// Community composition of a metagenome from the per-sequence predictions.
+// Sequences are weighted by their length (bases) or counted (sequences), optionally times a coverage or read count.
+// Mass unclassified at a rank stays with the deepest classified ancestor, it is written as a CAMI profile and a Kraken-style report.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+use clap::ValueEnum;
+use hashbrown::HashMap;
+use crate::samples_file_reader::{is_sample_sheet, SampleSource};
+use crate::taxonomy::{TaxonomicRank, Taxonomy};
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum ProfileUnit {
+    // Sequence length times the weight, e.g. contig length times coverage
+    Bases,
+    // The weight alone, e.g. the number of reads
+    Sequences,
+}
+
+impl Display for ProfileUnit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            ProfileUnit::Bases => "bases",
+            ProfileUnit::Sequences => "sequences",
+        })
+    }
+}
+
+// A row of a prediction table
+pub struct Classification {
+    name: String,
+    length: usize,
+    best_hit: Option<String>,
+    // The Novelty_rank column, when the database has novelty profiles. None within it means novel at every rank.
+    novelty_rank: Option<Option<TaxonomicRank>>,
+}
+
+fn parse_rank(value: &str) -> Option<TaxonomicRank> {
+    TaxonomicRank::down_iterator().find(|rank| rank.to_string() == value)
+}
+
+// Genome_name, Length, Best_hit and (if present) Novelty_rank columns of a GeneZip prediction table
+pub fn read_classifications(path: &Path) -> std::io::Result<Vec<Classification>> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let position = |name: &str| header.split('\t').position(|c| c == name);
+    let column = |name: &str| position(name)
+        .ok_or(Error::new(ErrorKind::InvalidData, format!("E: '{}' has no {} column, expected a GeneZip prediction table", path.display(), name)));
+    let (name_column, length_column, hit_column) = (column("Genome_name")?, column("Length")?, column("Best_hit")?);
+    let novelty_column = position("Novelty_rank");
+
+    let mut classifications = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, format!("E: line {} of '{}' {}", i + 2, path.display(), message));
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        let field = |column: usize| fields.get(column).copied().ok_or_else(|| invalid(format!("has {} columns, expected at least {}", fields.len(), column + 1)));
+        let length = field(length_column)?;
+        let novelty_rank = match novelty_column {
+            Some(column) => Some(match field(column)? {
+                "NA" => None,
+                rank => Some(parse_rank(rank).ok_or_else(|| invalid(format!("has an unknown novelty rank '{}'", rank)))?),
+            }),
+            None => None,
+        };
+        classifications.push(Classification {
+            name: field(name_column)?.to_string(),
+            length: length.parse().map_err(|_| invalid(format!("has a non-numeric length '{}'", length)))?,
+            best_hit: Some(field(hit_column)?).filter(|&hit| hit != "NA").map(String::from),
+            novelty_rank,
+        });
+    }
+    Ok(classifications)
+}
+
+// Samples without a weight are left out, Profile::new reports them if they were predicted.
+// The first weight of a repeated sample (several FASTA files) is taken.
+fn read_sheet_weights(path: &Path) -> std::io::Result<HashMap<String, f64>> {
+    let mut weights = HashMap::new();
+    for sample in SampleSource::new(path, false) {
+        let sample = sample.map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        if let Some(weight) = sample.get_weight() {
+            weights.entry(sample.get_name().to_string()).or_insert(weight);
+        }
+    }
+    Ok(weights)
+}
+
+// Weights in the format <sequence-name>\t<weight>, e.g. coverage or read count, or the weight column of a sample sheet
+pub fn read_weights(path: &Path) -> std::io::Result<HashMap<String, f64>> {
+    if is_sample_sheet(path) {
+        return read_sheet_weights(path);
+    }
+    let mut weights = HashMap::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let weight = line.split_once('\t')
+            .and_then(|(name, weight)| weight.trim().parse::<f64>().ok().filter(|w| *w >= 0.0).map(|weight| (name, weight)));
+        match weight {
+            Some((name, weight)) => if weights.insert(name.to_string(), weight).is_some() {
+                return Err(Error::new(ErrorKind::InvalidData, format!("E: sequence '{}' appears more than once in '{}'", name, path.display())));
+            },
+            None => return Err(Error::new(ErrorKind::InvalidData, format!("E: line {} of '{}' is not in the format <sequence-name>\\t<non-negative weight>", i + 1, path.display()))),
+        }
+    }
+    Ok(weights)
+}
+
+struct Clade {
+    rank: TaxonomicRank,
+    // The taxon with its rank prefix, e.g. 'g__Escherichia', used as the taxon ID
+    id: String,
+    name: String,
+    parent: Option<String>,
+    // Mass of the clade, and of the sequences classified no deeper than it
+    mass: f64,
+    direct: f64,
+}
+
+struct ModelAbundance {
+    taxonomy: Option<Taxonomy>,
+    sequences: usize,
+    mass: f64,
+}
+
+pub struct Profile {
+    sample_id: String,
+    unit: ProfileUnit,
+    total: f64,
+    unclassified: f64,
+    // Keyed by the lineage down to the clade
+    clades: BTreeMap<String, Clade>,
+    models: BTreeMap<String, ModelAbundance>,
+}
+
+impl Profile {
+    /*
+    Sums the mass of the sequences per best hit and per clade of its taxonomy, limited to the novelty rank when known.
+    Sequences with no best hit, a best hit without a taxonomy, or novel at every rank are unclassified.
+     */
+    pub fn new(sample_id: &str, classifications: &[Classification], model2taxonomy: &HashMap<String, Taxonomy>, weights: Option<&HashMap<String, f64>>, unit: ProfileUnit) -> std::io::Result<Self> {
+        let mut profile = Self {
+            sample_id: sample_id.to_string(),
+            unit,
+            total: 0.0,
+            unclassified: 0.0,
+            clades: BTreeMap::new(),
+            models: BTreeMap::new(),
+        };
+
+        for classification in classifications {
+            let weight = match weights {
+                Some(weights) => *weights.get(&classification.name)
+                    .ok_or(Error::new(ErrorKind::InvalidData, format!("E: sequence '{}' has no weight", classification.name)))?,
+                None => 1.0,
+            };
+            let mass = match unit {
+                ProfileUnit::Bases => classification.length as f64 * weight,
+                ProfileUnit::Sequences => weight,
+            };
+            profile.total += mass;
+
+            let taxonomy = classification.best_hit.as_ref().and_then(|hit| model2taxonomy.get(hit));
+            if let Some(hit) = &classification.best_hit {
+                let model = profile.models.entry(hit.clone()).or_insert(ModelAbundance { taxonomy: taxonomy.cloned(), sequences: 0, mass: 0.0 });
+                model.sequences += 1;
+                model.mass += mass;
+            }
+
+            let taxonomy = match (taxonomy, classification.novelty_rank) {
+                (Some(taxonomy), None) => Some(taxonomy.clone()),
+                (Some(taxonomy), Some(Some(rank))) => Some(taxonomy.limit2rank(&rank)),
+                _ => None,
+            };
+            let ranks = taxonomy.as_ref()
+                .map(|taxonomy| TaxonomicRank::down_iterator().filter(|rank| taxonomy.has_rank(rank)).collect::<Vec<TaxonomicRank>>())
+                .unwrap_or_default();
+            match (taxonomy, ranks.last()) {
+                (Some(taxonomy), Some(deepest)) => profile.add_lineage(&taxonomy, &ranks, *deepest, mass),
+                _ => profile.unclassified += mass,
+            }
+        }
+
+        Ok(profile)
+    }
+
+    fn add_lineage(&mut self, taxonomy: &Taxonomy, ranks: &[TaxonomicRank], deepest: TaxonomicRank, mass: f64) {
+        let mut parent = None;
+        for rank in ranks {
+            let stem = taxonomy.limit2rank(rank);
+            let key = stem.to_string();
+            let clade = self.clades.entry(key.clone()).or_insert_with(|| Clade {
+                rank: *rank,
+                id: stem.deepest_taxon().unwrap_or_default(),
+                name: stem.get_taxa(rank).cloned().unwrap_or_default(),
+                parent: parent.clone(),
+                mass: 0.0,
+                direct: 0.0,
+            });
+            clade.mass += mass;
+            if *rank == deepest {
+                clade.direct += mass;
+            }
+            parent = Some(key);
+        }
+    }
+
+    fn percentage(&self, mass: f64) -> f64 {
+        if self.total > 0.0 { 100.0 * mass / self.total } else { 0.0 }
+    }
+
+    // Clades from the root down to the clade, e.g. the taxon path of CAMI
+    fn lineage(&self, key: &str) -> Vec<&Clade> {
+        let mut lineage = Vec::new();
+        let mut key = Some(key);
+        while let Some(clade) = key.and_then(|key| self.clades.get(key)) {
+            lineage.push(clade);
+            key = clade.parent.as_deref();
+        }
+        lineage.reverse();
+        lineage
+    }
+
+    // Children of the clade (the roots for None), the most abundant first
+    fn children(&self, parent: Option<&str>) -> Vec<(&String, &Clade)> {
+        let mut children = self.clades.iter()
+            .filter(|(_, clade)| clade.parent.as_deref() == parent)
+            .collect::<Vec<_>>();
+        children.sort_by(|a, b| b.1.mass.total_cmp(&a.1.mass));
+        children
+    }
+
+    // CAMI profiling format, percentages are of the whole sample so the unclassified remainder is left out of every rank
+    pub fn write_cami<W: Write>(&self, mut stream: W) -> std::io::Result<()> {
+        writeln!(stream, "# Taxonomic Profiling Output")?;
+        writeln!(stream, "@SampleID:{}", self.sample_id)?;
+        writeln!(stream, "@Version:0.9.1")?;
+        writeln!(stream, "@Ranks:{}", TaxonomicRank::down_iterator().map(cami_rank).collect::<Vec<&str>>().join("|"))?;
+        writeln!(stream, "@@TAXID\tRANK\tTAXPATH\tTAXPATHSN\tPERCENTAGE")?;
+        for rank in TaxonomicRank::down_iterator() {
+            let mut clades = self.clades.iter().filter(|(_, clade)| clade.rank == rank).collect::<Vec<_>>();
+            clades.sort_by(|a, b| b.1.mass.total_cmp(&a.1.mass));
+            for (key, clade) in clades {
+                let lineage = self.lineage(key);
+                writeln!(stream, "{}\t{}\t{}\t{}\t{:.6}",
+                         clade.id,
+                         cami_rank(rank),
+                         lineage.iter().map(|c| c.id.as_str()).collect::<Vec<&str>>().join("|"),
+                         lineage.iter().map(|c| c.name.as_str()).collect::<Vec<&str>>().join("|"),
+                         self.percentage(clade.mass))?;
+            }
+        }
+        stream.flush()
+    }
+
+    // Kraken-style report: percentage, clade mass, mass classified no deeper, rank code, taxon ID and indented name
+    pub fn write_kraken<W: Write>(&self, mut stream: W) -> std::io::Result<()> {
+        let classified = self.total - self.unclassified;
+        writeln!(stream, "{:.2}\t{:.0}\t{:.0}\tU\t0\tunclassified", self.percentage(self.unclassified), self.unclassified, self.unclassified)?;
+        writeln!(stream, "{:.2}\t{:.0}\t0\tR\t1\troot", self.percentage(classified), classified)?;
+        let mut stack = self.children(None).into_iter().rev().map(|child| (child, 1)).collect::<Vec<_>>();
+        while let Some(((key, clade), depth)) = stack.pop() {
+            writeln!(stream, "{:.2}\t{:.0}\t{:.0}\t{}\t{}\t{}{}",
+                     self.percentage(clade.mass), clade.mass, clade.direct, kraken_rank(clade.rank), clade.id, "  ".repeat(depth), clade.name)?;
+            stack.extend(self.children(Some(key)).into_iter().rev().map(|child| (child, depth + 1)));
+        }
+        stream.flush()
+    }
+
+    // Mass of every best hit, the most abundant first
+    pub fn write_models<W: Write>(&self, mut stream: W) -> std::io::Result<()> {
+        writeln!(stream, "Model\tTaxonomy\tSequences\t{}\tPercentage", if let ProfileUnit::Bases = self.unit { "Bases" } else { "Weight" })?;
+        let mut models = self.models.iter().collect::<Vec<_>>();
+        models.sort_by(|a, b| b.1.mass.total_cmp(&a.1.mass));
+        for (model, abundance) in models {
+            let taxonomy = abundance.taxonomy.as_ref().map_or(String::from("NA"), |t| t.to_string());
+            writeln!(stream, "{}\t{}\t{}\t{:.0}\t{:.6}", model, taxonomy, abundance.sequences, abundance.mass, self.percentage(abundance.mass))?;
+        }
+        stream.flush()
+    }
+
+    // Writes <prefix>.profile (CAMI), <prefix>.kreport (Kraken-style) and <prefix>.models.tsv
+    pub fn write(&self, prefix: &Path) -> std::io::Result<()> {
+        let path = |suffix: &str| format!("{}{}", prefix.display(), suffix);
+        self.write_cami(BufWriter::new(File::create(path(".profile"))?))?;
+        self.write_kraken(BufWriter::new(File::create(path(".kreport"))?))?;
+        self.write_models(BufWriter::new(File::create(path(".models.tsv"))?))
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Profiled {:.0} {} of {}, {:.2}% classified into {} taxa, best hits of {} models",
+                 self.total, self.unit, self.sample_id, self.percentage(self.total - self.unclassified), self.clades.len(), self.models.len())
+    }
+}
+
+fn cami_rank(rank: TaxonomicRank) -> &'static str {
+    match rank {
+        TaxonomicRank::Domain => "superkingdom",
+        TaxonomicRank::Phylum => "phylum",
+        TaxonomicRank::Class => "class",
+        TaxonomicRank::Order => "order",
+        TaxonomicRank::Family => "family",
+        TaxonomicRank::Genus => "genus",
+        TaxonomicRank::Species => "species",
+    }
+}
+
+fn kraken_rank(rank: TaxonomicRank) -> char {
+    match rank {
+        TaxonomicRank::Domain => 'D',
+        TaxonomicRank::Phylum => 'P',
+        TaxonomicRank::Class => 'C',
+        TaxonomicRank::Order => 'O',
+        TaxonomicRank::Family => 'F',
+        TaxonomicRank::Genus => 'G',
+        TaxonomicRank::Species => 'S',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use hashbrown::HashMap;
+    use crate::profile::{read_weights, Classification, Profile, ProfileUnit};
+    use crate::taxonomy::{TaxonomicRank, Taxonomy};
+
+    #[test]
+    fn community_profile() {
+        let model2taxonomy: HashMap<String, Taxonomy> = [
+            ("m1", "d__Bacteria;p__Firmicutes;c__Bacilli"),
+            ("m2", "d__Bacteria;p__Firmicutes;c__Clostridia"),
+        ].into_iter().map(|(m, t)| (m.to_string(), Taxonomy::from(t))).collect();
+        let classification = |name: &str, length: usize, best_hit: Option<&str>, novelty_rank: Option<Option<TaxonomicRank>>| Classification {
+            name: name.to_string(),
+            length,
+            best_hit: best_hit.map(String::from),
+            novelty_rank,
+        };
+        let classifications = [
+            classification("a", 100, Some("m1"), None),
+            classification("b", 200, Some("m2"), None),
+            // A new class, its mass stays with the phylum
+            classification("c", 300, Some("m2"), Some(Some(TaxonomicRank::Phylum))),
+            classification("d", 400, None, None),
+        ];
+        let weights: HashMap<String, f64> = [("a", 2.0), ("b", 1.0), ("c", 1.0), ("d", 1.0)].into_iter().map(|(s, w)| (s.to_string(), w)).collect();
+        let profile = Profile::new("sample", &classifications, &model2taxonomy, Some(&weights), ProfileUnit::Bases).unwrap();
+
+        let mut kraken = Vec::new();
+        profile.write_kraken(&mut kraken).unwrap();
+        assert_eq!(String::from_utf8(kraken).unwrap(),
+                   "36.36\t400\t400\tU\t0\tunclassified\n\
+                    63.64\t700\t0\tR\t1\troot\n\
+                    63.64\t700\t0\tD\td__Bacteria\t  Bacteria\n\
+                    63.64\t700\t300\tP\tp__Firmicutes\t    Firmicutes\n\
+                    18.18\t200\t200\tC\tc__Bacilli\t      Bacilli\n\
+                    18.18\t200\t200\tC\tc__Clostridia\t      Clostridia\n");
+
+        let mut cami = Vec::new();
+        profile.write_cami(&mut cami).unwrap();
+        let cami = String::from_utf8(cami).unwrap();
+        assert!(cami.contains("\nc__Bacilli\tclass\td__Bacteria|p__Firmicutes|c__Bacilli\tBacteria|Firmicutes|Bacilli\t18.181818\n"));
+
+        let mut models = Vec::new();
+        profile.write_models(&mut models).unwrap();
+        assert_eq!(String::from_utf8(models).unwrap().lines().nth(1), Some("m2\td__Bacteria;p__Firmicutes;c__Clostridia;\t2\t500\t45.454545"));
+    }
+
+    #[test]
+    fn sample_sheet_weights() {
+        // The weight column of the sample sheet the queries were predicted from, the last query has no weight
+        let weights = read_weights(Path::new("../tests/weighted_sample_sheet.tsv")).unwrap();
+        assert_eq!(weights.len(), 2);
+        assert_eq!(weights["Actinomyces_graevenitzii_0"], 12.5);
+        assert_eq!(weights["Actinomyces_oris_C_0"], 3.0);
+    }
+}
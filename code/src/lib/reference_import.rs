@@ -0,0 +1,298 @@
+// Build reference lists (name, FASTA path and taxonomy) from GTDB or NCBI metadata, and a local directory of genomes.
+// Genomes are matched to the metadata by their GCA_/GCF_ accession, found in the file names.
+
+use std::collections::HashMap;
+use std::fs::{read_dir, File};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use crate::ncbi_taxdump::{TaxDump, TaxId};
+
+const FASTA_EXTENSIONS: [&str; 4] = [".fna", ".fasta", ".fa", ".fas"];
+const COMPRESSION_EXTENSIONS: [&str; 4] = [".gz", ".bz2", ".xz", ".zst"];
+const NON_GENOME_MARKERS: [&str; 2] = ["_cds_from_", "_rna_from_"];
+
+// GTDB columns, the first name found is used
+const GTDB_ACCESSION: [&str; 1] = ["accession"];
+const GTDB_TAXONOMY: [&str; 1] = ["gtdb_taxonomy"];
+const GTDB_REPRESENTATIVE: [&str; 1] = ["gtdb_representative"];
+const GTDB_COMPLETENESS: [&str; 2] = ["checkm2_completeness", "checkm_completeness"];
+const GTDB_CONTAMINATION: [&str; 2] = ["checkm2_contamination", "checkm_contamination"];
+const GTDB_N50: [&str; 2] = ["n50_contigs", "contig_n50"];
+
+// NCBI assembly_summary.txt columns, and their positions for files without a header
+const NCBI_ACCESSION: (&str, usize) = ("assembly_accession", 0);
+const NCBI_REFSEQ_CATEGORY: (&str, usize) = ("refseq_category", 4);
+const NCBI_TAXID: (&str, usize) = ("taxid", 5);
+const NCBI_REPRESENTATIVES: [&str; 2] = ["reference genome", "representative genome"];
+
+// A genome listed by the metadata and found in the genomes directory
+pub struct ImportedGenome {
+    accession: String,
+    path: PathBuf,
+    taxonomy: String,
+    // Only given by GTDB
+    quality: Option<[Option<String>; 3]>,
+}
+
+impl ImportedGenome {
+    pub fn get_accession(&self) -> &str { &self.accession }
+    pub fn get_path(&self) -> &Path { &self.path }
+    pub fn get_taxonomy(&self) -> &str { &self.taxonomy }
+}
+
+// The GCA_/GCF_ accession (with version) in a file name, e.g. GCF_000005845.2_ASM584v2_genomic.fna.gz -> GCF_000005845.2
+pub fn find_accession(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    (0..bytes.len()).find_map(|i| {
+        let rest = &bytes[i..];
+        if !(rest.starts_with(b"GCA_") || rest.starts_with(b"GCF_")) {
+            return None;
+        }
+        let digits = rest[4..].iter().take_while(|b| b.is_ascii_digit()).count();
+        if digits != 9 || rest.get(13) != Some(&b'.') {
+            return None;
+        }
+        let version = rest[14..].iter().take_while(|b| b.is_ascii_digit()).count();
+        if version == 0 {
+            return None;
+        }
+        Some(text[i..i + 14 + version].to_string())
+    })
+}
+
+// GenBank and RefSeq copies of an assembly share the accession number
+fn paired_accession(accession: &str) -> String {
+    match accession.split_at(3) {
+        ("GCA", rest) => format!("GCF{}", rest),
+        ("GCF", rest) => format!("GCA{}", rest),
+        _ => accession.to_string(),
+    }
+}
+
+fn is_genome_fasta(name: &str) -> bool {
+    let name = name.to_lowercase();
+    let name = COMPRESSION_EXTENSIONS.iter()
+        .find_map(|e| name.strip_suffix(e))
+        .unwrap_or(&name);
+    FASTA_EXTENSIONS.iter().any(|e| name.ends_with(e)) && !NON_GENOME_MARKERS.iter().any(|m| name.contains(m))
+}
+
+pub struct GenomeDirectory {
+    accession2path: HashMap<String, PathBuf>,
+}
+
+impl GenomeDirectory {
+    // Scans the directory recursively, if an accession has several files, the first path (in order) is used
+    pub fn scan(directory: &Path) -> std::io::Result<Self> {
+        let mut paths = Vec::new();
+        let mut folders = vec![directory.to_path_buf()];
+        while let Some(folder) = folders.pop() {
+            for entry in read_dir(&folder)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    folders.push(path);
+                } else {
+                    paths.push(path);
+                }
+            }
+        }
+        paths.sort();
+
+        let mut accession2path = HashMap::new();
+        for path in paths {
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) if is_genome_fasta(name) => name,
+                _ => continue,
+            };
+            if let Some(accession) = find_accession(name) {
+                accession2path.entry(accession).or_insert(path);
+            }
+        }
+
+        Ok(Self { accession2path })
+    }
+
+    pub fn len(&self) -> usize { self.accession2path.len() }
+    pub fn is_empty(&self) -> bool { self.accession2path.is_empty() }
+
+    pub fn get_path(&self, accession: &str) -> Option<&Path> {
+        self.accession2path.get(accession)
+            .or_else(|| self.accession2path.get(&paired_accession(accession)))
+            .map(|p| p.as_path())
+    }
+}
+
+fn read_header(path: &Path, line: Option<std::io::Result<String>>) -> std::io::Result<Vec<String>> {
+    match line {
+        Some(line) => Ok(line?.trim_start_matches('#').trim().split('\t').map(|c| c.trim().to_lowercase()).collect()),
+        None => Err(Error::new(ErrorKind::InvalidData, format!("E: '{}' is empty", path.display()))),
+    }
+}
+
+fn find_column(header: &[String], names: &[&str]) -> Option<usize> {
+    names.iter().find_map(|name| header.iter().position(|column| column == name))
+}
+
+/*
+Genomes of a GTDB metadata table (e.g. bac120_metadata.tsv) found in the genomes directory.
+Accessions are prefixed by RS_ or GB_ in GTDB, these are removed.
+ */
+pub fn import_gtdb(metadata: &Path, genomes: &GenomeDirectory, representatives_only: bool) -> std::io::Result<Vec<ImportedGenome>> {
+    let mut lines = BufReader::new(File::open(metadata)?).lines();
+    let header = read_header(metadata, lines.next())?;
+    let missing = |column: &str| Error::new(ErrorKind::InvalidData, format!("E: '{}' has no '{}' column", metadata.display(), column));
+    let accession_index = find_column(&header, &GTDB_ACCESSION).ok_or_else(|| missing(GTDB_ACCESSION[0]))?;
+    let taxonomy_index = find_column(&header, &GTDB_TAXONOMY).ok_or_else(|| missing(GTDB_TAXONOMY[0]))?;
+    let representative_index = find_column(&header, &GTDB_REPRESENTATIVE);
+    if representatives_only && representative_index.is_none() {
+        return Err(missing(GTDB_REPRESENTATIVE[0]));
+    }
+    let quality_indices = [find_column(&header, &GTDB_COMPLETENESS), find_column(&header, &GTDB_CONTAMINATION), find_column(&header, &GTDB_N50)];
+
+    let mut results = Vec::new();
+    for line in lines {
+        let line = line?;
+        let fields = line.trim_end_matches('\r').split('\t').collect::<Vec<&str>>();
+        let field = |index: usize| fields.get(index).map(|f| f.trim()).filter(|f| !f.is_empty());
+        let (accession, taxonomy) = match (field(accession_index), field(taxonomy_index)) {
+            (Some(accession), Some(taxonomy)) => (accession.trim_start_matches("RS_").trim_start_matches("GB_"), taxonomy),
+            _ => continue,
+        };
+        if representatives_only && !representative_index.and_then(field).is_some_and(|r| r == "t" || r.eq_ignore_ascii_case("true")) {
+            continue;
+        }
+
+        if let Some(path) = genomes.get_path(accession) {
+            results.push(ImportedGenome {
+                accession: accession.to_string(),
+                path: path.to_path_buf(),
+                taxonomy: taxonomy.to_string(),
+                quality: Some(quality_indices.map(|i| i.and_then(field).map(|f| f.to_string()))),
+            });
+        }
+    }
+
+    Ok(results)
+}
+
+/*
+Genomes of an NCBI assembly_summary.txt found in the genomes directory, with taxonomies taken from the taxdump.
+The column names are taken from the last comment line before the data, fixed positions are used otherwise.
+ */
+pub fn import_ncbi(assembly_summary: &Path, taxdump: &TaxDump, genomes: &GenomeDirectory, representatives_only: bool) -> std::io::Result<Vec<ImportedGenome>> {
+    let mut header: Option<Vec<String>> = None;
+    let mut results = Vec::new();
+    for line in BufReader::new(File::open(assembly_summary)?).lines() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.starts_with('#') {
+            header = Some(read_header(assembly_summary, Some(Ok(line.to_string())))?);
+            continue;
+        }
+
+        let index = |(name, position): (&str, usize)| header.as_ref()
+            .and_then(|h| find_column(h, &[name]))
+            .unwrap_or(position);
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        let field = |column: (&str, usize)| fields.get(index(column)).map(|f| f.trim()).unwrap_or("");
+
+        if representatives_only && !NCBI_REPRESENTATIVES.contains(&field(NCBI_REFSEQ_CATEGORY)) {
+            continue;
+        }
+        let accession = field(NCBI_ACCESSION);
+        let taxid = match field(NCBI_TAXID).parse::<TaxId>() {
+            Ok(taxid) => taxid,
+            Err(_) => continue,
+        };
+
+        match (genomes.get_path(accession), taxdump.gtdb_taxonomy(taxid)) {
+            (Some(path), Some(taxonomy)) => results.push(ImportedGenome {
+                accession: accession.to_string(),
+                path: path.to_path_buf(),
+                taxonomy,
+                quality: None,
+            }),
+            (Some(_), None) => eprintln!("W: taxid {} of '{}' is missing from the taxdump, skipping", taxid, accession),
+            _ => (),
+        }
+    }
+
+    Ok(results)
+}
+
+// In the training file format: <name>\t<fasta-file>\t<full taxonomy string>
+pub fn write_references<W: Write>(genomes: &[ImportedGenome], destination: W) -> std::io::Result<()> {
+    let mut stream = BufWriter::new(destination);
+    for genome in genomes {
+        writeln!(stream, "{}\t{}\t{}", genome.accession, genome.path.display(), genome.taxonomy)?;
+    }
+    stream.flush()
+}
+
+pub fn has_quality(genomes: &[ImportedGenome]) -> bool {
+    genomes.iter().any(|g| g.quality.is_some())
+}
+
+// A genome metadata table that can be used to pick representatives by quality (see genome_quality)
+pub fn write_quality<W: Write>(genomes: &[ImportedGenome], destination: W) -> std::io::Result<()> {
+    let mut stream = BufWriter::new(destination);
+    writeln!(stream, "genome\tcompleteness\tcontamination\tn50")?;
+    for genome in genomes {
+        let values = match &genome.quality {
+            Some(quality) => quality.iter().map(|v| v.clone().unwrap_or(String::from("NA"))).collect::<Vec<String>>(),
+            None => vec![String::from("NA"); 3],
+        };
+        writeln!(stream, "{}\t{}", genome.accession, values.join("\t"))?;
+    }
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::ncbi_taxdump::TaxDump;
+    use crate::reference_import::{find_accession, import_gtdb, import_ncbi, GenomeDirectory};
+
+    #[test]
+    fn accessions() {
+        assert_eq!(find_accession("GCF_000005845.2_ASM584v2_genomic.fna.gz"), Some(String::from("GCF_000005845.2")));
+        assert_eq!(find_accession("RS_GCA_000009045.1"), Some(String::from("GCA_000009045.1")));
+        assert_eq!(find_accession("GCF_00000584.2.fna"), None);
+        assert_eq!(find_accession("genome.fna"), None);
+    }
+
+    #[test]
+    fn genome_directory() {
+        let genomes = GenomeDirectory::scan(Path::new("../tests/import/genomes")).unwrap();
+        assert_eq!(genomes.len(), 2);
+        assert!(genomes.get_path("GCF_000005845.2").is_some());
+        // The RefSeq copy of a GenBank assembly
+        assert!(genomes.get_path("GCA_000009045.1").is_some());
+        assert!(genomes.get_path("GCF_000001405.40").is_none());
+    }
+
+    #[test]
+    fn gtdb() {
+        let genomes = GenomeDirectory::scan(Path::new("../tests/import/genomes")).unwrap();
+        let imported = import_gtdb(Path::new("../tests/import/gtdb_metadata.tsv"), &genomes, false).unwrap();
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[0].get_accession(), "GCF_000005845.2");
+        assert!(imported[0].get_taxonomy().ends_with("s__Escherichia coli"));
+
+        let imported = import_gtdb(Path::new("../tests/import/gtdb_metadata.tsv"), &genomes, true).unwrap();
+        assert_eq!(imported.len(), 1);
+    }
+
+    #[test]
+    fn ncbi() {
+        let genomes = GenomeDirectory::scan(Path::new("../tests/import/genomes")).unwrap();
+        let taxdump = TaxDump::load(Path::new("../tests/import/taxdump")).unwrap();
+        let imported = import_ncbi(Path::new("../tests/import/assembly_summary.txt"), &taxdump, &genomes, false).unwrap();
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[0].get_taxonomy(),
+                   "d__Bacteria;p__Pseudomonadota;c__Gammaproteobacteria;o__Enterobacterales;f__Enterobacteriaceae;g__Escherichia;s__Escherichia coli");
+        assert_eq!(imported[1].get_taxonomy(),
+                   "d__Bacteria;p__Bacillota;c__Bacilli;o__Bacillales;f__Bacillaceae;g__Bacillus;s__Bacillus subtilis");
+        assert_eq!(import_ncbi(Path::new("../tests/import/assembly_summary.txt"), &taxdump, &genomes, true).unwrap().len(), 1);
+    }
+}
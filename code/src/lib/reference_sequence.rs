@@ -1,11 +1,12 @@
 use std::path::{Path, PathBuf};
-use crate::get_gc::calc_gc;
-use crate::kmer::create_normalized_profile;
+use crate::get_gc::calc_gc_chunks;
+use crate::kmer::create_normalized_profile_from_chunks;
 use crate::lz78::{LenBases, LZ78};
 use crate::taxonomy::Taxonomy;
+use crate::novelty::NoveltyProfile;
 
 use serde::{Serialize, Deserialize};
-use crate::fasta_nucleutide_iterator::FastaNucltudiesIterator;
+use crate::fasta_chunk_reader::FastaChunkReader;
 
 #[derive(Serialize, Deserialize)]
 pub struct ReferenceSequence {
@@ -17,23 +18,58 @@ pub struct ReferenceSequence {
     kmer_cluster: Option<Taxonomy>,
     self_value: f64,
     fasta_path: PathBuf,
+    // The FASTA files after the first of a model trained on several, stored by the database apart from the model, like novelty
+    #[serde(skip)]
+    other_fasta_paths: Vec<PathBuf>,
+    // Scores on the other references, stored by the database apart from the model, so older databases can still be loaded
+    #[serde(skip)]
+    novelty: Option<NoveltyProfile>,
 }
 
 impl ReferenceSequence {
     pub fn new(fasta_path: &Path, name: &str, kmer_size: &Option<usize>, buffer_size: usize, lz_lenbases: LenBases, lzmax_depth: usize, kmer_cluster: &Option<Taxonomy>) -> Self {
-        let prediction_model = LZ78::new(lzmax_depth, lz_lenbases, FastaNucltudiesIterator::new(fasta_path, buffer_size));
-        let self_value = prediction_model.average_log_score(FastaNucltudiesIterator::new(fasta_path, buffer_size));
+        let prediction_model = LZ78::from_chunks(lzmax_depth, lz_lenbases, FastaChunkReader::new(fasta_path, buffer_size));
+        let self_value = prediction_model.average_log_score_chunks(FastaChunkReader::new(fasta_path, buffer_size));
         ReferenceSequence {
             prediction_model,
-            gc: calc_gc(FastaNucltudiesIterator::new(fasta_path, buffer_size)),
-            kmer: kmer_size.map(|k| create_normalized_profile(k, FastaNucltudiesIterator::new(fasta_path, buffer_size), &false).1.unwrap_or_else(|_| panic!("ERROR: failed to create kmer for {}, quitting", fasta_path.display()))),
+            gc: calc_gc_chunks(FastaChunkReader::new(fasta_path, buffer_size)),
+            kmer: kmer_size.map(|k| create_normalized_profile_from_chunks(k, FastaChunkReader::new(fasta_path, buffer_size)).unwrap_or_else(|_| panic!("ERROR: failed to create kmer for {}, quitting", fasta_path.display()))),
             name: name.to_string(),
             kmer_cluster: kmer_cluster.clone(),
             self_value,
             fasta_path: fasta_path.to_path_buf(),
+            other_fasta_paths: Vec::new(),
+            novelty: None,
         }
     }
 
+    // A single model trained on the concatenation of several FASTA files, e.g. all the representatives of a cluster.
+    // The first file is kept as the model's FASTA path, get_fasta_paths has all of them.
+    pub fn from_fastas(fasta_paths: &[PathBuf], name: &str, kmer_size: &Option<usize>, buffer_size: usize, lz_lenbases: LenBases, lzmax_depth: usize, kmer_cluster: &Option<Taxonomy>) -> Self {
+        let fasta_path = fasta_paths.first().unwrap_or_else(|| panic!("E: model '{}' has no FASTA files, quitting", name));
+        let prediction_model = LZ78::from_chunks(lzmax_depth, lz_lenbases, FastaChunkReader::from_paths(fasta_paths, buffer_size));
+        let self_value = prediction_model.average_log_score_chunks(FastaChunkReader::from_paths(fasta_paths, buffer_size));
+        ReferenceSequence {
+            prediction_model,
+            gc: calc_gc_chunks(FastaChunkReader::from_paths(fasta_paths, buffer_size)),
+            kmer: kmer_size.map(|k| create_normalized_profile_from_chunks(k, FastaChunkReader::from_paths(fasta_paths, buffer_size)).unwrap_or_else(|_| panic!("ERROR: failed to create kmer for {}, quitting", name))),
+            name: name.to_string(),
+            kmer_cluster: kmer_cluster.clone(),
+            self_value,
+            fasta_path: fasta_path.to_path_buf(),
+            other_fasta_paths: fasta_paths[1..].to_vec(),
+            novelty: None,
+        }
+    }
+
+    // A %G+C given by the user (e.g. in the sample sheet) replaces the one computed from the FASTA
+    pub fn with_gc(mut self, gc: Option<f64>) -> Self {
+        if let Some(gc) = gc {
+            self.gc = gc;
+        }
+        self
+    }
+
     pub fn get_prediction_model(&self) -> &LZ78 { &self.prediction_model }
     pub fn get_gc(&self) -> f64 { self.gc }
     pub fn get_kmer(&self) -> &Option<ndarray::Array1<f64>> { &self.kmer }
@@ -42,6 +78,13 @@ impl ReferenceSequence {
     pub fn get_kmer_cluster(&self) -> Option<&Taxonomy> { self.kmer_cluster.as_ref() }
     pub fn get_self_value(&self) -> f64 { self.self_value }
     pub fn get_fasta_path(&self) -> &Path { &self.fasta_path }
+    pub fn get_fasta_paths(&self) -> Vec<PathBuf> {
+        std::iter::once(&self.fasta_path).chain(self.other_fasta_paths.iter()).cloned().collect()
+    }
+    pub fn get_other_fasta_paths(&self) -> &[PathBuf] { &self.other_fasta_paths }
+    pub fn set_other_fasta_paths(&mut self, other_fasta_paths: Vec<PathBuf>) { self.other_fasta_paths = other_fasta_paths; }
+    pub fn get_novelty(&self) -> Option<&NoveltyProfile> { self.novelty.as_ref() }
+    pub fn set_novelty(&mut self, novelty: Option<NoveltyProfile>) { self.novelty = novelty; }
 }
 
 
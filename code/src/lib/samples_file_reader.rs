@@ -5,7 +5,10 @@ use std::fmt::{Debug, Display, Formatter};
 use std::fs::File;
 use std::hash::Hasher;
 use std::io::{BufRead, BufReader};
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::ncbi_taxdump::{TaxDump, TaxId, TaxonLineage};
 use crate::reference_sequence::ReferenceSequence;
 use crate::taxonomy::Taxonomy;
 
@@ -41,25 +44,63 @@ impl Display for SampleError {
 #[derive(Clone)]
 pub struct Sample {
     name: String,
-    path: PathBuf,
+    // At least one, a model may be trained on several FASTA files, e.g. a genome and its plasmids
+    paths: Vec<PathBuf>,
     taxonomy: Option<Taxonomy>,
+    // Set when the taxonomy column holds an NCBI taxid
+    lineage: Option<TaxonLineage>,
     line_number: usize,
+    // Optional columns of a sample sheet
+    gc: Option<f64>,
+    weight: Option<f64>,
+    group: Option<String>,
 }
 
 impl Sample {
     pub fn new(name: &str, path: &str, taxonomy: Option<&str>, line_number: usize) -> Self {
+        Self::with_taxonomy(name, path, taxonomy.map(Taxonomy::from), line_number)
+    }
+
+    pub fn with_taxonomy(name: &str, path: &str, taxonomy: Option<Taxonomy>, line_number: usize) -> Self {
+        Sample {
+            name: name.to_string(),
+            paths: vec![PathBuf::from(path)],
+            taxonomy,
+            lineage: None,
+            line_number,
+            gc: None,
+            weight: None,
+            group: None,
+        }
+    }
+
+    // The taxonomy is taken from the lineage, in its GTDB form
+    pub fn from_lineage(name: &str, path: &str, lineage: TaxonLineage, line_number: usize) -> Self {
         Sample {
             name: name.to_string(),
-            path: PathBuf::from(path),
-            taxonomy: taxonomy.map(Taxonomy::from),
+            paths: vec![PathBuf::from(path)],
+            taxonomy: Some(lineage.to_taxonomy()),
+            lineage: Some(lineage),
             line_number,
+            gc: None,
+            weight: None,
+            group: None,
         }
     }
 
     pub fn get_name(&self) -> &str { &self.name }
-    pub fn get_path(&self) -> &Path { &self.path }
+    pub fn get_path(&self) -> &Path { &self.paths[0] }
+    pub fn get_paths(&self) -> &[PathBuf] { &self.paths }
     pub fn get_taxonomy(&self) -> &Option<Taxonomy> { &self.taxonomy }
+    pub fn get_lineage(&self) -> Option<&TaxonLineage> { self.lineage.as_ref() }
+    pub fn get_taxonomy_mut(&mut self) -> Option<&mut Taxonomy> { self.taxonomy.as_mut() }
     pub fn get_line_number(&self) -> usize { self.line_number }
+    // The %G+C of the sheet, used instead of the one computed from the FASTA
+    pub fn get_gc(&self) -> Option<f64> { self.gc }
+    // Weight of a query, e.g. its read count, for the Krona, BIOM and profile abundances
+    pub fn get_weight(&self) -> Option<f64> { self.weight }
+    // Group or bin of the sample, e.g. the metagenome it was assembled from
+    pub fn get_group(&self) -> Option<&str> { self.group.as_deref() }
 }
 
 impl Debug for Sample {
@@ -71,9 +112,13 @@ impl From<&ReferenceSequence> for Sample {
     fn from(value: &ReferenceSequence) -> Self {
         Self {
             name: value.get_name().to_string(),
-            path: value.get_fasta_path().to_path_buf(),
+            paths: value.get_fasta_paths(),
             taxonomy: value.get_kmer_cluster().cloned(),
+            lineage: None,
             line_number: 0,
+            gc: None,
+            weight: None,
+            group: None,
         }
     }
 }
@@ -105,9 +150,168 @@ impl std::hash::Hash for Sample {
     }
 }
 
+// Input path for reading query sequences (FASTA) from stdin
+pub const STDIN_PATH: &str = "-";
+
+// Extensions of the FASTA files taken from a directory, each may also be compressed
+const FASTA_EXTENSIONS: [&str; 6] = ["fa", "fasta", "fna", "ffn", "fas", "fsa"];
+const COMPRESSION_EXTENSIONS: [&str; 4] = ["gz", "zst", "bz2", "xz"];
+
+// Separates the FASTA files of a single sample in the path column of a sample sheet
+const PATHS_SEPARATOR: char = ',';
+
+fn split_paths(paths: &str) -> Vec<PathBuf> {
+    paths.split(PATHS_SEPARATOR).map(str::trim).filter(|path| !path.is_empty()).map(PathBuf::from).collect()
+}
+
+/*
+Samples repeating a name are a single sample with the FASTA files of all of them, in order of appearance.
+Their taxonomies must agree, the sheet columns are taken from the first.
+ */
+pub fn merge_repeated_samples(samples: &[Sample]) -> Result<Vec<Sample>, SampleError> {
+    let mut merged: Vec<Sample> = Vec::with_capacity(samples.len());
+    let mut name2index: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
+    for sample in samples {
+        match name2index.get(sample.get_name()) {
+            Some(&index) => {
+                let first = &mut merged[index];
+                if first.get_taxonomy() != sample.get_taxonomy() {
+                    let display = |taxonomy: &Option<Taxonomy>| taxonomy.as_ref().map_or(String::from("None"), |t| t.to_string());
+                    return Err(SampleError::new(&format!("E: Sample '{}' is repeated with different taxonomies, '{}' and '{}'",
+                                                         sample.get_name(), display(first.get_taxonomy()), display(sample.get_taxonomy())), SampleErrorType::None));
+                }
+                first.paths.extend(sample.get_paths().iter().cloned());
+            },
+            None => {
+                name2index.insert(sample.get_name(), merged.len());
+                merged.push(sample.clone());
+            },
+        }
+    }
+    Ok(merged)
+}
+
+pub fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == STDIN_PATH
+}
+
+// A path which does not exist but has glob wildcards, e.g. 'genomes/*.fna'
+pub fn is_glob_pattern(path: &Path) -> bool {
+    !path.exists() && path.to_str().is_some_and(|path| path.contains(['*', '?', '[']))
+}
+
+fn without_compression_extension(path: &Path) -> &Path {
+    if path.extension().and_then(OsStr::to_str).is_some_and(|extension| COMPRESSION_EXTENSIONS.contains(&extension)) {
+        Path::new(path.file_stem().unwrap_or_default())
+    } else {
+        path
+    }
+}
+
+// The name of a FASTA file without its FASTA and compression extensions, e.g. 'sample' for 'sample.fna.gz'
+fn fasta_name(fasta: &Path) -> String {
+    let fasta = without_compression_extension(fasta);
+    let name = if fasta.extension().and_then(OsStr::to_str).is_some_and(|extension| FASTA_EXTENSIONS.contains(&extension)) {
+        fasta.file_stem()
+    } else {
+        fasta.file_name()
+    };
+    name.unwrap_or_default().to_string_lossy().to_string()
+}
+
+fn is_fasta(path: &Path) -> bool {
+    let path = without_compression_extension(path);
+    path.extension().and_then(OsStr::to_str).is_some_and(|extension| FASTA_EXTENSIONS.contains(&extension))
+}
+
+/*
+The FASTA files of a directory, or the files matching a glob pattern, as samples named by their file stem.
+Sorted by path, so the line numbers (and the order of predictions) are stable.
+ */
+fn list_fasta_samples(path: &Path) -> Result<Vec<Sample>, SampleError> {
+    let mut paths = if path.is_dir() {
+        std::fs::read_dir(path)
+            .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.path())).collect::<Result<Vec<PathBuf>, std::io::Error>>())
+            .map_err(|e| SampleError::new(&format!("E: Failed to list the FASTA files of the directory '{}', got '{}'", path.display(), e), SampleErrorType::IoError(e)))?
+            .into_iter()
+            .filter(|fasta| fasta.is_file() && is_fasta(fasta))
+            .collect::<Vec<PathBuf>>()
+    } else {
+        glob::glob(&path.to_string_lossy())
+            .map_err(|e| SampleError::new(&format!("E: Invalid glob pattern '{}', {}", path.display(), e), SampleErrorType::None))?
+            .filter_map(Result::ok)
+            .filter(|fasta| fasta.is_file())
+            .collect::<Vec<PathBuf>>()
+    };
+    if paths.is_empty() {
+        return Err(SampleError::new(&format!("E: No FASTA files were found in '{}'", path.display()), SampleErrorType::None));
+    }
+    paths.sort();
+    Ok(paths.iter()
+        .enumerate()
+        .map(|(line_number, fasta)| Sample::new(&fasta_name(fasta), &fasta.to_string_lossy(), None, line_number))
+        .collect())
+}
+
+/*
+Column indices of a sample sheet, taken from its header, e.g. 'name\tpath\ttaxonomy\tweight'.
+name and path are required, taxonomy, gc, weight and group (or bin) are optional and other columns are ignored.
+ */
+#[derive(Clone, Copy)]
+struct SheetColumns {
+    name: usize,
+    path: usize,
+    taxonomy: Option<usize>,
+    gc: Option<usize>,
+    weight: Option<usize>,
+    group: Option<usize>,
+}
+
+impl SheetColumns {
+    // None if the line is not a header, i.e. a legacy positional list
+    fn from_header(line: &str) -> Result<Option<Self>, String> {
+        let header = line.split('\t').map(|column| column.trim().to_lowercase()).collect::<Vec<String>>();
+        let column = |names: &[&str]| -> Result<Option<usize>, String> {
+            let indices = header.iter().enumerate()
+                .filter(|(_, column)| names.contains(&column.as_str()))
+                .map(|(i, _)| i)
+                .collect::<Vec<usize>>();
+            match indices.as_slice() {
+                [] => Ok(None),
+                [index] => Ok(Some(*index)),
+                _ => Err(format!("the column '{}' appears more than once", names.join("/"))),
+            }
+        };
+        let (name, path) = match (column(&["name"])?, column(&["path"])?) {
+            (Some(name), Some(path)) => (name, path),
+            _ => return Ok(None),
+        };
+        Ok(Some(SheetColumns {
+            name,
+            path,
+            taxonomy: column(&["taxonomy"])?,
+            gc: column(&["gc"])?,
+            weight: column(&["weight"])?,
+            group: column(&["group", "bin"])?,
+        }))
+    }
+}
+
+// A sample sheet, its first line (after comments and blank lines) is a header naming the name and path columns
+pub fn is_sample_sheet(path: &Path) -> bool {
+    match File::open(path) {
+        Ok(f) => BufReader::new(f).lines()
+            .map_while(Result::ok)
+            .find(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .is_some_and(|line| matches!(SheetColumns::from_header(line.trim_end_matches('\r')), Ok(Some(_)))),
+        Err(_) => false,
+    }
+}
+
 pub struct SampleSource {
     path: PathBuf,
     include_taxonomy: bool,
+    taxdump: Option<Arc<TaxDump>>,
 }
 
 impl SampleSource {
@@ -115,9 +319,16 @@ impl SampleSource {
         Self {
             path: path.to_path_buf(),
             include_taxonomy,
+            taxdump: None,
         }
     }
 
+    // With a taxdump, the taxonomy column may hold an NCBI taxid instead of a GTDB string, it is read whenever present
+    pub fn with_taxdump(mut self, taxdump: Option<Arc<TaxDump>>) -> Self {
+        self.taxdump = taxdump;
+        self
+    }
+
     fn get_path(&self) -> &Path { &self.path }
     fn get_include_taxonomy(&self) -> bool { self.include_taxonomy }
 }
@@ -135,7 +346,16 @@ pub struct SampleIterator {
     source: PathBuf,
     reader: Box<dyn BufRead>,
     include_taxonomy: bool,
+    taxdump: Option<Arc<TaxDump>>,
     line_number: usize,
+    // Samples of a directory or a glob pattern, instead of the lines of a name2file list
+    listed: Option<std::vec::IntoIter<Sample>>,
+    // Set if the list starts with a header, see SheetColumns
+    columns: Option<SheetColumns>,
+    header_checked: bool,
+    file_line: usize,
+    // Set if the source can't be read, returned once as the first item
+    error: Option<SampleError>,
 }
 
 impl From<SampleSource> for SampleIterator {
@@ -145,47 +365,165 @@ impl From<SampleSource> for SampleIterator {
 }
 impl From<&SampleSource> for SampleIterator {
     fn from(value: &SampleSource) -> Self {
-        SampleIterator {
+        let mut iterator = SampleIterator {
             source: value.path.to_path_buf(),
-            reader: Box::new(BufReader::new(match File::open(value.get_path()) {
-                Ok(f) => f,
-                Err(e) => panic!("E: Tried to open '{}' to read samples from, but encountered the following error: '{}'", value.get_path().display(), e),
-            })),
+            reader: Box::new(std::io::empty()),
             include_taxonomy: value.get_include_taxonomy(),
+            taxdump: value.taxdump.clone(),
             line_number: 0,
+            listed: None,
+            columns: None,
+            header_checked: false,
+            file_line: 0,
+            error: None,
+        };
+        if is_stdin(value.get_path()) {
+            iterator.error = Some(SampleError::new(&format!("E: Sequences from stdin ('{}') can only be predicted, as a multi-record (meta) input, use a name2file list, a directory or a glob pattern instead", STDIN_PATH), SampleErrorType::None));
+        } else if value.get_path().is_dir() || is_glob_pattern(value.get_path()) {
+            match list_fasta_samples(value.get_path()) {
+                Ok(listed) => iterator.listed = Some(listed.into_iter()),
+                Err(e) => iterator.error = Some(e),
+            }
+            iterator.header_checked = true;
+        } else {
+            match File::open(value.get_path()) {
+                Ok(f) => iterator.reader = Box::new(BufReader::new(f)),
+                Err(e) => iterator.error = Some(SampleError::new(&format!("E: Tried to open '{}' to read samples from, but encountered the following error: '{}'", value.get_path().display(), e), SampleErrorType::IoError(e))),
+            }
         }
+        iterator
     }
 }
 
 impl SampleIterator {
-    fn next(&mut self) -> Option<Result<Sample, SampleError>> {
-        let mut line = String::new();
-        match self.reader.read_line(&mut line) {
-            Err(e) => Some(Err(SampleError::new(&format!("E: Trying to parse samples from '{}' led to '{}'", self.source.display(), e), SampleErrorType::IoError(e)))),
-            Ok(0) => None,
-            Ok(_) => {
-                let mut split = line.strip_suffix('\n').unwrap().split('\t');
-                let name = match split.next() {
-                    Some(n) => n,
-                    None => return Some(Err(SampleError::new(&format!("E: Invalid line (number {}) in file {}, expected a tab-delimited line.", self.line_number, self.source.display()), SampleErrorType::None))),
-                };
-                let file_path = match split.next() {
-                    Some(fp) => fp,
-                    None => return Some(Err(SampleError::new(&format!("E: Invalid line (number {}) in file {}, expected a tab-delimited line with at-least 2 fields.", self.line_number, self.source.display()), SampleErrorType::None)))
-                };
-                let taxonomy = if self.include_taxonomy {
-                    match split.next() {
-                        Some(t) => Some(t),
-                        None => return Some(Err(SampleError::new(&format!("E: Invalid line (number {}) in file {}, expected a tab-delimited line with at-least 3 fields. This may happen if taxonomy column is missing, you may set --kmer 0 or add the missing column.", self.line_number, self.source.display()), SampleErrorType::None))),
+    // The next line holding a sample, comments (#), blank lines and the sample sheet header are skipped
+    fn next_line(&mut self) -> Option<Result<String, SampleError>> {
+        loop {
+            let mut line = String::new();
+            match self.reader.read_line(&mut line) {
+                Err(e) => return Some(Err(SampleError::new(&format!("E: Trying to parse samples from '{}' led to '{}'", self.source.display(), e), SampleErrorType::IoError(e)))),
+                Ok(0) => return None,
+                Ok(_) => {
+                    self.file_line += 1;
+                    let line = line.trim_end_matches(['\n', '\r']);
+                    if line.trim().is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    if !self.header_checked {
+                        self.header_checked = true;
+                        match SheetColumns::from_header(line) {
+                            Ok(Some(columns)) => {
+                                self.columns = Some(columns);
+                                continue;
+                            },
+                            Ok(None) => (),
+                            Err(e) => return Some(Err(SampleError::new(&format!("E: Invalid header in file {}, {}.", self.source.display(), e), SampleErrorType::None))),
+                        }
                     }
-                } else {
-                    None
-                };
-                self.line_number += 1;
-                Some(Ok(Sample::new(name, file_path, taxonomy, self.line_number - 1)))
+                    return Some(Ok(line.to_string()));
+                }
             }
         }
     }
+
+    // A sample with a GTDB taxonomy string, or an NCBI taxid when a taxdump is given
+    fn sample(&self, name: &str, file_paths: Vec<PathBuf>, taxonomy: Option<&str>, line_number: usize) -> Result<Sample, SampleError> {
+        let file_path = match file_paths.first() {
+            Some(file_path) => file_path.to_string_lossy().to_string(),
+            None => return Err(SampleError::new(&format!("E: Invalid line (number {}) in file {}, the path of '{}' is empty.", line_number, self.source.display(), name), SampleErrorType::None)),
+        };
+        let mut sample = match (&self.taxdump, taxonomy.and_then(|t| t.trim().parse::<TaxId>().ok())) {
+            (Some(taxdump), Some(taxid)) => match taxdump.get_lineage(taxid) {
+                Some(lineage) => Sample::from_lineage(name, &file_path, lineage, line_number),
+                None => return Err(SampleError::new(&format!("E: Invalid line (number {}) in file {}, taxid {} is missing from the taxdump.", line_number, self.source.display(), taxid), SampleErrorType::None)),
+            },
+            _ => match taxonomy.map(Taxonomy::parse).transpose() {
+                Ok(taxonomy) => Sample::with_taxonomy(name, &file_path, taxonomy, line_number),
+                Err(e) => return Err(SampleError::new(&format!("E: Invalid line (number {}) in file {}, invalid taxonomy: {}.", line_number, self.source.display(), e), SampleErrorType::None)),
+            },
+        };
+        sample.paths = file_paths;
+        Ok(sample)
+    }
+
+    // Legacy format, <name>\t<fasta-file>[\t<taxonomy>], paths are taken as is, commas included
+    fn parse_positional(&mut self, line: &str) -> Result<Sample, SampleError> {
+        let mut split = line.split('\t');
+        let name = match split.next() {
+            Some(n) => n,
+            None => return Err(SampleError::new(&format!("E: Invalid line (number {}) in file {}, expected a tab-delimited line.", self.line_number, self.source.display()), SampleErrorType::None)),
+        };
+        let file_path = match split.next() {
+            Some(fp) => fp,
+            None => return Err(SampleError::new(&format!("E: Invalid line (number {}) in file {}, expected a tab-delimited line with at-least 2 fields.", self.line_number, self.source.display()), SampleErrorType::None))
+        };
+        let taxonomy = if self.include_taxonomy || self.taxdump.is_some() {
+            match split.next() {
+                Some(t) => Some(t),
+                None if !self.include_taxonomy => None,
+                None => return Err(SampleError::new(&format!("E: Invalid line (number {}) in file {}, expected a tab-delimited line with at-least 3 fields. This may happen if taxonomy column is missing, you may set --kmer 0 or add the missing column.", self.line_number, self.source.display()), SampleErrorType::None)),
+            }
+        } else {
+            None
+        };
+        self.line_number += 1;
+        self.sample(name, vec![PathBuf::from(file_path)], taxonomy, self.line_number - 1)
+    }
+
+    // Sample sheet format, columns by the header, relative paths are resolved against the directory of the sheet
+    fn parse_sheet_line(&mut self, line: &str, columns: SheetColumns) -> Result<Sample, SampleError> {
+        let fields = line.split('\t').map(str::trim).collect::<Vec<&str>>();
+        let field = |column: Option<usize>| column.and_then(|column| fields.get(column)).copied().filter(|field| !field.is_empty());
+        let invalid = |message: String| SampleError::new(&format!("E: Invalid line {} in sample sheet {}, {}.", self.file_line, self.source.display(), message), SampleErrorType::None);
+
+        let name = field(Some(columns.name)).ok_or_else(|| invalid(String::from("the name is missing")))?;
+        let file_paths = field(Some(columns.path)).ok_or_else(|| invalid(String::from("the path is missing")))?;
+        let file_paths = split_paths(file_paths).into_iter()
+            .map(|file_path| match self.source.parent() {
+                Some(directory) if file_path.is_relative() => directory.join(file_path),
+                _ => file_path,
+            })
+            .collect::<Vec<PathBuf>>();
+        let taxonomy = field(columns.taxonomy);
+        if self.include_taxonomy && taxonomy.is_none() {
+            return Err(invalid(String::from("the taxonomy is missing, you may set --kmer 0 or fill the taxonomy column")));
+        }
+        let gc = field(columns.gc)
+            .map(|gc| gc.parse::<f64>().ok().filter(|gc| (0.0..=100.0).contains(gc)).ok_or_else(|| invalid(format!("GC should be a percentage, got '{}'", gc))))
+            .transpose()?;
+        let weight = field(columns.weight)
+            .map(|weight| weight.parse::<f64>().ok().filter(|weight| *weight >= 0.0).ok_or_else(|| invalid(format!("weight should be non-negative, got '{}'", weight))))
+            .transpose()?;
+
+        self.line_number += 1;
+        let mut sample = self.sample(name, file_paths, if self.include_taxonomy || self.taxdump.is_some() { taxonomy } else { None }, self.line_number - 1)?;
+        sample.gc = gc;
+        sample.weight = weight;
+        sample.group = field(columns.group).map(str::to_string);
+        Ok(sample)
+    }
+
+    fn next(&mut self) -> Option<Result<Sample, SampleError>> {
+        if let Some(e) = self.error.take() {
+            self.listed = Some(Vec::new().into_iter());
+            return Some(Err(e));
+        }
+        if let Some(listed) = self.listed.as_mut() {
+            return listed.next().map(|sample| if self.include_taxonomy {
+                Err(SampleError::new(&format!("E: '{}' has no taxonomy column, as it is not a name2file list. You may set --kmer 0 or use a list with a taxonomy column.", self.source.display()), SampleErrorType::None))
+            } else {
+                Ok(sample)
+            });
+        }
+        let line = match self.next_line()? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e)),
+        };
+        match self.columns {
+            Some(columns) => Some(self.parse_sheet_line(&line, columns)),
+            None => Some(self.parse_positional(&line)),
+        }
+    }
 }
 
 impl Iterator for SampleIterator {
@@ -207,4 +545,68 @@ impl Iterator for SampleIterator {
 //     {
 //         self.next().unwrap()
 //     }
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::path::PathBuf;
+    use crate::samples_file_reader::{merge_repeated_samples, Sample, SampleError, SampleSource};
+
+    #[test]
+    fn directory_and_glob() {
+        let from_directory = SampleSource::new(Path::new("../tests/import/genomes"), false).into_iter().collect::<Result<Vec<Sample>, SampleError>>().unwrap();
+        assert_eq!(from_directory.iter().map(|s| s.get_name()).collect::<Vec<_>>(),
+                   vec!["GCF_000005845.2_ASM584v2_cds_from_genomic", "GCF_000005845.2_ASM584v2_genomic"]);
+        assert_eq!(from_directory[1].get_path(), Path::new("../tests/import/genomes/GCF_000005845.2_ASM584v2_genomic.fna"));
+
+        let from_glob = SampleSource::new(Path::new("../tests/import/genomes/*_genomic.fna"), false).into_iter().collect::<Result<Vec<Sample>, SampleError>>().unwrap();
+        assert_eq!(from_glob, from_directory);
+        // A directory has no taxonomy column
+        assert!(SampleSource::new(Path::new("../tests/import/genomes"), true).into_iter().all(|s| s.is_err()));
+    }
+    #[test]
+    fn sample_sheet() {
+        let samples = SampleSource::new(Path::new("../tests/taxonomy_test_sample_sheet.tsv"), true).into_iter().collect::<Result<Vec<Sample>, SampleError>>().unwrap();
+        assert_eq!(samples.iter().map(|s| (s.get_name(), s.get_line_number(), s.get_gc(), s.get_group())).collect::<Vec<_>>(),
+                   vec![("Actinomyces_graevenitzii_0", 0, Some(68.5), Some("HMP_2012")), ("Actinomyces_oris_C_0", 1, None, Some("CM_periimplantitis"))]);
+        // Relative to the sheet, rather than to the working directory
+        assert_eq!(samples[0].get_path(), Path::new("../tests/../data/HMP_2012__SRS019219__bin.33_genes.fna.gz"));
+        assert_eq!(samples[1].get_taxonomy().as_ref().map(|t| t.to_string()), Some(String::from("d__Bacteria;p__Actinobacteriota;c__Actinomycetia;o__Actinomycetales;f__Actinomycetaceae;g__Actinomyces;s__Actinomyces oris_C")));
+        assert_eq!(samples[0].get_weight(), None);
+
+        // The legacy positional format still works
+        let legacy = SampleSource::new(Path::new("../tests/taxonomy_test_training.txt"), true).into_iter().collect::<Result<Vec<Sample>, SampleError>>().unwrap();
+        assert_eq!(legacy[0].get_name(), "Actinomyces_graevenitzii_0");
+        assert_eq!(legacy[0].get_path(), Path::new("../data/HMP_2012__SRS019219__bin.33_genes.fna.gz"));
+    }
+    #[test]
+    fn multiple_fasta_files() {
+        let samples = SampleSource::new(Path::new("../tests/multi_fasta_training.txt"), true).into_iter().collect::<Result<Vec<Sample>, SampleError>>().unwrap();
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[0].get_paths(), &[PathBuf::from("../tests/../data/HMP_2012__SRS019219__bin.33_genes.fna.gz"), PathBuf::from("../tests/../data/FerrettiP_2018__CA_C10002MS2015SA_t0M15__bin.17_genes.fna.gz")]);
+
+        let models = merge_repeated_samples(&samples).unwrap();
+        assert_eq!(models.iter().map(|s| (s.get_name(), s.get_paths().len())).collect::<Vec<_>>(),
+                   vec![("Actinomyces_graevenitzii", 2), ("Neisseria_flavescens_A", 2)]);
+        assert_eq!(models[1].get_path(), Path::new("../tests/../data/FerrettiP_2018__CA_C10002MS2015SA_t0M15__bin.9_genes.fna.gz"));
+
+        // The same name with another taxonomy is most likely a mistake
+        let conflicting = [samples[1].clone(), Sample::new(samples[1].get_name(), "other.fna", Some("d__Archaea"), 3)];
+        assert!(merge_repeated_samples(&conflicting).is_err());
+
+        // Only sample sheets split paths, a legacy list may have commas in its paths
+        let legacy = SampleSource::new(Path::new("../tests/comma_path_training.txt"), true).into_iter().collect::<Result<Vec<Sample>, SampleError>>().unwrap();
+        assert_eq!(legacy[0].get_paths(), &[PathBuf::from("../data/bins,2012/HMP_2012__SRS019219__bin.33_genes.fna.gz")]);
+    }
+
+    #[test]
+    fn unreadable_sources() {
+        // Reported as the first item instead of a panic, e.g. a list that does not exist or a glob without matches
+        for path in ["../tests/missing_training.txt", "../tests/import/genomes/*.missing", "-"] {
+            let mut samples = SampleSource::new(Path::new(path), false).into_iter();
+            assert!(samples.next().unwrap().is_err(), "{}", path);
+            assert!(samples.next().is_none(), "{}", path);
+        }
+    }
+}
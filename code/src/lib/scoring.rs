@@ -0,0 +1,358 @@
+// Scores a GeneZip prediction table against a truth table of query taxonomies.
+// The best hit of every query is assigned the taxonomy of its model in the database, and compared rank by rank with the truth.
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+use hashbrown::HashMap;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use crate::taxonomy::{TaxonomicRank, Taxonomy};
+
+pub struct ScoreParameters {
+    // Number of bootstrap replicates, 0 skips the confidence intervals
+    pub bootstrap: usize,
+    pub confidence: f64,
+    pub random_state: u64,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct ConfidenceInterval {
+    low: f64,
+    high: f64,
+}
+
+impl ConfidenceInterval {
+    pub fn get_low(&self) -> f64 { self.low }
+    pub fn get_high(&self) -> f64 { self.high }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RankScore {
+    rank: String,
+    // Queries whose truth names this rank
+    queries: usize,
+    // Queries whose best hit names this rank
+    classified: usize,
+    correct: usize,
+    precision: Option<f64>,
+    recall: Option<f64>,
+    f1: Option<f64>,
+    unclassified_rate: Option<f64>,
+    precision_ci: Option<ConfidenceInterval>,
+    recall_ci: Option<ConfidenceInterval>,
+    f1_ci: Option<ConfidenceInterval>,
+    unclassified_rate_ci: Option<ConfidenceInterval>,
+}
+
+impl RankScore {
+    pub fn get_rank(&self) -> &str { &self.rank }
+    pub fn get_queries(&self) -> usize { self.queries }
+    pub fn get_classified(&self) -> usize { self.classified }
+    pub fn get_correct(&self) -> usize { self.correct }
+    pub fn get_precision(&self) -> Option<f64> { self.precision }
+    pub fn get_recall(&self) -> Option<f64> { self.recall }
+    pub fn get_f1(&self) -> Option<f64> { self.f1 }
+    pub fn get_unclassified_rate(&self) -> Option<f64> { self.unclassified_rate }
+    pub fn get_precision_ci(&self) -> Option<ConfidenceInterval> { self.precision_ci }
+    pub fn get_recall_ci(&self) -> Option<ConfidenceInterval> { self.recall_ci }
+    pub fn get_f1_ci(&self) -> Option<ConfidenceInterval> { self.f1_ci }
+    pub fn get_unclassified_rate_ci(&self) -> Option<ConfidenceInterval> { self.unclassified_rate_ci }
+}
+
+#[derive(Serialize)]
+pub struct ScoreReport {
+    queries: usize,
+    // Queries of the truth table that are absent from the predictions, scored as unclassified
+    missing_predictions: usize,
+    // Predicted queries that are absent from the truth table, not scored
+    missing_truth: usize,
+    // Best hits that have no taxonomy in the database, scored as unclassified
+    unknown_models: usize,
+    bootstrap: usize,
+    confidence: Option<f64>,
+    ranks: Vec<RankScore>,
+}
+
+struct ScoredQuery<'a> {
+    truth: &'a Taxonomy,
+    predicted: Option<&'a Taxonomy>,
+}
+
+// The best hit of every query, as written by predict (Genome_name and Best_hit columns), NA when no model was scored
+pub fn read_best_hits(path: &Path) -> std::io::Result<Vec<(String, Option<String>)>> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let column = |name: &str| header.split('\t').position(|c| c == name)
+        .ok_or(Error::new(ErrorKind::InvalidData, format!("E: '{}' has no {} column, expected a GeneZip prediction table", path.display(), name)));
+    let (name_column, hit_column) = (column("Genome_name")?, column("Best_hit")?);
+
+    let mut best_hits = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        match (fields.get(name_column), fields.get(hit_column)) {
+            (Some(&name), Some(&hit)) => best_hits.push((name.to_string(), if hit == "NA" { None } else { Some(hit.to_string()) })),
+            _ => return Err(Error::new(ErrorKind::InvalidData, format!("E: line {} of '{}' has {} columns, expected at least {}", i + 2, path.display(), fields.len(), name_column.max(hit_column) + 1))),
+        }
+    }
+    Ok(best_hits)
+}
+
+// Truth table in the format <query-name>\t<full taxonomy string>
+pub fn read_truth(path: &Path) -> std::io::Result<HashMap<String, Taxonomy>> {
+    let mut truth = HashMap::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let (name, taxonomy) = line.split_once('\t')
+            .ok_or(Error::new(ErrorKind::InvalidData, format!("E: line {} of '{}' is not in the format <query-name>\\t<taxonomy>", i + 1, path.display())))?;
+        let taxonomy = Taxonomy::parse(taxonomy)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("E: line {} of '{}': {}", i + 1, path.display(), e)))?;
+        if truth.insert(name.to_string(), taxonomy).is_some() {
+            return Err(Error::new(ErrorKind::InvalidData, format!("E: query '{}' appears more than once in '{}'", name, path.display())));
+        }
+    }
+    Ok(truth)
+}
+
+fn ratio(numerator: usize, denominator: usize) -> Option<f64> {
+    if denominator == 0 { None } else { Some(numerator as f64 / denominator as f64) }
+}
+
+fn f1(precision: Option<f64>, recall: Option<f64>) -> Option<f64> {
+    match (precision, recall) {
+        (Some(p), Some(r)) if p + r > 0.0 => Some(2.0 * p * r / (p + r)),
+        (Some(_), Some(_)) => Some(0.0),
+        _ => None,
+    }
+}
+
+// Counts of (queries, classified, correct) at a rank
+fn rank_counts<'a, I>(queries: I, rank: &TaxonomicRank) -> (usize, usize, usize)
+where I: Iterator<Item=&'a ScoredQuery<'a>> {
+    queries.filter(|q| q.truth.has_rank(rank))
+        .fold((0, 0, 0), |(total, classified, correct), q| {
+            let predicted = q.predicted.and_then(|p| p.get_taxa(rank)).filter(|name| !name.is_empty());
+            let is_correct = predicted.is_some_and(|name| Some(name) == q.truth.get_taxa(rank));
+            (total + 1, classified + predicted.is_some() as usize, correct + is_correct as usize)
+        })
+}
+
+// Metrics of a rank: precision, recall, F1 and unclassified rate
+fn rank_metrics((queries, classified, correct): (usize, usize, usize)) -> [Option<f64>; 4] {
+    let precision = ratio(correct, classified);
+    let recall = ratio(correct, queries);
+    [precision, recall, f1(precision, recall), ratio(queries - classified, queries)]
+}
+
+// Percentile interval of the replicate values, None if no replicate defined the metric
+fn percentile_interval(mut values: Vec<f64>, confidence: f64) -> Option<ConfidenceInterval> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let alpha = (1.0 - confidence) / 2.0;
+    let at = |q: f64| values[(q * (values.len() - 1) as f64).round() as usize];
+    Some(ConfidenceInterval { low: at(alpha), high: at(1.0 - alpha) })
+}
+
+fn bootstrap(queries: &[ScoredQuery], parameters: &ScoreParameters) -> HashMap<TaxonomicRank, [Option<ConfidenceInterval>; 4]> {
+    let mut rng = StdRng::seed_from_u64(parameters.random_state);
+    let mut replicates: HashMap<TaxonomicRank, [Vec<f64>; 4]> = HashMap::new();
+    for _ in 0..parameters.bootstrap {
+        let sample = (0..queries.len()).map(|_| &queries[rng.random_range(0..queries.len())]).collect::<Vec<&ScoredQuery>>();
+        for rank in TaxonomicRank::down_iterator() {
+            let metrics = rank_metrics(rank_counts(sample.iter().copied(), &rank));
+            let values = replicates.entry(rank).or_default();
+            for (metric, value) in metrics.into_iter().enumerate() {
+                if let Some(value) = value {
+                    values[metric].push(value);
+                }
+            }
+        }
+    }
+    replicates.into_iter()
+        .map(|(rank, values)| (rank, values.map(|v| percentile_interval(v, parameters.confidence))))
+        .collect()
+}
+
+pub fn score(best_hits: &[(String, Option<String>)], model2taxonomy: &HashMap<String, Taxonomy>, truth: &HashMap<String, Taxonomy>, parameters: &ScoreParameters) -> std::io::Result<ScoreReport> {
+    if model2taxonomy.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "E: the database has no taxonomy, build it with a taxonomy column to score predictions"));
+    }
+    if parameters.bootstrap > 0 && !(parameters.confidence > 0.0 && parameters.confidence < 1.0) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("E: confidence must be between 0 and 1, got {}", parameters.confidence)));
+    }
+
+    let mut predicted = HashMap::new();
+    for (name, best_hit) in best_hits {
+        if predicted.insert(name.as_str(), best_hit.as_ref()).is_some() {
+            return Err(Error::new(ErrorKind::InvalidData, format!("E: query '{}' is predicted more than once", name)));
+        }
+    }
+
+    // Sorted so the bootstrap draws don't depend on the hashing order
+    let mut names = truth.keys().collect::<Vec<&String>>();
+    names.sort();
+    let mut unknown_models = HashSet::new();
+    let queries = names.iter()
+        .map(|&name| ScoredQuery {
+            truth: &truth[name],
+            predicted: predicted.get(name.as_str()).copied().flatten().and_then(|hit| {
+                let taxonomy = model2taxonomy.get(hit);
+                if taxonomy.is_none() {
+                    unknown_models.insert(hit);
+                }
+                taxonomy
+            }),
+        })
+        .collect::<Vec<ScoredQuery>>();
+
+    let intervals = if parameters.bootstrap > 0 && !queries.is_empty() { bootstrap(&queries, parameters) } else { HashMap::new() };
+    let ranks = TaxonomicRank::down_iterator()
+        .map(|rank| {
+            let counts = rank_counts(queries.iter(), &rank);
+            let [precision, recall, f1, unclassified_rate] = rank_metrics(counts);
+            let [precision_ci, recall_ci, f1_ci, unclassified_rate_ci] = intervals.get(&rank).copied().unwrap_or_default();
+            RankScore {
+                rank: rank.to_string(),
+                queries: counts.0,
+                classified: counts.1,
+                correct: counts.2,
+                precision,
+                recall,
+                f1,
+                unclassified_rate,
+                precision_ci,
+                recall_ci,
+                f1_ci,
+                unclassified_rate_ci,
+            }
+        })
+        .collect();
+
+    Ok(ScoreReport {
+        queries: queries.len(),
+        missing_predictions: names.iter().filter(|name| !predicted.contains_key(name.as_str())).count(),
+        missing_truth: predicted.keys().filter(|&&name| !truth.contains_key(name)).count(),
+        unknown_models: unknown_models.len(),
+        bootstrap: parameters.bootstrap,
+        confidence: if parameters.bootstrap > 0 { Some(parameters.confidence) } else { None },
+        ranks,
+    })
+}
+
+fn na(value: Option<f64>) -> String {
+    value.map_or(String::from("NA"), |v| format!("{:.5}", v))
+}
+
+impl ScoreReport {
+    pub fn get_ranks(&self) -> &[RankScore] { &self.ranks }
+    pub fn get_missing_predictions(&self) -> usize { self.missing_predictions }
+    pub fn get_missing_truth(&self) -> usize { self.missing_truth }
+    pub fn get_unknown_models(&self) -> usize { self.unknown_models }
+
+    /*
+    Writes a row per rank, with the bootstrap intervals as <metric>_low and <metric>_high columns if computed.
+    A .json path writes the whole report as JSON instead
+     */
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut stream = BufWriter::new(File::create(path)?);
+        if path.extension().is_some_and(|e| e == "json") {
+            serde_json::to_writer_pretty(&mut stream, self).map_err(Error::other)?;
+            writeln!(stream)?;
+            return stream.flush();
+        }
+
+        let metrics = ["precision", "recall", "f1", "unclassified_rate"];
+        write!(stream, "rank\tqueries\tclassified\tcorrect\t{}", metrics.join("\t"))?;
+        if self.bootstrap > 0 {
+            for metric in metrics {
+                write!(stream, "\t{metric}_low\t{metric}_high")?;
+            }
+        }
+        writeln!(stream)?;
+        for r in &self.ranks {
+            write!(stream, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", r.rank, r.queries, r.classified, r.correct, na(r.precision), na(r.recall), na(r.f1), na(r.unclassified_rate))?;
+            if self.bootstrap > 0 {
+                for ci in [r.precision_ci, r.recall_ci, r.f1_ci, r.unclassified_rate_ci] {
+                    write!(stream, "\t{}\t{}", na(ci.map(|c| c.low)), na(ci.map(|c| c.high)))?;
+                }
+            }
+            writeln!(stream)?;
+        }
+        stream.flush()
+    }
+}
+
+impl Display for ScoreReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Scored {} queries ({} without prediction, {} predictions without truth, {} best hits without taxonomy)",
+                 self.queries, self.missing_predictions, self.missing_truth, self.unknown_models)?;
+        writeln!(f, "rank\tprecision\trecall\tf1\tunclassified")?;
+        for r in &self.ranks {
+            let short = |v: Option<f64>| v.map_or(String::from("NA"), |v| format!("{:.3}", v));
+            writeln!(f, "{}\t{}\t{}\t{}\t{}", r.rank, short(r.precision), short(r.recall), short(r.f1), short(r.unclassified_rate))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashbrown::HashMap;
+    use crate::scoring::{score, ScoreParameters};
+    use crate::taxonomy::Taxonomy;
+
+    fn taxonomy(value: &str) -> Taxonomy { Taxonomy::parse(value).unwrap() }
+
+    #[test]
+    fn rank_scores() {
+        let model2taxonomy: HashMap<String, Taxonomy> = [
+            ("m1", "d__Bacteria;p__Firmicutes;c__Bacilli;o__;f__;g__;s__"),
+            ("m2", "d__Bacteria;p__Proteobacteria;c__Gammaproteobacteria;o__;f__;g__;s__"),
+        ].into_iter().map(|(name, t)| (name.to_string(), taxonomy(t))).collect();
+        let truth: HashMap<String, Taxonomy> = [
+            ("q1", "d__Bacteria;p__Firmicutes;c__Bacilli;o__;f__;g__;s__"),
+            ("q2", "d__Bacteria;p__Firmicutes;c__Clostridia;o__;f__;g__;s__"),
+            ("q3", "d__Bacteria;p__Proteobacteria;c__;o__;f__;g__;s__"),
+            ("q4", "d__Bacteria;p__Proteobacteria;c__;o__;f__;g__;s__"),
+        ].into_iter().map(|(name, t)| (name.to_string(), taxonomy(t))).collect();
+        let best_hits = vec![
+            (String::from("q1"), Some(String::from("m1"))),
+            (String::from("q2"), Some(String::from("m1"))),
+            (String::from("q3"), None),
+            (String::from("q5"), Some(String::from("m2"))),
+        ];
+        let parameters = ScoreParameters { bootstrap: 0, confidence: 0.95, random_state: 1 };
+
+        let report = score(&best_hits, &model2taxonomy, &truth, &parameters).unwrap();
+        assert_eq!((report.get_missing_predictions(), report.get_missing_truth(), report.get_unknown_models()), (1, 1, 0));
+        let phylum = &report.get_ranks()[1];
+        assert_eq!((phylum.get_queries(), phylum.get_classified(), phylum.get_correct()), (4, 2, 2));
+        assert_eq!((phylum.get_precision(), phylum.get_recall(), phylum.get_unclassified_rate()), (Some(1.0), Some(0.5), Some(0.5)));
+        let class = &report.get_ranks()[2];
+        assert_eq!((class.get_queries(), class.get_classified(), class.get_correct()), (2, 2, 1));
+        assert_eq!(class.get_f1(), Some(0.5));
+        assert_eq!(report.get_ranks()[6].get_queries(), 0);
+        assert_eq!(report.get_ranks()[6].get_precision(), None);
+
+        let parameters = ScoreParameters { bootstrap: 200, ..parameters };
+        let report = score(&best_hits, &model2taxonomy, &truth, &parameters).unwrap();
+        let ci = report.get_ranks()[1].get_recall_ci().unwrap();
+        assert!(ci.get_low() <= 0.5 && 0.5 <= ci.get_high());
+        assert_eq!(report.get_ranks()[1].get_precision_ci().map(|c| (c.get_low(), c.get_high())), Some((1.0, 1.0)));
+        assert_eq!(ci, score(&best_hits, &model2taxonomy, &truth, &parameters).unwrap().get_ranks()[1].get_recall_ci().unwrap());
+
+        assert!(score(&best_hits, &HashMap::new(), &truth, &parameters).is_err());
+    }
+}
@@ -0,0 +1,331 @@
+// Cuts genomes into random fragments, with optional sequencing errors, to benchmark the accuracy on short contigs.
+// Writes the fragments as FASTA, a truth table of their taxonomy (as read by score) and a table of where each fragment came from.
+
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use crate::contig_naming::sequence_id2str;
+use crate::fasta_records_iterator::{FastaPartType, FastaRecordIterator};
+use crate::samples_file_reader::Sample;
+
+const NUCLEOTIDES: [u8; 4] = [b'A', b'C', b'G', b'T'];
+const FASTA_LINE_WIDTH: usize = 80;
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum LengthDistribution {
+    // Every fragment has the given length
+    Fixed,
+    // The given length is the median, and the log of the length is normally distributed
+    LogNormal,
+}
+
+impl Display for LengthDistribution {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            LengthDistribution::Fixed => "fixed",
+            LengthDistribution::LogNormal => "log-normal",
+        })
+    }
+}
+
+pub struct SimulationParameters {
+    pub fragments: usize,
+    pub length: usize,
+    pub distribution: LengthDistribution,
+    // Standard deviation of the log of the length, used by log-normal
+    pub sigma: f64,
+    // Per-base error rates, an indel is an insertion or a deletion with equal probability
+    pub substitution_rate: f64,
+    pub indel_rate: f64,
+    pub buffer_size: usize,
+    pub random_state: u64,
+}
+
+pub struct Fragment {
+    name: String,
+    genome: String,
+    contig: String,
+    start: usize,
+    // Length on the genome, before insertions and deletions
+    length: usize,
+    substitutions: usize,
+    insertions: usize,
+    deletions: usize,
+    sequence: Vec<u8>,
+}
+
+impl Fragment {
+    pub fn get_name(&self) -> &str { &self.name }
+    pub fn get_genome(&self) -> &str { &self.genome }
+    pub fn get_contig(&self) -> &str { &self.contig }
+    pub fn get_start(&self) -> usize { self.start }
+    pub fn get_length(&self) -> usize { self.length }
+    pub fn get_sequence(&self) -> &[u8] { &self.sequence }
+}
+
+#[derive(Default)]
+pub struct SimulationSummary {
+    genomes: usize,
+    fragments: usize,
+    bases: usize,
+    substitutions: usize,
+    insertions: usize,
+    deletions: usize,
+}
+
+impl Display for SimulationSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Simulated {} fragments ({} bp) from {} genomes, with {} substitutions, {} insertions and {} deletions",
+                 self.fragments, self.bases, self.genomes, self.substitutions, self.insertions, self.deletions)
+    }
+}
+
+fn check_parameters(parameters: &SimulationParameters) -> std::io::Result<()> {
+    let is_rate = |rate: f64| (0.0..=1.0).contains(&rate);
+    if parameters.length == 0 {
+        Err(Error::new(ErrorKind::InvalidInput, "E: fragment length must be >= 1"))
+    } else if !(parameters.sigma >= 0.0 && parameters.sigma.is_finite()) {
+        Err(Error::new(ErrorKind::InvalidInput, format!("E: sigma must be >= 0, got {}", parameters.sigma)))
+    } else if !is_rate(parameters.substitution_rate) || !is_rate(parameters.indel_rate) || parameters.substitution_rate + parameters.indel_rate > 1.0 {
+        Err(Error::new(ErrorKind::InvalidInput, format!("E: error rates must be between 0 and 1 together, got substitution {} and indel {}", parameters.substitution_rate, parameters.indel_rate)))
+    } else {
+        Ok(())
+    }
+}
+
+// The contigs of a FASTA, as (name, upper-case sequence)
+fn read_contigs(fasta: &Path, buffer_size: usize) -> Vec<(String, Vec<u8>)> {
+    let mut contigs: Vec<(String, Vec<u8>)> = Vec::new();
+    for part in FastaRecordIterator::new(fasta, buffer_size) {
+        match part {
+            FastaPartType::ID(id) => contigs.push((sequence_id2str(&id).to_string(), Vec::new())),
+            FastaPartType::Nuc(nuc) if nuc.is_ascii_alphabetic() => match contigs.last_mut() {
+                Some((_, sequence)) => sequence.push(nuc),
+                None => contigs.push((String::new(), vec![nuc])),
+            },
+            FastaPartType::Nuc(_) => (),
+        }
+    }
+    contigs.retain(|(_, sequence)| !sequence.is_empty());
+    contigs
+}
+
+// Box-Muller transform of two uniform draws
+fn standard_normal(rng: &mut StdRng) -> f64 {
+    let u1 = 1.0 - rng.random::<f64>();
+    let u2 = rng.random::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+fn draw_length(rng: &mut StdRng, parameters: &SimulationParameters) -> usize {
+    match parameters.distribution {
+        LengthDistribution::Fixed => parameters.length,
+        LengthDistribution::LogNormal => {
+            let length = ((parameters.length as f64).ln() + parameters.sigma * standard_normal(rng)).exp();
+            (length.round() as usize).max(1)
+        },
+    }
+}
+
+fn random_nucleotide(rng: &mut StdRng) -> u8 {
+    NUCLEOTIDES[rng.random_range(0..NUCLEOTIDES.len())]
+}
+
+// Copies the region with errors, returns the sequence and the number of substitutions, insertions and deletions
+fn add_errors(region: &[u8], rng: &mut StdRng, substitution_rate: f64, indel_rate: f64) -> (Vec<u8>, usize, usize, usize) {
+    let (mut substitutions, mut insertions, mut deletions) = (0, 0, 0);
+    let mut sequence = Vec::with_capacity(region.len());
+    for &nuc in region {
+        let draw = rng.random::<f64>();
+        if draw < substitution_rate {
+            let substitute = NUCLEOTIDES.iter().filter(|&&n| n != nuc).copied().collect::<Vec<u8>>();
+            sequence.push(substitute[rng.random_range(0..substitute.len())]);
+            substitutions += 1;
+        } else if draw < substitution_rate + indel_rate {
+            if rng.random::<bool>() {
+                sequence.push(nuc);
+                sequence.push(random_nucleotide(rng));
+                insertions += 1;
+            } else {
+                deletions += 1;
+            }
+        } else {
+            sequence.push(nuc);
+        }
+    }
+    (sequence, substitutions, insertions, deletions)
+}
+
+/*
+Fragments of a genome. The start is drawn uniformly among all the positions a fragment fits in,
+and fragments longer than the longest contig are cut to its length.
+The contigs of a genome are those of all its FASTA files.
+ */
+fn simulate_genome(sample: &Sample, parameters: &SimulationParameters, rng: &mut StdRng) -> Vec<Fragment> {
+    let contigs = sample.get_paths().iter()
+        .flat_map(|fasta| read_contigs(fasta, parameters.buffer_size))
+        .collect::<Vec<(String, Vec<u8>)>>();
+    let longest = contigs.iter().map(|(_, sequence)| sequence.len()).max().unwrap_or(0);
+    if longest == 0 {
+        return Vec::new();
+    }
+
+    (0..parameters.fragments)
+        .map(|i| {
+            let length = draw_length(rng, parameters).min(longest);
+            let candidates = contigs.iter().filter(|(_, sequence)| sequence.len() >= length).collect::<Vec<_>>();
+            let total = candidates.iter().map(|(_, sequence)| sequence.len() - length + 1).sum::<usize>();
+            let mut position = rng.random_range(0..total);
+            let (contig, sequence) = candidates.iter()
+                .find(|(_, sequence)| {
+                    let starts = sequence.len() - length + 1;
+                    if position < starts { true } else { position -= starts; false }
+                })
+                .unwrap_or_else(|| unreachable!("positions are drawn within the candidate contigs"));
+            let (fragment, substitutions, insertions, deletions) = add_errors(&sequence[position..position + length], rng, parameters.substitution_rate, parameters.indel_rate);
+            Fragment {
+                name: format!("{}__fragment_{}", sample.get_name(), i + 1),
+                genome: sample.get_name().to_string(),
+                contig: contig.clone(),
+                start: position,
+                length,
+                substitutions,
+                insertions,
+                deletions,
+                sequence: fragment,
+            }
+        })
+        .collect()
+}
+
+// The truth table needs the taxonomy of every genome
+fn check_taxonomies(samples: &[Sample]) -> std::io::Result<()> {
+    match samples.iter().find(|s| s.get_taxonomy().is_none()) {
+        Some(sample) => Err(Error::new(ErrorKind::InvalidInput, format!("E: genome '{}' has no taxonomy, simulate needs the taxonomy of every genome for the truth table", sample.get_name()))),
+        None => Ok(()),
+    }
+}
+
+// Fragments of every genome, each genome draws from its own stream so the output doesn't depend on the number of threads
+pub fn simulate(samples: &[Sample], parameters: &SimulationParameters) -> std::io::Result<Vec<Vec<Fragment>>> {
+    check_parameters(parameters)?;
+    check_taxonomies(samples)?;
+    Ok(samples.par_iter()
+        .enumerate()
+        .map(|(i, sample)| simulate_genome(sample, parameters, &mut StdRng::seed_from_u64(parameters.random_state.wrapping_add(i as u64))))
+        .collect())
+}
+
+pub fn get_paths(prefix: &Path) -> [PathBuf; 3] {
+    ["fna", "truth.tsv", "fragments.tsv"]
+        .map(|suffix| PathBuf::from(format!("{}.{}", prefix.display(), suffix)))
+}
+
+/*
+Writes <prefix>.fna, <prefix>.truth.tsv and <prefix>.fragments.tsv.
+Fragments are named <genome>__fragment_<i>, the 0-based start is on the original contig.
+ */
+pub fn write_fragments(samples: &[Sample], fragments: &[Vec<Fragment>], prefix: &Path) -> std::io::Result<SimulationSummary> {
+    check_taxonomies(samples)?;
+    let [fasta_path, truth_path, table_path] = get_paths(prefix);
+    let mut fasta = BufWriter::new(File::create(fasta_path)?);
+    let mut truth = BufWriter::new(File::create(truth_path)?);
+    let mut table = BufWriter::new(File::create(table_path)?);
+    writeln!(table, "fragment\tgenome\tcontig\tstart\tlength\tfragment_length\tsubstitutions\tinsertions\tdeletions")?;
+
+    let mut summary = SimulationSummary { genomes: samples.len(), ..Default::default() };
+    for (sample, fragments) in samples.iter().zip(fragments) {
+        let taxonomy = sample.get_taxonomy().as_ref().expect("E: unreachable, taxonomies were checked");
+        for fragment in fragments {
+            writeln!(fasta, ">{} genome={} contig={} start={} length={}", fragment.name, fragment.genome, fragment.contig, fragment.start, fragment.length)?;
+            for line in fragment.sequence.chunks(FASTA_LINE_WIDTH) {
+                fasta.write_all(line)?;
+                writeln!(fasta)?;
+            }
+            writeln!(truth, "{}\t{}", fragment.name, taxonomy)?;
+            writeln!(table, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", fragment.name, fragment.genome, fragment.contig, fragment.start, fragment.length,
+                     fragment.sequence.len(), fragment.substitutions, fragment.insertions, fragment.deletions)?;
+
+            summary.fragments += 1;
+            summary.bases += fragment.sequence.len();
+            summary.substitutions += fragment.substitutions;
+            summary.insertions += fragment.insertions;
+            summary.deletions += fragment.deletions;
+        }
+    }
+    fasta.flush()?;
+    truth.flush()?;
+    table.flush()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::samples_file_reader::{merge_repeated_samples, Sample};
+    use crate::simulation::{add_errors, read_contigs, simulate, LengthDistribution, SimulationParameters};
+
+    fn parameters() -> SimulationParameters {
+        SimulationParameters {
+            fragments: 20,
+            length: 10,
+            distribution: LengthDistribution::Fixed,
+            sigma: 0.5,
+            substitution_rate: 0.0,
+            indel_rate: 0.0,
+            buffer_size: 512,
+            random_state: 1,
+        }
+    }
+
+    #[test]
+    fn fragments() {
+        let samples = vec![Sample::new("gc", "../tests/gc_tests.fna", Some("d__Bacteria;p__;c__;o__;f__;g__;s__"), 1)];
+        let contigs = read_contigs(samples[0].get_path(), 512);
+        assert_eq!(contigs.iter().map(|(name, sequence)| (name.as_str(), sequence.len())).collect::<Vec<_>>(), vec![("1", 32), ("2", 11)]);
+
+        let fragments = simulate(&samples, &parameters()).unwrap();
+        assert_eq!(fragments[0].len(), 20);
+        for fragment in &fragments[0] {
+            let (_, contig) = contigs.iter().find(|(name, _)| name == fragment.get_contig()).unwrap();
+            assert_eq!(fragment.get_sequence(), &contig[fragment.get_start()..fragment.get_start() + 10]);
+        }
+        let again = simulate(&samples, &parameters()).unwrap();
+        assert!(fragments[0].iter().zip(&again[0]).all(|(a, b)| a.get_sequence() == b.get_sequence() && a.get_start() == b.get_start()));
+
+        // Longer than any contig, cut to the longest one
+        let long = simulate(&samples, &SimulationParameters { length: 1000, distribution: LengthDistribution::LogNormal, ..parameters() }).unwrap();
+        assert!(long[0].iter().all(|f| f.get_length() <= 32 && f.get_length() >= 1));
+
+        assert!(simulate(&samples, &SimulationParameters { substitution_rate: 0.7, indel_rate: 0.7, ..parameters() }).is_err());
+        // No truth without a taxonomy
+        assert!(simulate(&[Sample::new("gc", "../tests/gc_tests.fna", None, 1)], &parameters()).is_err());
+
+        // A genome of several FASTA files has the contigs of all of them
+        let taxonomy = Some("d__Bacteria;p__;c__;o__;f__;g__;s__");
+        let genome = merge_repeated_samples(&[Sample::new("two", "../tests/gc_tests.fna", taxonomy, 1), Sample::new("two", "../tests/presentation_test_two_seq.fna", taxonomy, 2)]).unwrap();
+        let fragments = simulate(&genome, &SimulationParameters { fragments: 200, length: 3, ..parameters() }).unwrap();
+        assert!(fragments[0].iter().any(|f| f.get_contig() == "1"));
+        assert!(fragments[0].iter().any(|f| f.get_contig() == "test"));
+    }
+
+    #[test]
+    fn errors() {
+        let region = b"ACGTACGTACGTACGTACGT".repeat(50);
+        let mut rng = StdRng::seed_from_u64(1);
+        let (sequence, substitutions, insertions, deletions) = add_errors(&region, &mut rng, 0.1, 0.0);
+        assert_eq!((sequence.len(), insertions, deletions), (region.len(), 0, 0));
+        assert_eq!(sequence.iter().zip(&region).filter(|(a, b)| a != b).count(), substitutions);
+        assert!(substitutions > 0);
+
+        let (sequence, substitutions, insertions, deletions) = add_errors(&region, &mut rng, 0.0, 0.1);
+        assert_eq!(substitutions, 0);
+        assert_eq!(sequence.len(), region.len() + insertions - deletions);
+    }
+}
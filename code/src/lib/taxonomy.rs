@@ -61,7 +61,7 @@ impl TaxonomicRank {
         }
     }
 
-    fn down_iterator() -> impl Iterator<Item = TaxonomicRank> {
+    pub fn down_iterator() -> impl Iterator<Item = TaxonomicRank> {
         [TaxonomicRank::Domain, TaxonomicRank::Phylum, TaxonomicRank::Class, TaxonomicRank::Order, TaxonomicRank::Family, TaxonomicRank::Genus, TaxonomicRank::Species].iter().copied()
     }
 
@@ -77,7 +77,7 @@ impl TaxonomicRank {
         }
     }
 
-    fn get_father(&self) -> Option<TaxonomicRank> {
+    pub fn get_father(&self) -> Option<TaxonomicRank> {
         match self {
             TaxonomicRank::Domain => None,
             TaxonomicRank::Phylum => Some(TaxonomicRank::Domain),
@@ -147,6 +147,11 @@ impl Taxonomy {
 
     pub fn get_taxa(&self, rank: &TaxonomicRank) -> Option<&String> { self.rank2name.get(rank) }
 
+    // The shared stem of both taxonomies, None if they differ already at the domain.
+    pub fn lca(&self, other: &Taxonomy) -> Option<Taxonomy> {
+        self.lcu(other).map(|rank| self.limit2rank(&rank))
+    }
+
     pub fn equal_to_rank(&self, other: &Taxonomy, rank: &TaxonomicRank) -> bool {
         match self.lcu(other) {
             None => false,
@@ -169,6 +174,13 @@ impl Taxonomy {
         }
     }
     
+    // True if every rank named by the clade names the same taxon here, e.g. any taxonomy is within 'g__Escherichia' of that genus
+    pub fn is_within(&self, clade: &Taxonomy) -> bool {
+        TaxonomicRank::down_iterator()
+            .filter(|rank| clade.has_rank(rank))
+            .all(|rank| self.get_taxa(&rank) == clade.get_taxa(&rank))
+    }
+
     // Returns false if the rank is not-named / empty / non-existing.
     pub fn has_rank(&self, rank: &TaxonomicRank) -> bool {
         match self.get_taxa(rank) {
@@ -187,6 +199,61 @@ impl Taxonomy {
         self.rank2name.insert(*rank, new_name);
     }
 
+    // Ranks that are not named, from the domain down to the species
+    pub fn missing_ranks(&self) -> Vec<TaxonomicRank> {
+        TaxonomicRank::down_iterator().filter(|rank| !self.has_rank(rank)).collect()
+    }
+
+    // Missing ranks above a named rank, e.g. a genus without a family
+    pub fn gaps(&self) -> Vec<TaxonomicRank> {
+        match TaxonomicRank::down_iterator().filter(|rank| self.has_rank(rank)).min() {
+            Some(deepest) => self.missing_ranks().into_iter().filter(|rank| *rank > deepest).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // The deepest named taxon with its rank prefix, e.g. 'g__Escherichia'
+    pub fn deepest_taxon(&self) -> Option<String> {
+        TaxonomicRank::down_iterator()
+            .filter(|rank| self.has_rank(rank))
+            .min()
+            .and_then(|rank| self.get_taxa(&rank).map(|name| format!("{}__{}", TaxonomicRank::taxonomic_rank2prefix(rank), name)))
+    }
+
+    // Fills all missing ranks, top-down so each filled name is derived from its (filled) father. Returns the filled ranks.
+    pub fn fill_missing_ranks(&mut self) -> Vec<TaxonomicRank> {
+        let missing = self.missing_ranks();
+        for rank in &missing {
+            self.fill_rank(rank);
+        }
+        missing
+    }
+
+    /*
+    Parses a GTDB taxonomy string, e.g. d__Bacteria;p__Firmicutes_A;...;s__Peptacetobacter
+    Ranks must be given from the domain down, at most once each. A trailing ';' (as written by Display) is allowed.
+     */
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut rank2name = HashMap::new();
+        let mut previous: Option<TaxonomicRank> = None;
+
+        for entry in value.strip_suffix(';').unwrap_or(value).split(';') {
+            let (rank, name) = taxa_parser(entry)?;
+            if rank2name.contains_key(&rank) {
+                return Err(format!("{} is given more than once", rank));
+            }
+            if let Some(previous) = previous.filter(|previous| rank > *previous) {
+                return Err(format!("{} is given after {}", rank, previous));
+            }
+            previous = Some(rank);
+            rank2name.insert(rank, name.to_string());
+        }
+
+        Ok(Taxonomy {
+            rank2name
+        })
+    }
+
 }
 
 impl Hash for Taxonomy {
@@ -233,35 +300,26 @@ impl Eq for Taxonomy {
 
 }
 
-fn taxa_parser(value: &str) -> (TaxonomicRank, &str) {
-    if value.len() < 3 {
-        panic!("E: invalid taxonomic rank! got '{}', quitting", value);
-    }
-    let name = &value[3..];
-    match value.as_bytes()[0] {
-        b'd' => (TaxonomicRank::Domain, name),
-        b'p' => (TaxonomicRank::Phylum, name),
-        b'c' => (TaxonomicRank::Class, name),
-        b'o' => (TaxonomicRank::Order, name),
-        b'f' => (TaxonomicRank::Family, name),
-        b'g' => (TaxonomicRank::Genus, name),
-        b's' => (TaxonomicRank::Species, name),
-        _ => panic!("E: invalid taxonomy, got '{}', it has no rank", value),
+fn taxa_parser(value: &str) -> Result<(TaxonomicRank, &str), String> {
+    if value.len() < 3 || value.as_bytes()[1..3] != *b"__" {
+        return Err(format!("'{}' is not in the <rank>__<name> format", value));
     }
+    let rank = match value.as_bytes()[0] {
+        b'd' => TaxonomicRank::Domain,
+        b'p' => TaxonomicRank::Phylum,
+        b'c' => TaxonomicRank::Class,
+        b'o' => TaxonomicRank::Order,
+        b'f' => TaxonomicRank::Family,
+        b'g' => TaxonomicRank::Genus,
+        b's' => TaxonomicRank::Species,
+        _ => return Err(format!("'{}' has no rank", value)),
+    };
+    Ok((rank, &value[3..]))
 }
 
 impl From<&str> for Taxonomy {
     fn from(value: &str) -> Self {
-        let mut rank2name = HashMap::new();
-
-        for rank in value.split(';') {
-            let (rank, name) = taxa_parser(rank);
-            rank2name.insert(rank,name.to_string());
-        }
-
-        Taxonomy {
-            rank2name
-        }
+        Taxonomy::parse(value).unwrap_or_else(|e| panic!("E: invalid taxonomy '{}', {}, quitting", value, e))
     }
 }
 
@@ -314,4 +372,43 @@ mod tests {
         assert_eq!(taxonomy.get_taxa(&TaxonomicRank::Phylum), Some(&"Thermoplasmatota".to_string()));
         assert_eq!(taxonomy.get_taxa(&TaxonomicRank::Domain), Some(&"Archaea".to_string()));
     }
+
+    #[test]
+    fn test_taxonomy_lca() {
+        let a = Taxonomy::from("d__Bacteria;p__Actinobacteriota;c__Actinomycetia;o__Actinomycetales;f__Actinomycetaceae;g__Actinomyces;s__Actinomyces oris");
+        let b = Taxonomy::from("d__Bacteria;p__Actinobacteriota;c__Actinomycetia;o__Actinomycetales;f__Actinomycetaceae;g__Actinomyces;s__Actinomyces naeslundii");
+        let c = Taxonomy::from("d__Archaea;p__Thermoplasmatota");
+        assert_eq!(a.lca(&b).map(|t| t.to_string()), Some("d__Bacteria;p__Actinobacteriota;c__Actinomycetia;o__Actinomycetales;f__Actinomycetaceae;g__Actinomyces;".to_string()));
+        assert_eq!(a.lca(&a), Some(a.clone()));
+        assert!(a.lca(&c).is_none());
+    }
+
+    #[test]
+    fn test_taxonomy_is_within() {
+        let a = Taxonomy::from("d__Bacteria;p__Actinobacteriota;c__Actinomycetia;o__Actinomycetales;f__Actinomycetaceae;g__Actinomyces;s__Actinomyces oris");
+        assert!(a.is_within(&Taxonomy::from("g__Actinomyces")));
+        assert!(a.is_within(&Taxonomy::from("p__Actinobacteriota;c__Actinomycetia")));
+        assert!(!a.is_within(&Taxonomy::from("d__Archaea")));
+        assert!(!Taxonomy::from("d__Bacteria;p__Actinobacteriota").is_within(&Taxonomy::from("g__Actinomyces")));
+    }
+
+    #[test]
+    fn test_taxonomy_parse() {
+        assert!(Taxonomy::parse("d__Bacteria;c__Clostridia;p__Firmicutes_A").is_err());
+        assert!(Taxonomy::parse("d__Bacteria;p__Firmicutes_A;p__Firmicutes").is_err());
+        assert!(Taxonomy::parse("d__Bacteria;p").is_err());
+        assert!(Taxonomy::parse("d__Bacteria;x__Firmicutes_A").is_err());
+        assert!(Taxonomy::parse("").is_err());
+        // Display writes a trailing ';' when the species is missing
+        let genus = Taxonomy::parse("d__Bacteria;p__Firmicutes_A;c__Clostridia;o__Peptostreptococcales;f__Peptostreptococcaceae;g__Peptacetobacter;").unwrap();
+        assert_eq!(Taxonomy::parse(&genus.to_string()), Ok(genus.clone()));
+
+        assert_eq!(genus.missing_ranks(), vec![TaxonomicRank::Species]);
+        assert!(genus.gaps().is_empty());
+        let mut gapped = Taxonomy::parse("d__Bacteria;p__Firmicutes_A;c__;g__Peptacetobacter").unwrap();
+        assert_eq!(gapped.gaps(), vec![TaxonomicRank::Class, TaxonomicRank::Order, TaxonomicRank::Family]);
+        assert_eq!(gapped.fill_missing_ranks().len(), 4);
+        assert!(gapped.missing_ranks().is_empty());
+        assert_eq!(gapped.get_taxa(&TaxonomicRank::Order), Some(&"Firmicutes_A->UnknownClass->UnknownOrder".to_string()));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,233 @@
+// Checks the taxonomies of a sample list before training: rank order, missing ranks and conflicting parents.
+// Missing ranks may be filled (see Taxonomy::fill_rank), the issues are written as a TSV report.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::io::{BufWriter, Write};
+use crate::samples_file_reader::{Sample, SampleIterator, SampleSource};
+use crate::taxonomy::{TaxonomicRank, Taxonomy};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IssueSeverity {
+    // The taxonomy is inconsistent, the sample list should not be used as is
+    Error,
+    Warning,
+    // The issue was repaired by filling the missing ranks
+    Fixed,
+}
+
+impl Display for IssueSeverity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            IssueSeverity::Error => "error",
+            IssueSeverity::Warning => "warning",
+            IssueSeverity::Fixed => "fixed",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum IssueKind {
+    // Could not be parsed, e.g. ranks out of order or given twice
+    Malformed,
+    // Missing ranks above a named rank
+    Gap(Vec<TaxonomicRank>),
+    // Missing ranks at the end of the taxonomy, e.g. no species
+    Incomplete(Vec<TaxonomicRank>),
+    // The same taxon is placed under different parents, e.g. a genus in two families
+    ConflictingParents(TaxonomicRank),
+}
+
+impl Display for IssueKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IssueKind::Malformed => write!(f, "malformed"),
+            IssueKind::Gap(_) => write!(f, "gap"),
+            IssueKind::Incomplete(_) => write!(f, "incomplete"),
+            IssueKind::ConflictingParents(_) => write!(f, "conflicting_parents"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TaxonomyIssue {
+    // 1-based line of the sample list, None for issues spanning several samples
+    line: Option<usize>,
+    sample: Option<String>,
+    severity: IssueSeverity,
+    kind: IssueKind,
+    details: String,
+}
+
+impl TaxonomyIssue {
+    pub fn get_line(&self) -> Option<usize> { self.line }
+    pub fn get_sample(&self) -> Option<&str> { self.sample.as_deref() }
+    pub fn get_severity(&self) -> IssueSeverity { self.severity }
+    pub fn get_kind(&self) -> &IssueKind { &self.kind }
+    pub fn get_details(&self) -> &str { &self.details }
+}
+
+pub struct TaxonomyReport {
+    samples: usize,
+    issues: Vec<TaxonomyIssue>,
+}
+
+impl TaxonomyReport {
+    pub fn get_issues(&self) -> &[TaxonomyIssue] { &self.issues }
+
+    pub fn count(&self, severity: IssueSeverity) -> usize {
+        self.issues.iter().filter(|issue| issue.severity == severity).count()
+    }
+
+    pub fn is_consistent(&self) -> bool { self.count(IssueSeverity::Error) == 0 }
+
+    pub fn write<W: Write>(&self, destination: W) -> std::io::Result<()> {
+        let mut stream = BufWriter::new(destination);
+        writeln!(stream, "line\tsample\tseverity\tissue\tdetails")?;
+        for issue in &self.issues {
+            writeln!(stream, "{}\t{}\t{}\t{}\t{}",
+                     issue.line.map_or(String::from("NA"), |l| l.to_string()),
+                     issue.sample.as_deref().unwrap_or("NA"),
+                     issue.severity,
+                     issue.kind,
+                     issue.details)?;
+        }
+        stream.flush()
+    }
+}
+
+impl Display for TaxonomyReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} samples, {} taxonomy errors, {} warnings, {} fixed",
+               self.samples, self.count(IssueSeverity::Error), self.count(IssueSeverity::Warning), self.count(IssueSeverity::Fixed))
+    }
+}
+
+fn ranks2string(ranks: &[TaxonomicRank]) -> String {
+    ranks.iter().map(|rank| rank.to_string()).collect::<Vec<String>>().join(",")
+}
+
+// Missing ranks of one sample, filled if requested
+fn check_missing_ranks(sample: &mut Sample, line: usize, fill_missing: bool, issues: &mut Vec<TaxonomyIssue>) {
+    let taxonomy = match sample.get_taxonomy() {
+        Some(taxonomy) => taxonomy,
+        None => return,
+    };
+    let gaps = taxonomy.gaps();
+    let incomplete = taxonomy.missing_ranks().into_iter().filter(|rank| !gaps.contains(rank)).collect::<Vec<TaxonomicRank>>();
+    let original = taxonomy.to_string();
+
+    for (kind, ranks, severity) in [(IssueKind::Gap(gaps.clone()), gaps, IssueSeverity::Error),
+                                    (IssueKind::Incomplete(incomplete.clone()), incomplete, IssueSeverity::Warning)] {
+        if !ranks.is_empty() {
+            issues.push(TaxonomyIssue {
+                line: Some(line),
+                sample: Some(sample.get_name().to_string()),
+                severity: if fill_missing { IssueSeverity::Fixed } else { severity },
+                kind,
+                details: format!("missing {} in '{}'", ranks2string(&ranks), original),
+            });
+        }
+    }
+
+    if fill_missing {
+        if let Some(taxonomy) = sample.get_taxonomy_mut() {
+            taxonomy.fill_missing_ranks();
+        }
+    }
+}
+
+// Taxa placed under more than one parent, in the order of the ranks and then names
+fn check_parents(samples: &[Sample], issues: &mut Vec<TaxonomyIssue>) {
+    let mut taxon2parents: BTreeMap<(TaxonomicRank, String), BTreeMap<String, BTreeSet<String>>> = BTreeMap::new();
+    for sample in samples {
+        let taxonomy: &Taxonomy = match sample.get_taxonomy() {
+            Some(taxonomy) => taxonomy,
+            None => continue,
+        };
+        for rank in TaxonomicRank::down_iterator() {
+            if let (Some(father), Some(name)) = (rank.get_father(), taxonomy.get_taxa(&rank).filter(|n| !n.is_empty())) {
+                if let Some(parent) = taxonomy.get_taxa(&father).filter(|n| !n.is_empty()) {
+                    taxon2parents.entry((rank, name.clone()))
+                        .or_default()
+                        .entry(parent.clone())
+                        .or_default()
+                        .insert(sample.get_name().to_string());
+                }
+            }
+        }
+    }
+
+    for ((rank, name), parents) in taxon2parents.into_iter().filter(|(_, parents)| parents.len() > 1) {
+        let father = rank.get_father().map_or(String::new(), |father| father.to_string());
+        let details = parents.iter()
+            .map(|(parent, samples)| format!("{} {} ({} samples, e.g. {})", father, parent, samples.len(), samples.iter().next().map_or("", |s| s.as_str())))
+            .collect::<Vec<String>>()
+            .join(", ");
+        issues.push(TaxonomyIssue {
+            line: None,
+            sample: None,
+            severity: IssueSeverity::Error,
+            kind: IssueKind::ConflictingParents(rank),
+            details: format!("{} {} is placed under {}", rank, name, details),
+        });
+    }
+}
+
+/*
+Loads all the samples of the source and checks their taxonomies.
+Samples that could not be read are reported as malformed and left out, the rest are returned (with missing ranks filled if requested).
+ */
+pub fn validate_taxonomies(source: &SampleSource, fill_missing: bool) -> (Vec<Sample>, TaxonomyReport) {
+    let mut samples = Vec::new();
+    let mut issues = Vec::new();
+    let mut lines = 0;
+
+    for (i, sample) in SampleIterator::from(source).enumerate() {
+        lines += 1;
+        match sample {
+            Ok(mut sample) => {
+                check_missing_ranks(&mut sample, i + 1, fill_missing, &mut issues);
+                samples.push(sample);
+            },
+            Err(e) => issues.push(TaxonomyIssue {
+                line: Some(i + 1),
+                sample: None,
+                severity: IssueSeverity::Error,
+                kind: IssueKind::Malformed,
+                details: e.to_string(),
+            }),
+        }
+    }
+    check_parents(&samples, &mut issues);
+
+    (samples, TaxonomyReport { samples: lines, issues })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::samples_file_reader::SampleSource;
+    use crate::taxonomy::TaxonomicRank;
+    use crate::taxonomy_validation::{validate_taxonomies, IssueKind, IssueSeverity};
+
+    #[test]
+    fn validation() {
+        let source = SampleSource::new(Path::new("../tests/taxonomy_validation_training.txt"), true);
+        let (samples, report) = validate_taxonomies(&source, false);
+        assert_eq!(samples.len(), 4);
+        assert!(!report.is_consistent());
+        let kinds = report.get_issues().iter().map(|issue| (issue.get_line(), issue.get_kind().to_string())).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![(Some(2), String::from("incomplete")),
+                               (Some(3), String::from("gap")),
+                               (Some(5), String::from("malformed")),
+                               (None, String::from("conflicting_parents"))]);
+        assert!(matches!(report.get_issues()[3].get_kind(), IssueKind::ConflictingParents(TaxonomicRank::Genus)));
+
+        // Filling repairs gaps, but not conflicts or malformed lines
+        let (samples, report) = validate_taxonomies(&source, true);
+        assert!(samples.iter().all(|sample| sample.get_taxonomy().as_ref().is_some_and(|t| t.missing_ranks().is_empty())));
+        assert_eq!(report.count(IssueSeverity::Fixed), 2);
+        assert_eq!(report.count(IssueSeverity::Error), 2);
+    }
+}
@@ -7,27 +7,46 @@ use crate::lz78::LenBases;
 use crate::{fasta_records_iterator, samples_file_reader};
 use crate::cached_fasta_nucleutide_iterator::CachedFastaNucltudiesIterator;
 use crate::contig_naming::{are_genes_of_same_contig, get_contig_name, sequence_id2str};
-use crate::fasta_nucleutide_iterator::FastaNucltudiesIterator;
 use crate::fasta_record::FastaRecord;
 use crate::output_streams::OutputStreams;
-use crate::samples_file_reader::{Sample, SampleError, SampleIterator};
+use crate::samples_file_reader::{is_stdin, Sample, SampleError, SampleIterator, STDIN_PATH};
 use crate::logger::log_event;
+use crate::ncbi_taxdump::TaxDump;
 
 pub fn create_lz_classifier(mut log_stream: Option<&mut BufWriter<Box<dyn Write>>>,
                             max_depth: usize,
                             name2file: &Path,
                             buffer_size: usize,
-                            kmer_size: &Option<usize>) -> Classifier {
+                            kmer_size: &Option<usize>,
+                            taxdump: Option<Arc<TaxDump>>) -> Classifier {
     log_event(&mut log_stream, "Starting classifier creation");
     let len_bases: LenBases = LenBases::new(max_depth);
     let mut classifier: Classifier = Classifier::new(len_bases);
 
     log_event(&mut log_stream, "Training");
-    if let Err(e) = classifier.batch_add_model(name2file, max_depth, buffer_size, kmer_size) {
+    if let Err(e) = classifier.batch_add_model(name2file, max_depth, buffer_size, kmer_size, taxdump) {
         eprintln!("{}", e);
     }
 
+    finish_lz_classifier(log_stream, classifier)
+}
+
+// Same as create_lz_classifier, for samples that were already loaded (e.g. after taxonomy validation)
+pub fn create_lz_classifier_from_samples(mut log_stream: Option<&mut BufWriter<Box<dyn Write>>>,
+                                         max_depth: usize,
+                                         samples: &[Sample],
+                                         buffer_size: usize,
+                                         kmer_size: &Option<usize>) -> Classifier {
+    log_event(&mut log_stream, "Starting classifier creation");
+    let mut classifier: Classifier = Classifier::new(LenBases::new(max_depth));
+
+    log_event(&mut log_stream, "Training");
+    classifier.batch_add_samples(samples, max_depth, buffer_size, kmer_size);
+
+    finish_lz_classifier(log_stream, classifier)
+}
 
+fn finish_lz_classifier(mut log_stream: Option<&mut BufWriter<Box<dyn Write>>>, classifier: Classifier) -> Classifier {
     if let Some(&mut ref mut log_stream) = log_stream {
         classifier.print_stats(log_stream).expect("E: Failed to write into log");
     }
@@ -111,10 +130,29 @@ impl ReadyPredictor {
     }
 
     fn predict(&self, sample: &Sample) -> (Vec<(String, Option<f64>)>, usize) {
-        owned_prediction_results(self.classifier.predict(CachedFastaNucltudiesIterator::from(FastaNucltudiesIterator::new(sample.get_path(), self.buffer_size)), self.gc_limit, &self.kmer_size, self.reflect))
+        let sequence = CachedFastaNucltudiesIterator::from_paths(sample.get_paths(), self.buffer_size);
+        owned_prediction_results(self.classifier.predict(sequence, self.gc_limit, &self.kmer_size, self.reflect))
     }
 }
 
+// Each record of the FASTA is a query, as in meta prediction
+fn read_fasta_records(fasta: &Path, buffer_size: usize) -> Vec<(String, FastaRecord)> {
+    let mut records: Vec<(String, FastaRecord)> = Vec::new();
+    for record_part in fasta_records_iterator::FastaRecordIterator::new(fasta, buffer_size) {
+        match record_part {
+            fasta_records_iterator::FastaPartType::ID(id) => {
+                let name = sequence_id2str(id.as_slice()).to_string();
+                records.push((name.clone(), FastaRecord::new(&name, "", buffer_size)));
+            },
+            fasta_records_iterator::FastaPartType::Nuc(nuc) => match records.last_mut() {
+                Some((_, record)) => record.push(nuc),
+                None => panic!("E: The fasta file '{}' is malformed! a non-description line shows up before description line", fasta.display()),
+            },
+        }
+    }
+    records
+}
+
 pub fn predict_using_lz_classifier(mut log_stream: Option<&mut BufWriter<Box<dyn Write>>>,
                                buffer_size: usize,
                                kmer_size: &Option<usize>,
@@ -132,18 +170,28 @@ pub fn predict_using_lz_classifier(mut log_stream: Option<&mut BufWriter<Box<dyn
         let prediction_function: PredictorFunction = Arc::new(move |s| ready_predictor.predict(s));
         // let get_sample_results = |s| SampleResults::from((s, &prediction_function));
         let get_sample_prediction = Arc::new(|s| SamplePrediction::from((s, &prediction_function)));
-        let samples = SampleIterator::from(samples_file_reader::SampleSource::new(prediction_name2file, false))
-            .map(|s| match s {
-                Err(se) => Err(se),
-                Ok(sample) => Ok(Arc::new(Mutex::new(sample))),
-            })
-            .collect::<Result<Vec<Arc<Mutex<Sample>>>, SampleError>>()?;
-
-
-        // samples.into_par_iter()
-        samples.into_par_iter()
-            .map(|s| get_sample_prediction(s))
-            .collect::<Vec<SamplePrediction>>()
+        if is_stdin(prediction_name2file) {
+            read_fasta_records(prediction_name2file, buffer_size).into_par_iter()
+                .enumerate()
+                .map(|(line_number, (name, record))| SamplePrediction {
+                    sample: Sample::new(&name, STDIN_PATH, None, line_number),
+                    prediction: owned_prediction_results(classifier.predict(record, gc_limit, kmer_size, reflect)),
+                })
+                .collect::<Vec<SamplePrediction>>()
+        } else {
+            let samples = SampleIterator::from(samples_file_reader::SampleSource::new(prediction_name2file, false))
+                .map(|s| match s {
+                    Err(se) => Err(se),
+                    Ok(sample) => Ok(Arc::new(Mutex::new(sample))),
+                })
+                .collect::<Result<Vec<Arc<Mutex<Sample>>>, SampleError>>()?;
+
+
+            // samples.into_par_iter()
+            samples.into_par_iter()
+                .map(|s| get_sample_prediction(s))
+                .collect::<Vec<SamplePrediction>>()
+        }
 
         // SampleIterator::from(samples_file_reader::SampleSource::new(prediction_name2file, false))
         //     .into_iter()
@@ -160,6 +208,7 @@ pub fn predict_using_lz_classifier(mut log_stream: Option<&mut BufWriter<Box<dyn
     results.par_sort_by_key(|i| i.sample.get_line_number());
 
     for sp in results {
+        output_streams.set_query_columns(sp.sample.get_weight(), sp.sample.get_group());
         if let Err(e) = write_classifier_prediction(&classifier, sp.sample.get_name(), output_streams, &refed_prediction_results(&sp.prediction)) {
             panic!("{}", e);
         }
@@ -174,7 +223,7 @@ pub fn predict_using_lz_classifier(mut log_stream: Option<&mut BufWriter<Box<dyn
     //     }
     // }
 
-    output_streams.flush().unwrap_or_else(|_| panic!("E: Failed to flush output stream into '{}'", output_streams));
+    output_streams.finish().unwrap_or_else(|_| panic!("E: Failed to flush output stream into '{}'", output_streams));
 
     log_event(&mut log_stream, "GeneZip prediction is ready");
 
@@ -215,6 +264,7 @@ pub fn meta_predict_using_lz_classifier(mut log_stream: Option<&mut BufWriter<Bo
     log_event(&mut log_stream, "Predicting");
 
     classifier.print_header(output_streams).unwrap_or_else(|_| panic!("E: Failed to write header into output file '{}'", output_streams));
+    output_streams.set_sample(&fasta.file_stem().map_or(fasta.display().to_string(), |stem| stem.to_string_lossy().to_string()));
 
     let mut temp_fasta_stream = None;
     let mut prev_record_id = None;
@@ -272,7 +322,7 @@ pub fn meta_predict_using_lz_classifier(mut log_stream: Option<&mut BufWriter<Bo
         meta_predict_using_lz_classifier_helper(&mut temp_fasta_stream, genes, min_genes, found_genes, classifier, gc_limit, &prev_id, output_streams);
     }
 
-    output_streams.flush().unwrap_or_else(|_| panic!("E: Failed to flush output stream into '{}'", output_streams));
+    output_streams.finish().unwrap_or_else(|_| panic!("E: Failed to flush output stream into '{}'", output_streams));
 
     log_event(&mut log_stream, "GeneZip prediction is ready");
 
@@ -282,8 +332,10 @@ pub fn meta_predict_using_lz_classifier(mut log_stream: Option<&mut BufWriter<Bo
 #[cfg(test)]
 mod tests {
     use std::io::BufRead;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
     use std::sync::Arc;
+    use crate::cached_fasta_nucleutide_iterator::CachedFastaNucltudiesIterator;
+    use crate::ncbi_taxdump::TaxDump;
     use crate::output_streams::{OutputFileType, OutputStreams};
     use crate::use_classifier::{create_lz_classifier, meta_predict_using_lz_classifier, predict_using_lz_classifier};
 
@@ -296,7 +348,7 @@ mod tests {
         };
 
         // max_depth is 12, for consistency with the small sample.
-        let classifier = create_lz_classifier(None, 12, &PathBuf::from("../tests/small_example_training.txt"), 512, &None);
+        let classifier = create_lz_classifier(None, 12, &PathBuf::from("../tests/small_example_training.txt"), 512, &None, None);
         meta_predict_using_lz_classifier(None,
                                          512,
                                          &classifier,
@@ -331,7 +383,7 @@ mod tests {
         };
 
         // max_depth is 12, for consistency with the small sample.
-        let classifier = create_lz_classifier(None, 12, &PathBuf::from("../tests/small_example_training.txt"), 512, &None);
+        let classifier = create_lz_classifier(None, 12, &PathBuf::from("../tests/small_example_training.txt"), 512, &None, None);
         meta_predict_using_lz_classifier(None,
                                          512,
                                          &classifier,
@@ -380,7 +432,7 @@ mod tests {
         };
 
         // max_depth is 12, for consistency with the small sample.
-        let classifier = create_lz_classifier(None, 12, &PathBuf::from("../tests/small_example_training.txt"), 512, &None);
+        let classifier = create_lz_classifier(None, 12, &PathBuf::from("../tests/small_example_training.txt"), 512, &None, None);
         meta_predict_using_lz_classifier(None,
                                          512,
                                          &classifier,
@@ -430,7 +482,7 @@ mod tests {
         };
 
         // max_depth is 12, for consistency with the small sample.
-        let classifier = Arc::new(create_lz_classifier(None, 12, &PathBuf::from("../tests/small_example_training.txt"), 512, &None));
+        let classifier = Arc::new(create_lz_classifier(None, 12, &PathBuf::from("../tests/small_example_training.txt"), 512, &None, None));
         predict_using_lz_classifier(None,
                                     512,
                                     &None,
@@ -467,4 +519,83 @@ mod tests {
         std::fs::remove_file(&basic_output_path).unwrap();
         std::fs::remove_file(&lz_matrix_path).unwrap();
     }
+
+    #[test]
+    fn test_taxid_training() {
+        let basic_output_path = PathBuf::from("../tests/taxid_predication_basic.tsv");
+        let mut output_streams = OutputStreams::new(&[(OutputFileType::BaseGz, basic_output_path.as_path())].into_iter().collect()).unwrap();
+
+        // Models may be given a taxid or a GTDB taxonomy string, only taxids have a lineage
+        let taxdump = Arc::new(TaxDump::load(Path::new("../tests/import/taxdump")).unwrap());
+        let classifier = Arc::new(create_lz_classifier(None, 12, &PathBuf::from("../tests/import/taxid_training.txt"), 512, &None, Some(taxdump)));
+        assert_eq!(classifier.get_model2lineage().len(), 1);
+        predict_using_lz_classifier(None,
+                                    512,
+                                    &None,
+                                    None,
+                                    classifier,
+                                    &PathBuf::from("../tests/import/taxid_prediction.txt"),
+                                    &mut output_streams,
+                                    false).unwrap();
+        drop(output_streams);
+
+        let lines = ["Genome_name\tLength\tBest_hit\tBest_hit_taxid\tBest_hit_lineage\tBest_hit_taxid_lineage",
+            "query\t10\tEscherichia_coli_K-12\t83333\tcellular organisms;Bacteria;Pseudomonadota;Gammaproteobacteria;Enterobacterales;Enterobacteriaceae;Escherichia;Escherichia coli;Escherichia coli K-12\t131567;2;1224;1236;91347;543;561;562;83333"];
+        let output = std::fs::read(&basic_output_path).unwrap();
+        assert_eq!(output.lines().count(), lines.len());
+        for (cl, kl) in output.lines().zip(lines) {
+            assert_eq!(cl.unwrap(), kl);
+        }
+
+        std::fs::remove_file(&basic_output_path).unwrap();
+    }
+
+    #[test]
+    fn test_taxid_kmer_filter() {
+        // The query is closest to the K-12 profile, E. coli (taxid 562) shares its NCBI genus and B. subtilis is filtered out
+        let taxdump = Arc::new(TaxDump::load(Path::new("../tests/import/taxdump")).unwrap());
+        let classifier = create_lz_classifier(None, 12, &PathBuf::from("../tests/import/taxid_kmer_training.txt"), 512, &Some(4), Some(taxdump));
+        let query = CachedFastaNucltudiesIterator::from_paths(&[PathBuf::from("../tests/import/genomes/GCF_000005845.2_ASM584v2_genomic.fna")], 512);
+        let mut models = classifier.predict(query, None, &Some(4), false).0.into_iter()
+            .map(|(model, _)| model.as_str())
+            .collect::<Vec<&str>>();
+        models.sort();
+        assert_eq!(models, vec!["Escherichia_coli", "Escherichia_coli_K-12"]);
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let basic_output_path = PathBuf::from("../tests/json_lines_predication_basic.tsv");
+        let json_lines_path = PathBuf::from("../tests/json_lines_predication.jsonl");
+        let mut output_streams = OutputStreams::new(&[(OutputFileType::BaseGz, basic_output_path.as_path()),
+                                                      (OutputFileType::JsonLines, json_lines_path.as_path())].into_iter().collect()).unwrap()
+            .with_json_hits(1);
+
+        let taxdump = Arc::new(TaxDump::load(Path::new("../tests/import/taxdump")).unwrap());
+        let classifier = Arc::new(create_lz_classifier(None, 12, &PathBuf::from("../tests/import/taxid_training.txt"), 512, &None, Some(taxdump)));
+        predict_using_lz_classifier(None,
+                                    512,
+                                    &None,
+                                    None,
+                                    classifier,
+                                    &PathBuf::from("../tests/import/taxid_prediction.txt"),
+                                    &mut output_streams,
+                                    false).unwrap();
+        drop(output_streams);
+
+        let output = std::fs::read(&json_lines_path).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        let record: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(record["name"], "query");
+        assert_eq!(record["length"], 10);
+        assert_eq!(record["candidates"], record["models"]);
+        assert_eq!(record["scores"].as_array().unwrap().len(), 1);
+        assert_eq!(record["scores"][0]["model"], record["best_hit"]);
+        assert_eq!(record["best_hit"], "Escherichia_coli_K-12");
+        assert_eq!(record["taxid"], 83333);
+        assert_eq!(record["warnings"], serde_json::json!([]));
+
+        std::fs::remove_file(&basic_output_path).unwrap();
+        std::fs::remove_file(&json_lines_path).unwrap();
+    }
 }
\ No newline at end of file